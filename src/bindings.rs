@@ -0,0 +1,373 @@
+//! Key bindings: keys bound to commands in named tables. `prefix` holds the
+//! keys that follow the prefix key and `root` those that work without it.
+//! `copy-mode` and `copy-mode-vi` hold the keys of copy mode, as chosen by
+//! the `mode-keys` option, and `switch-client -T` enters any other table for
+//! one key. The defaults are `bind-key` commands like any the configuration runs.
+
+use std::collections::BTreeMap;
+
+use crossterm::event::{KeyCode, KeyModifiers};
+
+use crate::config;
+use crate::keys::{binding_key, key_name, parse_key_name};
+
+/// The tmux bindings pmux starts with.
+pub const DEFAULTS: &str = r#"
+bind-key -r -T prefix Left select-pane -L
+bind-key -r -T prefix Right select-pane -R
+bind-key -r -T prefix Up select-pane -U
+bind-key -r -T prefix Down select-pane -D
+bind-key -r -T prefix C-Left resize-pane -L
+bind-key -r -T prefix C-Right resize-pane -R
+bind-key -r -T prefix C-Up resize-pane -U
+bind-key -r -T prefix C-Down resize-pane -D
+bind-key -r -T prefix M-Left resize-pane -L 5
+bind-key -r -T prefix M-Right resize-pane -R 5
+bind-key -r -T prefix M-Up resize-pane -U 5
+bind-key -r -T prefix M-Down resize-pane -D 5
+bind-key -T prefix 1 select-window -t 1
+bind-key -T prefix 2 select-window -t 2
+bind-key -T prefix 3 select-window -t 3
+bind-key -T prefix 4 select-window -t 4
+bind-key -T prefix 5 select-window -t 5
+bind-key -T prefix 6 select-window -t 6
+bind-key -T prefix 7 select-window -t 7
+bind-key -T prefix 8 select-window -t 8
+bind-key -T prefix 9 select-window -t 9
+bind-key -T prefix c new-window
+bind-key -T prefix n next-window
+bind-key -T prefix p previous-window
+bind-key -T prefix % split-window -h
+bind-key -T prefix '"' split-window -v
+bind-key -T prefix x kill-pane
+bind-key -T prefix d detach-client
+bind-key -T prefix z resize-pane -Z
+bind-key -T prefix s set-window-option synchronize-panes
+bind-key -T prefix w choose-tree
+bind-key -T prefix , command-prompt -p "rename window" "rename-window '%%'"
+bind-key -T prefix t command-prompt -p "pane title" "select-pane -T '%%'"
+bind-key -T prefix Space next-layout
+bind-key -T prefix [ copy-mode
+bind-key -T prefix ] paste-buffer
+bind-key -T prefix : command-prompt
+bind-key -T prefix q display-panes
+bind-key -T prefix C-b send-prefix
+bind-key -T copy-mode-vi Escape send-keys -X cancel
+bind-key -T copy-mode-vi q send-keys -X cancel
+bind-key -T copy-mode-vi ] send-keys -X cancel
+bind-key -T copy-mode-vi PPage send-keys -X page-up
+bind-key -T copy-mode-vi NPage send-keys -X page-down
+bind-key -T copy-mode-vi C-b send-keys -X page-up
+bind-key -T copy-mode-vi C-f send-keys -X page-down
+bind-key -T copy-mode-vi C-u send-keys -X halfpage-up
+bind-key -T copy-mode-vi C-d send-keys -X halfpage-down
+bind-key -T copy-mode-vi C-y send-keys -X scroll-up
+bind-key -T copy-mode-vi C-e send-keys -X scroll-down
+bind-key -T copy-mode-vi g send-keys -X history-top
+bind-key -T copy-mode-vi G send-keys -X history-bottom
+bind-key -T copy-mode-vi Left send-keys -X cursor-left
+bind-key -T copy-mode-vi Right send-keys -X cursor-right
+bind-key -T copy-mode-vi Up send-keys -X cursor-up
+bind-key -T copy-mode-vi Down send-keys -X cursor-down
+bind-key -T copy-mode-vi h send-keys -X cursor-left
+bind-key -T copy-mode-vi l send-keys -X cursor-right
+bind-key -T copy-mode-vi k send-keys -X cursor-up
+bind-key -T copy-mode-vi j send-keys -X cursor-down
+bind-key -T copy-mode-vi v send-keys -X begin-selection
+bind-key -T copy-mode-vi Space send-keys -X begin-selection
+bind-key -T copy-mode-vi y send-keys -X copy-selection-and-cancel
+bind-key -T copy-mode-vi Enter send-keys -X copy-selection-and-cancel
+bind-key -T copy-mode Escape send-keys -X cancel
+bind-key -T copy-mode q send-keys -X cancel
+bind-key -T copy-mode C-g send-keys -X cancel
+bind-key -T copy-mode PPage send-keys -X page-up
+bind-key -T copy-mode NPage send-keys -X page-down
+bind-key -T copy-mode M-v send-keys -X page-up
+bind-key -T copy-mode C-v send-keys -X page-down
+bind-key -T copy-mode M-< send-keys -X history-top
+bind-key -T copy-mode M-> send-keys -X history-bottom
+bind-key -T copy-mode Left send-keys -X cursor-left
+bind-key -T copy-mode Right send-keys -X cursor-right
+bind-key -T copy-mode Up send-keys -X cursor-up
+bind-key -T copy-mode Down send-keys -X cursor-down
+bind-key -T copy-mode C-b send-keys -X cursor-left
+bind-key -T copy-mode C-f send-keys -X cursor-right
+bind-key -T copy-mode C-p send-keys -X cursor-up
+bind-key -T copy-mode C-n send-keys -X cursor-down
+bind-key -T copy-mode C-Space send-keys -X begin-selection
+bind-key -T copy-mode M-w send-keys -X copy-selection-and-cancel
+bind-key -T copy-mode C-w send-keys -X copy-selection-and-cancel
+"#;
+
+pub struct Binding {
+    pub key: (KeyCode, KeyModifiers),
+    /// `-r`: the key can be pressed again without the prefix.
+    pub repeat: bool,
+    /// Commands to run in order, each a list of words.
+    pub commands: Vec<Vec<String>>,
+}
+
+#[derive(Default)]
+pub struct KeyTables {
+    tables: BTreeMap<String, Vec<Binding>>,
+}
+
+impl KeyTables {
+    /// The tables holding the `DEFAULTS`.
+    pub fn with_defaults() -> Self {
+        let mut tables = KeyTables::default();
+        for line in DEFAULTS.lines() {
+            for args in config::tokenize(line).unwrap_or_default() {
+                if let Ok((table, binding)) = parse_bind(&args[1..]) { tables.bind(&table, binding); }
+            }
+        }
+        tables
+    }
+
+    /// Bind a key in `table`, replacing any binding it had.
+    pub fn bind(&mut self, table: &str, binding: Binding) {
+        let t = self.tables.entry(table.to_string()).or_default();
+        t.retain(|b| b.key != binding.key);
+        t.push(binding);
+    }
+
+    /// Remove the binding of `key` in `table`, or every binding in it when `key` is `None`.
+    pub fn unbind(&mut self, table: &str, key: Option<(KeyCode, KeyModifiers)>) {
+        let Some(t) = self.tables.get_mut(table) else { return };
+        match key {
+            Some(key) => t.retain(|b| b.key != key),
+            None => t.clear(),
+        }
+    }
+
+    pub fn has_table(&self, table: &str) -> bool {
+        self.tables.contains_key(table)
+    }
+
+    pub fn get(&self, table: &str, key: (KeyCode, KeyModifiers)) -> Option<&Binding> {
+        self.tables.get(table)?.iter().find(|b| b.key == key)
+    }
+
+    /// The bindings of `table`, or of every table, as the `bind-key` commands that make them.
+    pub fn list(&self, table: Option<&str>) -> Vec<String> {
+        let mut out = Vec::new();
+        for (name, bindings) in self.tables.iter().filter(|(name, _)| table.is_none_or(|t| t == name.as_str())) {
+            let mut lines: Vec<(String, String)> = bindings.iter().map(|b| {
+                let key = config::quote(&key_name(b.key));
+                let commands: Vec<String> = b.commands.iter().map(|c| c.iter().map(|w| config::quote(w)).collect::<Vec<_>>().join(" ")).collect();
+                let line = format!("bind-key {}-T {} {} {}", if b.repeat { "-r " } else { "" }, name, key, commands.join(" \\; "));
+                (key, line)
+            }).collect();
+            lines.sort();
+            out.extend(lines.into_iter().map(|(_, line)| line));
+        }
+        out
+    }
+}
+
+/// A key name, as looked up by `binding_key`.
+fn parse_key(name: &str) -> Result<(KeyCode, KeyModifiers), String> {
+    parse_key_name(name).map(|k| binding_key(&k)).ok_or_else(|| format!("unknown key: {}", name))
+}
+
+/// `bind-key [-nr] [-T table] key command [arguments]`: the table and the
+/// binding. Commands are separated by `;` words, and a command given as one
+/// word is split into words itself.
+pub fn parse_bind(args: &[String]) -> Result<(String, Binding), String> {
+    let usage = || "usage: bind-key [-nr] [-T table] key command [arguments]".to_string();
+    let (mut table, mut repeat, mut i) = ("prefix".to_string(), false, 0);
+    while let Some(a) = args.get(i).filter(|a| a.len() > 1 && a.starts_with('-')) {
+        match a.as_str() {
+            "-T" => { table = args.get(i + 1).cloned().ok_or_else(usage)?; i += 2; continue; }
+            "-n" => table = "root".to_string(),
+            "-r" => repeat = true,
+            "-nr" | "-rn" => { table = "root".to_string(); repeat = true; }
+            _ => return Err(usage()),
+        }
+        i += 1;
+    }
+    let key = parse_key(args.get(i).ok_or_else(usage)?)?;
+    let words = &args[i + 1..];
+    let commands: Vec<Vec<String>> = match words {
+        [one] => config::tokenize(one)?,
+        _ => words.split(|w| w == ";").filter(|c| !c.is_empty()).map(<[String]>::to_vec).collect(),
+    };
+    if commands.is_empty() { return Err(usage()); }
+    Ok((table, Binding { key, repeat, commands }))
+}
+
+/// `unbind-key [-an] [-T table] [key]`: the table and the key, or `None` with `-a` for all of them.
+pub fn parse_unbind(args: &[String]) -> Result<(String, Option<(KeyCode, KeyModifiers)>), String> {
+    let usage = || "usage: unbind-key [-an] [-T table] key".to_string();
+    let (mut table, mut all, mut i) = ("prefix".to_string(), false, 0);
+    while let Some(a) = args.get(i).filter(|a| a.len() > 1 && a.starts_with('-')) {
+        match a.as_str() {
+            "-T" => { table = args.get(i + 1).cloned().ok_or_else(usage)?; i += 2; continue; }
+            "-n" => table = "root".to_string(),
+            "-a" => all = true,
+            "-an" | "-na" => { table = "root".to_string(); all = true; }
+            _ => return Err(usage()),
+        }
+        i += 1;
+    }
+    match (all, args.get(i)) {
+        (true, _) => Ok((table, None)),
+        (false, Some(key)) => Ok((table, Some(parse_key(key)?))),
+        (false, None) => Err(usage()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        config::tokenize(line).unwrap().concat()
+    }
+
+    fn commands(b: &Binding) -> Vec<String> {
+        b.commands.iter().map(|c| c.join(" ")).collect()
+    }
+
+    fn key(name: &str) -> (KeyCode, KeyModifiers) {
+        parse_key(name).unwrap()
+    }
+
+    #[test]
+    fn bind_flags_choose_the_table() {
+        let cases = [
+            ("x kill-pane", "prefix", false),
+            ("-n F1 new-window", "root", false),
+            ("-r Left select-pane -L", "prefix", true),
+            ("-T resize h resize-pane -L", "resize", false),
+            ("-r -T resize h resize-pane -L", "resize", true),
+            ("-T resize -r h resize-pane -L", "resize", true),
+            ("-nr h resize-pane -L", "root", true),
+            ("-rn h resize-pane -L", "root", true),
+            ("-n -T other h resize-pane -L", "other", false),
+            ("-T other -n h resize-pane -L", "root", false),
+        ];
+        for (line, table, repeat) in cases {
+            let (t, b) = parse_bind(&args(line)).unwrap();
+            assert_eq!((t.as_str(), b.repeat), (table, repeat), "{}", line);
+        }
+    }
+
+    #[test]
+    fn bind_commands() {
+        let cases: &[(&str, &[&str])] = &[
+            ("x kill-pane", &["kill-pane"]),
+            ("x split-window -h", &["split-window -h"]),
+            ("x split-window -h \\; select-pane -L", &["split-window -h", "select-pane -L"]),
+            ("x 'split-window -h ; select-pane -L'", &["split-window -h", "select-pane -L"]),
+            ("x 'split-window -h'", &["split-window -h"]),
+            ("x \\; new-window \\; \\;", &["new-window"]),
+            ("x command-prompt -p 'new name' \"rename-window '%%'\"", &["command-prompt -p new name rename-window '%%'"]),
+        ];
+        for &(line, want) in cases {
+            let (_, b) = parse_bind(&args(line)).unwrap();
+            assert_eq!(commands(&b), want, "{}", line);
+        }
+    }
+
+    #[test]
+    fn bind_keys() {
+        let cases = [
+            ("C-b", KeyCode::Char('b'), KeyModifiers::CONTROL),
+            ("^B", KeyCode::Char('b'), KeyModifiers::CONTROL),
+            ("M-Left", KeyCode::Left, KeyModifiers::ALT),
+            ("'\"'", KeyCode::Char('"'), KeyModifiers::NONE),
+            ("\\;", KeyCode::Char(';'), KeyModifiers::NONE),
+            ("Space", KeyCode::Char(' '), KeyModifiers::NONE),
+        ];
+        for (name, code, mods) in cases {
+            let (_, b) = parse_bind(&args(&format!("{} new-window", name))).unwrap();
+            assert_eq!(b.key, (code, mods), "{}", name);
+        }
+    }
+
+    #[test]
+    fn bind_errors() {
+        for line in ["", "x", "-T", "-T table", "-x y new-window", "Nope new-window", "-n", "x \\;"] {
+            assert!(parse_bind(&args(line)).is_err(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn unbind_flags() {
+        let cases = [
+            ("x", "prefix", Some(key("x"))),
+            ("-n F1", "root", Some(key("F1"))),
+            ("-T copy-mode q", "copy-mode", Some(key("q"))),
+            ("-a", "prefix", None),
+            ("-a -T copy-mode-vi", "copy-mode-vi", None),
+            ("-an", "root", None),
+            ("-na", "root", None),
+            ("-a x", "prefix", None),
+        ];
+        for (line, table, want) in cases {
+            assert_eq!(parse_unbind(&args(line)).unwrap(), (table.to_string(), want), "{}", line);
+        }
+        for line in ["", "-n", "-x y", "-T", "Nope"] {
+            assert!(parse_unbind(&args(line)).is_err(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn binding_and_unbinding() {
+        let mut tables = KeyTables::with_defaults();
+        assert_eq!(commands(tables.get("prefix", key("c")).unwrap()), ["new-window"]);
+        assert!(tables.get("root", key("c")).is_none());
+        let (t, b) = parse_bind(&args("c kill-pane")).unwrap();
+        tables.bind(&t, b);
+        assert_eq!(commands(tables.get("prefix", key("c")).unwrap()), ["kill-pane"]);
+        assert_eq!(tables.list(Some("prefix")).iter().filter(|l| l.contains(" c ")).count(), 1);
+        tables.unbind("prefix", Some(key("c")));
+        assert!(tables.get("prefix", key("c")).is_none());
+        assert!(tables.get("prefix", key("n")).is_some());
+        tables.unbind("copy-mode", None);
+        assert!(tables.has_table("copy-mode"));
+        assert!(tables.list(Some("copy-mode")).is_empty());
+        assert!(!tables.list(Some("copy-mode-vi")).is_empty());
+        tables.unbind("missing", None);
+        assert!(!tables.has_table("missing"));
+    }
+
+    #[test]
+    fn list_is_sorted_bind_commands() {
+        let mut tables = KeyTables::default();
+        for line in ["-T t b second", "-r -T t a first \\; again", "-n C-F1 root-command"] {
+            let (t, b) = parse_bind(&args(line)).unwrap();
+            tables.bind(&t, b);
+        }
+        assert_eq!(tables.list(Some("t")), ["bind-key -r -T t a first \\; again", "bind-key -T t b second"]);
+        assert_eq!(tables.list(None), [
+            "bind-key -T root C-F1 root-command",
+            "bind-key -r -T t a first \\; again",
+            "bind-key -T t b second",
+        ]);
+    }
+
+    #[test]
+    fn list_reads_back_as_the_same_bindings() {
+        let mut tables = KeyTables::with_defaults();
+        for line in [
+            "-n M-'\"' display-message \"it's \\\"quoted\\\"\"",
+            "-r -T resize '#' resize-pane -L 5 \\; switch-client -T resize",
+            "-T prefix \\; command-prompt -p 'a;b' 'send-keys \\;'",
+            "-n '~' send-keys '~/x' ''",
+        ] {
+            let (t, b) = parse_bind(&args(line)).unwrap();
+            tables.bind(&t, b);
+        }
+        let listed = tables.list(None);
+        assert!(listed.len() > 80);
+        for line in &listed {
+            let words = args(line);
+            assert_eq!(words[0], "bind-key");
+            let (table, b) = parse_bind(&words[1..]).unwrap_or_else(|e| panic!("{}: {}", line, e));
+            let original = tables.get(&table, b.key).unwrap_or_else(|| panic!("{}: no binding", line));
+            assert_eq!((b.repeat, &b.commands), (original.repeat, &original.commands), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Escape sequences vt100 parses but leaves to its embedder.
+//!
+//! Programs ask their terminal questions — device attributes, the cursor
+//! position, whether a mode is set, which colours it uses — and wait for the
+//! answer on their input. Each pane's parser carries a [`PaneCallbacks`] that
+//! queues those answers; the server writes them to the pane's pty.
+
+use std::path::{Path, PathBuf};
+
+use vt100::{MouseProtocolEncoding, MouseProtocolMode, Screen};
+
+use crate::colors::palette;
+
+/// Parser type used for every pane.
+pub type PaneParser = vt100::Parser<PaneCallbacks>;
+
+pub fn new_parser(rows: u16, cols: u16, scrollback: usize) -> PaneParser {
+    vt100::Parser::new_with_callbacks(rows, cols, scrollback, PaneCallbacks::default())
+}
+
+#[derive(Default)]
+pub struct PaneCallbacks {
+    /// Replies not yet written back to the pane.
+    replies: Vec<u8>,
+    /// Cursor shape last requested with DECSCUSR, unless reset to the default.
+    cursor_shape: Option<u8>,
+    /// Title set with OSC 0 or 2 and not yet picked up.
+    new_title: Option<String>,
+    /// Working directory the shell last reported with OSC 7.
+    cwd: Option<PathBuf>,
+}
+
+impl PaneCallbacks {
+    /// Take the replies queued since the last call.
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.replies)
+    }
+
+    /// The title the application set since the last call, if any.
+    pub fn take_title(&mut self) -> Option<String> {
+        self.new_title.take()
+    }
+
+    pub fn cwd(&self) -> Option<&Path> {
+        self.cwd.as_deref()
+    }
+
+    pub fn cursor_shape(&self) -> Option<u8> {
+        self.cursor_shape
+    }
+
+    fn reply(&mut self, s: &str) {
+        self.replies.extend_from_slice(s.as_bytes());
+    }
+}
+
+impl vt100::Callbacks for PaneCallbacks {
+    fn set_window_title(&mut self, _screen: &mut Screen, title: &[u8]) {
+        self.new_title = Some(String::from_utf8_lossy(title).into_owned());
+    }
+
+    fn unhandled_csi(&mut self, screen: &mut Screen, i1: Option<u8>, i2: Option<u8>, params: &[&[u16]], c: char) {
+        let first = params.first().and_then(|p| p.first()).copied().unwrap_or(0);
+        match (i1, i2, c) {
+            // primary device attributes: a VT220 with ANSI colour
+            (None, None, 'c') if first == 0 => self.reply("\x1b[?62;22c"),
+            // secondary device attributes
+            (Some(b'>'), None, 'c') if first == 0 => self.reply("\x1b[>1;10;0c"),
+            // device status report
+            (None, None, 'n') if first == 5 => self.reply("\x1b[0n"),
+            (None, None, 'n') if first == 6 => {
+                let (row, col) = screen.cursor_position();
+                self.reply(&format!("\x1b[{};{}R", row + 1, col + 1));
+            }
+            (Some(b'?'), None, 'n') if first == 6 => {
+                let (row, col) = screen.cursor_position();
+                self.reply(&format!("\x1b[?{};{};1R", row + 1, col + 1));
+            }
+            // DECRQM: 1 set, 2 reset, 0 not recognised
+            (Some(b'?'), Some(b'$'), 'p') => self.reply(&format!("\x1b[?{};{}$y", first, private_mode_state(screen, first))),
+            (Some(b'$'), None, 'p') => self.reply(&format!("\x1b[{};0$y", first)),
+            // DECSCUSR: 0 restores the terminal's default shape
+            (Some(b' '), None, 'q') if first <= 6 => self.cursor_shape = (first != 0).then_some(first as u8),
+            _ => {}
+        }
+    }
+
+    fn unhandled_osc(&mut self, _screen: &mut Screen, params: &[&[u8]]) {
+        match params {
+            [b"10", b"?"] => self.reply(&format!("\x1b]10;{}\x1b\\", rgb_spec(DEFAULT_FG))),
+            [b"11", b"?"] => self.reply(&format!("\x1b]11;{}\x1b\\", rgb_spec(DEFAULT_BG))),
+            [b"12", b"?"] => self.reply(&format!("\x1b]12;{}\x1b\\", rgb_spec(DEFAULT_FG))),
+            [b"7", url] => { if let Some(path) = file_url_path(url) { self.cwd = Some(path); } }
+            [b"4", rest @ ..] => {
+                // OSC 4 ; index ; ? [; index ; ? ...]
+                for pair in rest.chunks(2) {
+                    let [index, b"?"] = pair else { continue };
+                    let Some(n) = std::str::from_utf8(index).ok().and_then(|s| s.parse::<u8>().ok()) else { continue };
+                    self.reply(&format!("\x1b]4;{};{}\x1b\\", n, rgb_spec(palette(n))));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The local path of an OSC 7 `file://host/path` URL.
+fn file_url_path(url: &[u8]) -> Option<PathBuf> {
+    let rest = url.strip_prefix(b"file://")?;
+    // skip the host name
+    let path = &rest[rest.iter().position(|&b| b == b'/')?..];
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        let hex = path.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (path[i], hex) {
+            (b'%', Some(b)) => { bytes.push(b); i += 3; }
+            (b, _) => { bytes.push(b); i += 1; }
+        }
+    }
+    let path = String::from_utf8(bytes).ok()?;
+    // Windows shells send file://host/C:/dir
+    let path = match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => &path[1..],
+        _ => &path[..],
+    };
+    Some(PathBuf::from(path))
+}
+
+fn private_mode_state(screen: &Screen, mode: u16) -> u8 {
+    let set = match mode {
+        1 => screen.application_cursor(),
+        25 => !screen.hide_cursor(),
+        47 | 1047 | 1049 => screen.alternate_screen(),
+        66 => screen.application_keypad(),
+        9 => screen.mouse_protocol_mode() == MouseProtocolMode::Press,
+        1000 => screen.mouse_protocol_mode() == MouseProtocolMode::PressRelease,
+        1002 => screen.mouse_protocol_mode() == MouseProtocolMode::ButtonMotion,
+        1003 => screen.mouse_protocol_mode() == MouseProtocolMode::AnyMotion,
+        1005 => screen.mouse_protocol_encoding() == MouseProtocolEncoding::Utf8,
+        1006 => screen.mouse_protocol_encoding() == MouseProtocolEncoding::Sgr,
+        2004 => screen.bracketed_paste(),
+        _ => return 0,
+    };
+    if set { 1 } else { 2 }
+}
+
+/// Colours reported for the default foreground and background. Panes have no
+/// colour of their own, so these are the usual light-on-dark defaults.
+const DEFAULT_FG: (u8, u8, u8) = (0xe5, 0xe5, 0xe5);
+const DEFAULT_BG: (u8, u8, u8) = (0x00, 0x00, 0x00);
+
+fn rgb_spec((r, g, b): (u8, u8, u8)) -> String {
+    format!("rgb:{:02x}{:02x}/{:02x}{:02x}/{:02x}{:02x}", r, r, g, g, b, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The replies a fresh 24x80 pane queues for `input`.
+    fn replies(input: &str) -> String {
+        let mut parser = new_parser(24, 80, 0);
+        parser.process(input.as_bytes());
+        String::from_utf8(parser.callbacks_mut().take_replies()).unwrap()
+    }
+
+    #[test]
+    fn device_attributes() {
+        assert_eq!(replies("\x1b[c"), "\x1b[?62;22c");
+        assert_eq!(replies("\x1b[0c"), "\x1b[?62;22c");
+        assert_eq!(replies("\x1b[>c"), "\x1b[>1;10;0c");
+        assert_eq!(replies("\x1b[>0c"), "\x1b[>1;10;0c");
+        assert_eq!(replies("\x1b[1c"), "");
+    }
+
+    #[test]
+    fn status_and_cursor_reports() {
+        assert_eq!(replies("\x1b[5n"), "\x1b[0n");
+        assert_eq!(replies("\x1b[6n"), "\x1b[1;1R");
+        assert_eq!(replies("\x1b[5;10H\x1b[6n"), "\x1b[5;10R");
+        assert_eq!(replies("abc\x1b[?6n"), "\x1b[?1;4;1R");
+    }
+
+    #[test]
+    fn mode_reports() {
+        assert_eq!(replies("\x1b[?1$p"), "\x1b[?1;2$y");
+        assert_eq!(replies("\x1b[?1h\x1b[?1$p"), "\x1b[?1;1$y");
+        assert_eq!(replies("\x1b[?25$p"), "\x1b[?25;1$y");
+        assert_eq!(replies("\x1b[?1049h\x1b[?1049$p"), "\x1b[?1049;1$y");
+        assert_eq!(replies("\x1b[?1002h\x1b[?1006h\x1b[?1002$p\x1b[?1006$p\x1b[?1000$p"), "\x1b[?1002;1$y\x1b[?1006;1$y\x1b[?1000;2$y");
+        assert_eq!(replies("\x1b[?2004h\x1b[?2004$p"), "\x1b[?2004;1$y");
+        assert_eq!(replies("\x1b[?12345$p"), "\x1b[?12345;0$y");
+        assert_eq!(replies("\x1b[4$p"), "\x1b[4;0$y");
+    }
+
+    #[test]
+    fn colour_queries() {
+        assert_eq!(replies("\x1b]10;?\x07"), "\x1b]10;rgb:e5e5/e5e5/e5e5\x1b\\");
+        assert_eq!(replies("\x1b]11;?\x1b\\"), "\x1b]11;rgb:0000/0000/0000\x1b\\");
+        assert_eq!(replies("\x1b]12;?\x07"), "\x1b]12;rgb:e5e5/e5e5/e5e5\x1b\\");
+        assert_eq!(replies("\x1b]4;1;?\x07"), "\x1b]4;1;rgb:cdcd/0000/0000\x1b\\");
+        assert_eq!(replies("\x1b]4;1;?;196;?\x07"), "\x1b]4;1;rgb:cdcd/0000/0000\x1b\\\x1b]4;196;rgb:ffff/0000/0000\x1b\\");
+        // setting a colour isn't a query
+        assert_eq!(replies("\x1b]4;1;rgb:ff/00/00\x07\x1b]10;#ffffff\x07"), "");
+    }
+
+    #[test]
+    fn replies_are_taken_once() {
+        let mut parser = new_parser(24, 80, 0);
+        parser.process(b"\x1b[5n\x1b[c");
+        assert_eq!(parser.callbacks_mut().take_replies(), b"\x1b[0n\x1b[?62;22c");
+        assert!(parser.callbacks_mut().take_replies().is_empty());
+    }
+
+    #[test]
+    fn titles_cursor_shapes_and_directories() {
+        let mut parser = new_parser(24, 80, 0);
+        parser.process(b"\x1b]2;hello\x07\x1b[5 q\x1b]7;file://host/tmp/a%20dir\x07");
+        let callbacks = parser.callbacks_mut();
+        assert_eq!(callbacks.take_title().as_deref(), Some("hello"));
+        assert_eq!(callbacks.take_title(), None);
+        assert_eq!(callbacks.cursor_shape(), Some(5));
+        assert_eq!(callbacks.cwd(), Some(Path::new("/tmp/a dir")));
+        parser.process(b"\x1b[0 q");
+        assert_eq!(parser.callbacks_mut().cursor_shape(), None);
+        assert_eq!(file_url_path(b"file://host/C:/Users/me"), Some(PathBuf::from("C:/Users/me")));
+    }
+}
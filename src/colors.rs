@@ -0,0 +1,220 @@
+//! Colours: what the terminal a client runs in can show, and mapping pane
+//! colours down to that.
+
+use ratatui::style::Color;
+
+use crate::glob::glob_match;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorDepth {
+    /// The sixteen ANSI colours.
+    Basic,
+    /// The xterm 256-colour palette.
+    Indexed,
+    /// 24-bit RGB.
+    TrueColor,
+}
+
+/// Work out the colour depth of the terminal from `TERM`, `COLORTERM` and a
+/// tmux-style `terminal-overrides` value such as `xterm*:Tc,screen*:colors=256`.
+pub fn detect(term: &str, colorterm: &str, overrides: &str) -> ColorDepth {
+    let mut depth = if colorterm.eq_ignore_ascii_case("truecolor") || colorterm.eq_ignore_ascii_case("24bit") {
+        ColorDepth::TrueColor
+    } else if term.contains("256col") {
+        ColorDepth::Indexed
+    } else if cfg!(windows) {
+        // the Windows console has taken RGB sequences since Windows 10 1703
+        ColorDepth::TrueColor
+    } else {
+        ColorDepth::Basic
+    };
+    for entry in overrides.split(',') {
+        let mut fields = entry.trim().split(':');
+        let Some(pattern) = fields.next().filter(|p| !p.is_empty()) else { continue };
+        if !glob_match(pattern.as_bytes(), term.as_bytes()) { continue; }
+        for cap in fields {
+            match cap {
+                "Tc" | "RGB" => depth = ColorDepth::TrueColor,
+                "colors=256" => depth = ColorDepth::Indexed,
+                "colors=16" | "colors=8" => depth = ColorDepth::Basic,
+                _ => {}
+            }
+        }
+    }
+    depth
+}
+
+/// Palette entry `i` as a ratatui colour, using the named colours for the first sixteen.
+pub fn indexed(i: u8) -> Color {
+    match i {
+        0 => Color::Black,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Yellow,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        7 => Color::Gray,
+        8 => Color::DarkGray,
+        9 => Color::LightRed,
+        10 => Color::LightGreen,
+        11 => Color::LightYellow,
+        12 => Color::LightBlue,
+        13 => Color::LightMagenta,
+        14 => Color::LightCyan,
+        15 => Color::White,
+        _ => Color::Indexed(i),
+    }
+}
+
+/// The closest colour to `c` that a terminal of `depth` can show.
+pub fn downgrade(c: Color, depth: ColorDepth) -> Color {
+    match (c, depth) {
+        (_, ColorDepth::TrueColor) => c,
+        (Color::Rgb(r, g, b), ColorDepth::Indexed) => indexed(nearest_256(r, g, b)),
+        (Color::Rgb(r, g, b), ColorDepth::Basic) => indexed(nearest_16(r, g, b)),
+        (Color::Indexed(i), ColorDepth::Basic) if i >= 16 => {
+            let (r, g, b) = palette(i);
+            indexed(nearest_16(r, g, b))
+        }
+        _ => c,
+    }
+}
+
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+/// Nearest entry of the 6x6x6 colour cube or the grey ramp.
+fn nearest_256(r: u8, g: u8, b: u8) -> u8 {
+    let level = |v: u8| CUBE_LEVELS.iter().enumerate().min_by_key(|(_, l)| (v as i32 - **l as i32).abs()).map(|(i, _)| i as u8).unwrap_or(0);
+    let (ri, gi, bi) = (level(r), level(g), level(b));
+    let cube = 16 + 36 * ri + 6 * gi + bi;
+    let avg = ((r as u16 + g as u16 + b as u16) / 3) as u8;
+    let grey = 232 + (avg.saturating_sub(3) / 10).min(23);
+    if distance(palette(grey), (r, g, b)) < distance(palette(cube), (r, g, b)) { grey } else { cube }
+}
+
+fn nearest_16(r: u8, g: u8, b: u8) -> u8 {
+    (0..16u8).min_by_key(|&i| distance(palette(i), (r, g, b))).unwrap_or(7)
+}
+
+/// The xterm 256-colour palette.
+pub fn palette(n: u8) -> (u8, u8, u8) {
+    const BASE: [(u8, u8, u8); 16] = [
+        (0x00, 0x00, 0x00), (0xcd, 0x00, 0x00), (0x00, 0xcd, 0x00), (0xcd, 0xcd, 0x00),
+        (0x00, 0x00, 0xee), (0xcd, 0x00, 0xcd), (0x00, 0xcd, 0xcd), (0xe5, 0xe5, 0xe5),
+        (0x7f, 0x7f, 0x7f), (0xff, 0x00, 0x00), (0x00, 0xff, 0x00), (0xff, 0xff, 0x00),
+        (0x5c, 0x5c, 0xff), (0xff, 0x00, 0xff), (0x00, 0xff, 0xff), (0xff, 0xff, 0xff),
+    ];
+    match n {
+        0..=15 => BASE[n as usize],
+        16..=231 => {
+            let i = n - 16;
+            (CUBE_LEVELS[(i / 36) as usize], CUBE_LEVELS[(i / 6 % 6) as usize], CUBE_LEVELS[(i % 6) as usize])
+        }
+        _ => {
+            let v = 8 + 10 * (n - 232);
+            (v, v, v)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_depth_from_the_environment() {
+        let plain = if cfg!(windows) { ColorDepth::TrueColor } else { ColorDepth::Basic };
+        let cases = [
+            ("xterm", "", plain),
+            ("screen", "", plain),
+            ("", "", plain),
+            ("xterm-256color", "", ColorDepth::Indexed),
+            ("tmux-256color", "", ColorDepth::Indexed),
+            ("xterm", "truecolor", ColorDepth::TrueColor),
+            ("xterm-256color", "24BIT", ColorDepth::TrueColor),
+            ("xterm", "yes", plain),
+        ];
+        for (term, colorterm, want) in cases {
+            assert_eq!(detect(term, colorterm, ""), want, "TERM={} COLORTERM={}", term, colorterm);
+        }
+    }
+
+    #[test]
+    fn terminal_overrides_apply_to_matching_terminals() {
+        let cases = [
+            ("xterm-256color", "", "xterm*:Tc", ColorDepth::TrueColor),
+            ("xterm", "", "xterm*:RGB", ColorDepth::TrueColor),
+            ("screen-256color", "", "xterm*:Tc", ColorDepth::Indexed),
+            ("xterm-256color", "", "xterm:Tc", ColorDepth::Indexed),
+            ("screen", "", "xterm*:Tc, screen*:colors=256", ColorDepth::Indexed),
+            ("xterm-256color", "truecolor", "*:colors=16", ColorDepth::Basic),
+            ("xterm-256color", "", "xterm*:Tc,xterm-256*:colors=256", ColorDepth::Indexed),
+            ("xterm-kitty", "", "xterm-?itty:Tc", ColorDepth::TrueColor),
+            ("xterm-256color", "", ",,:Tc,xterm*:bce", ColorDepth::Indexed),
+        ];
+        for (term, colorterm, overrides, want) in cases {
+            assert_eq!(detect(term, colorterm, overrides), want, "TERM={} overrides={}", term, overrides);
+        }
+    }
+
+    #[test]
+    fn nearest_palette_entries() {
+        let cases = [
+            ((0, 0, 0), 16),
+            ((255, 0, 0), 196),
+            ((255, 255, 255), 231),
+            ((95, 135, 175), 67),
+            ((128, 128, 128), 244),
+            ((100, 100, 100), 241),
+        ];
+        for ((r, g, b), want) in cases {
+            assert_eq!(nearest_256(r, g, b), want, "rgb({}, {}, {})", r, g, b);
+        }
+        let cases = [
+            ((0, 0, 0), 0),
+            ((200, 0, 0), 1),
+            ((255, 0, 0), 9),
+            ((128, 128, 128), 8),
+            ((229, 229, 229), 7),
+            ((250, 250, 250), 15),
+            ((90, 90, 250), 12),
+        ];
+        for ((r, g, b), want) in cases {
+            assert_eq!(nearest_16(r, g, b), want, "rgb({}, {}, {})", r, g, b);
+        }
+    }
+
+    #[test]
+    fn downgrades_to_the_depth() {
+        let cases = [
+            (Color::Rgb(255, 0, 0), ColorDepth::TrueColor, Color::Rgb(255, 0, 0)),
+            (Color::Rgb(255, 0, 0), ColorDepth::Indexed, Color::Indexed(196)),
+            (Color::Rgb(0, 0, 0), ColorDepth::Indexed, Color::Indexed(16)),
+            (Color::Rgb(255, 0, 0), ColorDepth::Basic, Color::LightRed),
+            (Color::Indexed(196), ColorDepth::Basic, Color::LightRed),
+            (Color::Indexed(244), ColorDepth::Basic, Color::DarkGray),
+            (Color::Indexed(196), ColorDepth::Indexed, Color::Indexed(196)),
+            (Color::Indexed(5), ColorDepth::Basic, Color::Indexed(5)),
+            (Color::Red, ColorDepth::Basic, Color::Red),
+        ];
+        for (c, depth, want) in cases {
+            assert_eq!(downgrade(c, depth), want, "{:?} at {:?}", c, depth);
+        }
+    }
+
+    #[test]
+    fn palette_matches_xterm() {
+        assert_eq!(palette(1), (0xcd, 0, 0));
+        assert_eq!(palette(16), (0, 0, 0));
+        assert_eq!(palette(67), (95, 135, 175));
+        assert_eq!(palette(231), (255, 255, 255));
+        assert_eq!(palette(232), (8, 8, 8));
+        assert_eq!(palette(255), (238, 238, 238));
+    }
+}
@@ -0,0 +1,454 @@
+//! The configuration language: files of pmux commands, one per line.
+//!
+//! Words are separated by spaces. Single quotes keep everything literal.
+//! Double quotes allow the escapes `\"`, `\\`, `\n`, `\t` and `\e`. Outside
+//! quotes a backslash escapes a space, quote, `;`, `#` or backslash, and any
+//! other backslash is kept, so Windows paths need no quoting. A `#` at the
+//! start of a word begins a comment. A `~` at the start of a word is the home
+//! directory. A backslash at the end of a line joins it to the next. Several
+//! commands can share a line separated by `;`, and `\;` is a literal `;`.
+//! `%if`, `%elif`, `%else` and `%endif` take or skip lines on the value of a
+//! format, and `source-file` reads other files.
+
+use std::path::{Path, PathBuf};
+
+use crate::format;
+use crate::glob::glob_match;
+
+/// Files nested deeper than this are taken to be sourcing themselves.
+const MAX_DEPTH: usize = 50;
+
+/// What configuration files are run against.
+pub trait Commands {
+    /// Run one command; an `Err` is reported with the file and line.
+    fn run(&mut self, args: &[String]) -> Result<(), String>;
+    /// Expand the format of a `%if` or `%elif`.
+    fn expand(&mut self, fmt: &str) -> String;
+    /// Report an error, already prefixed with its file and line.
+    fn error(&mut self, msg: String);
+}
+
+/// The configuration files read at startup when `-f` isn't given:
+/// `~/.pmux.conf` and `$XDG_CONFIG_HOME/pmux/pmux.conf`, which defaults to
+/// `~/.config/pmux/pmux.conf`.
+pub fn default_files() -> Vec<PathBuf> {
+    let Some(home) = home_dir() else { return Vec::new() };
+    let config_home = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).unwrap_or_else(|| home.join(".config"));
+    vec![home.join(".pmux.conf"), config_home.join("pmux").join("pmux.conf")]
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("USERPROFILE").or_else(|| std::env::var_os("HOME")).map(PathBuf::from)
+}
+
+/// Run every command in file `path`.
+pub fn source_file(path: &Path, cmds: &mut dyn Commands) {
+    source_at_depth(path, cmds, 0);
+}
+
+/// `source-file [-q] path...`: run the files matching each glob pattern,
+/// resolving relative ones against `dir`. `-q` ignores patterns that match nothing.
+pub fn source_command(args: &[String], dir: &Path, cmds: &mut dyn Commands) -> Result<(), String> {
+    source_command_at_depth(args, dir, cmds, 0)
+}
+
+fn source_command_at_depth(args: &[String], dir: &Path, cmds: &mut dyn Commands, depth: usize) -> Result<(), String> {
+    let quiet = args.iter().any(|a| a == "-q");
+    let patterns: Vec<&String> = args.iter().filter(|a| !a.starts_with('-')).collect();
+    if patterns.is_empty() { return Err("usage: source-file [-q] path".to_string()); }
+    for pattern in patterns {
+        let files = glob(&dir.join(pattern));
+        if files.is_empty() && !quiet { return Err(format!("no such file: {}", pattern)); }
+        for f in files { source_at_depth(&f, cmds, depth + 1); }
+    }
+    Ok(())
+}
+
+fn source_at_depth(path: &Path, cmds: &mut dyn Commands, depth: usize) {
+    if depth > MAX_DEPTH { cmds.error(format!("{}: too many nested files", path.display())); return; }
+    let text = match std::fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(e) => { cmds.error(format!("{}: {}", path.display(), e)); return; }
+    };
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut conds: Vec<Cond> = Vec::new();
+    let mut last_line = 0;
+    for (n, line) in logical_lines(&text) {
+        last_line = n;
+        let report = |cmds: &mut dyn Commands, msg: String| cmds.error(format!("{}:{}: {}", path.display(), n, msg));
+        let active = conds.last().is_none_or(|c| c.active);
+        let trimmed = line.trim();
+        if let Some(directive) = trimmed.strip_prefix('%') {
+            let (word, rest) = directive.split_once(char::is_whitespace).unwrap_or((directive, ""));
+            let condition = |cmds: &mut dyn Commands| -> Option<bool> {
+                match tokenize(rest).map(|c| c.concat()) {
+                    Ok(args) if args.len() == 1 => Some(format::truthy(&cmds.expand(&args[0]))),
+                    Ok(_) => { report(cmds, format!("%{} takes one format", word)); None }
+                    Err(e) => { report(cmds, e); None }
+                }
+            };
+            match (word, conds.last_mut()) {
+                ("if", _) => {
+                    let taken = active && condition(cmds).unwrap_or(false);
+                    conds.push(Cond { parent: active, taken, active: taken });
+                }
+                ("elif", Some(c)) => {
+                    let (parent, taken) = (c.parent, c.taken);
+                    let now = parent && !taken && condition(cmds).unwrap_or(false);
+                    if let Some(c) = conds.last_mut() { c.active = now; c.taken |= now; }
+                }
+                ("else", Some(c)) => { c.active = c.parent && !c.taken; c.taken = true; }
+                ("endif", Some(_)) => { conds.pop(); }
+                ("elif" | "else" | "endif", None) => report(cmds, format!("%{} without %if", word)),
+                _ => report(cmds, format!("unknown directive: %{}", word)),
+            }
+            continue;
+        }
+        if !active { continue; }
+        let commands = match tokenize(&line) {
+            Ok(c) => c,
+            Err(e) => { report(cmds, e); continue; }
+        };
+        for args in commands {
+            let result = match args[0].as_str() {
+                // relative to this file rather than to the server's directory
+                "source-file" | "source" => source_command_at_depth(&args[1..], dir, cmds, depth),
+                _ => cmds.run(&args),
+            };
+            if let Err(e) = result { report(cmds, e); }
+        }
+    }
+    if !conds.is_empty() { cmds.error(format!("{}:{}: missing %endif", path.display(), last_line)); }
+}
+
+/// State of one `%if` block.
+struct Cond {
+    /// Lines around the block are being run.
+    parent: bool,
+    /// A branch of the block has been taken.
+    taken: bool,
+    /// Lines in the current branch are being run.
+    active: bool,
+}
+
+/// Lines with continuations joined, each with the number of its first line.
+fn logical_lines(text: &str) -> Vec<(usize, String)> {
+    let mut out: Vec<(usize, String)> = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (i, line) in text.lines().enumerate() {
+        let (n, mut joined) = pending.take().unwrap_or((i + 1, String::new()));
+        let continued = line.ends_with('\\') && !line.ends_with("\\\\");
+        joined.push_str(if continued { &line[..line.len() - 1] } else { line });
+        if continued { pending = Some((n, joined)); } else { out.push((n, joined)); }
+    }
+    out.extend(pending);
+    out
+}
+
+/// Split a line into commands, each a list of words.
+pub fn tokenize(line: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut commands: Vec<Vec<String>> = Vec::new();
+    let mut args: Vec<String> = Vec::new();
+    let mut word = String::new();
+    // set once a word has started, so that "" is an empty argument
+    let mut in_word = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\r' | '\n' => { if in_word { args.push(std::mem::take(&mut word)); in_word = false; } }
+            '#' if !in_word => break,
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("missing closing '".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => word.push('\n'),
+                            Some('t') => word.push('\t'),
+                            Some('e') => word.push('\x1b'),
+                            Some(c @ ('"' | '\\' | '$')) => word.push(c),
+                            Some(c) => { word.push('\\'); word.push(c); }
+                            None => return Err("missing closing \"".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("missing closing \"".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                match chars.peek() {
+                    Some(&c @ (' ' | '\t' | '\'' | '"' | ';' | '#' | '\\')) => { word.push(c); chars.next(); }
+                    _ => word.push('\\'),
+                }
+            }
+            ';' => {
+                if in_word { args.push(std::mem::take(&mut word)); in_word = false; }
+                if !args.is_empty() { commands.push(std::mem::take(&mut args)); }
+            }
+            '~' if !in_word && matches!(chars.peek(), None | Some('/' | '\\' | ' ' | '\t')) => {
+                in_word = true;
+                word.push_str(&home_dir().map(|h| h.display().to_string()).unwrap_or_else(|| "~".to_string()));
+            }
+            c => { in_word = true; word.push(c); }
+        }
+    }
+    if in_word { args.push(word); }
+    if !args.is_empty() { commands.push(args); }
+    Ok(commands)
+}
+
+/// Existing files matching `pattern`, whose components may use `*` and `?`.
+fn glob(pattern: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::new()];
+    for comp in pattern.components() {
+        let part = comp.as_os_str().to_string_lossy();
+        if !part.contains(['*', '?']) {
+            for p in paths.iter_mut() { p.push(comp); }
+            continue;
+        }
+        let mut next = Vec::new();
+        for dir in &paths {
+            let Ok(entries) = std::fs::read_dir(if dir.as_os_str().is_empty() { Path::new(".") } else { dir }) else { continue };
+            let mut names: Vec<String> = entries.flatten().map(|e| e.file_name().to_string_lossy().into_owned()).collect();
+            names.sort();
+            for name in names {
+                // like the shell, wildcards don't match hidden files
+                if name.starts_with('.') && !part.starts_with('.') { continue; }
+                if glob_match(part.as_bytes(), name.as_bytes()) { next.push(dir.join(name)); }
+            }
+        }
+        paths = next;
+    }
+    paths.retain(|p| p.is_file());
+    paths
+}
+
+/// `s` as one word of a command, quoted when it needs to be.
+pub fn quote(s: &str) -> String {
+    let plain = !s.is_empty() && !s.starts_with(['#', '~']) && !s.contains(|c: char| c.is_whitespace() || matches!(c, '\'' | '"' | '\\' | ';'));
+    if plain { return s.to_string(); }
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' | '\\' | '$' => { out.push('\\'); out.push(c); }
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\x1b' => out.push_str("\\e"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the commands run and errors reported; formats expand to themselves.
+    #[derive(Default)]
+    struct Record {
+        ran: Vec<String>,
+        errors: Vec<String>,
+    }
+
+    impl Commands for Record {
+        fn run(&mut self, args: &[String]) -> Result<(), String> {
+            if args[0] == "fail" { return Err("failed".to_string()); }
+            self.ran.push(args[1..].join(" "));
+            Ok(())
+        }
+        fn expand(&mut self, fmt: &str) -> String {
+            fmt.to_string()
+        }
+        fn error(&mut self, msg: String) {
+            self.errors.push(msg);
+        }
+    }
+
+    /// A fresh directory holding `files`, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str, files: &[(&str, &str)]) -> TempDir {
+            let dir = std::env::temp_dir().join(format!("pmux-config-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            for (file, text) in files {
+                let path = dir.join(file);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, text).unwrap();
+            }
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn words(line: &str) -> Vec<Vec<String>> {
+        tokenize(line).unwrap_or_else(|e| panic!("{}: {}", line, e))
+    }
+
+    #[test]
+    fn tokenizes_words_and_quotes() {
+        let cases: &[(&str, &[&[&str]])] = &[
+            ("a b\tc", &[&["a", "b", "c"]]),
+            ("  a   b  ", &[&["a", "b"]]),
+            ("'a b' \"c d\"", &[&["a b", "c d"]]),
+            ("'it''s' x'y'z", &[&["its", "xyz"]]),
+            ("'a \\n \"b\"'", &[&["a \\n \"b\""]]),
+            ("\"a\\\"b\\\\c\\n\\t\\e\\$\\q\"", &[&["a\"b\\c\n\t\x1b$\\q"]]),
+            ("a\\ b C:\\Users\\me", &[&["a b", "C:\\Users\\me"]]),
+            ("\\'x\\\" \\\\", &[&["'x\"", "\\"]]),
+            ("\"\" ''", &[&["", ""]]),
+            ("", &[]),
+        ];
+        for &(line, want) in cases {
+            assert_eq!(words(line), want, "{}", line);
+        }
+    }
+
+    #[test]
+    fn separates_commands() {
+        let cases: &[(&str, &[&[&str]])] = &[
+            ("a ; b c", &[&["a"], &["b", "c"]]),
+            ("a;b", &[&["a"], &["b"]]),
+            ("a \\; b", &[&["a", ";", "b"]]),
+            ("a\\;", &[&["a;"]]),
+            ("'a;b' \"c;d\"", &[&["a;b", "c;d"]]),
+            (";; a ;;", &[&["a"]]),
+        ];
+        for &(line, want) in cases {
+            assert_eq!(words(line), want, "{}", line);
+        }
+    }
+
+    #[test]
+    fn comments_and_home() {
+        let home = home_dir().map(|h| h.display().to_string()).unwrap_or_else(|| "~".to_string());
+        let cases: Vec<(&str, Vec<Vec<String>>)> = vec![
+            ("# all comment", vec![]),
+            ("a # comment ; b", vec![vec!["a".into()]]),
+            ("a#b \\#c '#d'", vec![vec!["a#b".into(), "#c".into(), "#d".into()]]),
+            ("cd ~", vec![vec!["cd".into(), home.clone()]]),
+            ("cd ~/x", vec![vec!["cd".into(), format!("{}/x", home)]]),
+            ("a~ ~user '~'", vec![vec!["a~".into(), "~user".into(), "~".into()]]),
+        ];
+        for (line, want) in cases {
+            assert_eq!(words(line), want, "{}", line);
+        }
+    }
+
+    #[test]
+    fn unterminated_quotes_are_errors() {
+        for line in ["'abc", "\"abc", "\"abc\\", "a 'b c"] {
+            assert!(tokenize(line).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn quoted_words_read_back() {
+        for s in ["plain", "", "two words", "it's", "say \"hi\"", "C:\\dir", "a;b", "#x", "~", "tab\there", "line\nbreak", "\x1b[1m", "$HOME"] {
+            assert_eq!(words(&format!("cmd {}", quote(s))), [["cmd", s]], "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn continuations_join_lines() {
+        assert_eq!(logical_lines("a \\\n  b\nc\\\\\nd\\"), [(1, "a   b".to_string()), (3, "c\\\\".to_string()), (4, "d".to_string())]);
+    }
+
+    #[test]
+    fn conditionals() {
+        let text = "\
+%if 1
+run a
+%if 0
+run b
+%elif 1
+run c
+%elif 1
+run c2
+%else
+run d
+%endif
+%elif 1
+run e
+%else
+run f
+%endif
+%if 0
+%if 1
+run g
+%endif
+%elif 0
+run h
+%else
+run i
+%endif
+%if ''
+run j
+%endif
+run k ; run l
+%else
+%endif
+%bogus
+fail \\
+  here
+%if 1 2
+%endif
+%if 1
+";
+        let dir = TempDir::new("if", &[("test.conf", text)]);
+        let path = dir.0.join("test.conf");
+        let mut rec = Record::default();
+        source_file(&path, &mut rec);
+        assert_eq!(rec.ran, ["a", "c", "i", "k", "l"]);
+        let p = path.display();
+        assert_eq!(rec.errors, [
+            format!("{}:30: %else without %if", p),
+            format!("{}:31: %endif without %if", p),
+            format!("{}:32: unknown directive: %bogus", p),
+            format!("{}:33: failed", p),
+            format!("{}:35: %if takes one format", p),
+            format!("{}:37: missing %endif", p),
+        ]);
+    }
+
+    #[test]
+    fn sources_relative_globs() {
+        let dir = TempDir::new("glob", &[
+            ("main.conf", "run main\nsource-file sub/*.conf\nsource -q missing*.conf\nsource-file missing.conf"),
+            ("sub/b.conf", "run b"),
+            ("sub/a.conf", "run a"),
+            ("sub/.hidden.conf", "run hidden"),
+        ]);
+        let mut rec = Record::default();
+        source_file(&dir.0.join("main.conf"), &mut rec);
+        assert_eq!(rec.ran, ["main", "a", "b"]);
+        assert_eq!(rec.errors, [format!("{}:4: no such file: missing.conf", dir.0.join("main.conf").display())]);
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let dir = TempDir::new("depth", &[("loop.conf", "run once\nsource-file loop.conf")]);
+        let mut rec = Record::default();
+        source_file(&dir.0.join("loop.conf"), &mut rec);
+        assert_eq!(rec.ran.len(), MAX_DEPTH + 1);
+        assert_eq!(rec.errors, [format!("{}: too many nested files", dir.0.join("loop.conf").display())]);
+    }
+}
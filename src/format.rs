@@ -0,0 +1,350 @@
+//! The tmux format language, shared by the status line, titles and the
+//! `-F` options of the list commands.
+//!
+//! `#{name}` is replaced by a variable and `#{?cond,then,else}` picks one of
+//! two formats. `#{==:a,b}` and the other comparisons give `1` or `0`.
+//! Modifiers before a colon reshape a value: `=N` truncates (from the end
+//! when negative), `pN` pads, and `t` turns a timestamp into a date. They
+//! combine with `;`, as in `#{=10;p10:pane_title}`. `#S`, `#I`, `#W` and the
+//! other single letters stand for the common variables. `##`, `#,` and `#}`
+//! are a literal `#`, `,` and `}`. `#(command)` is replaced by the output of
+//! a shell command, after expanding any formats inside it. `#[...]` style
+//! markers are left for the status line to interpret.
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, TimeZone};
+use unicode_width::UnicodeWidthChar;
+
+/// What a format is expanded against.
+pub trait Context {
+    /// The value of variable `name`; unknown variables are empty.
+    fn var(&self, name: &str) -> Option<String>;
+    /// The output to show for `#(cmd)`.
+    fn command(&self, cmd: &str) -> String;
+}
+
+/// Expand `fmt` against `ctx`.
+pub fn expand(fmt: &str, ctx: &dyn Context) -> String {
+    let mut out = String::new();
+    let mut rest = fmt;
+    while let Some(i) = rest.find('#') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        let Some(c) = rest.chars().next() else { out.push('#'); break };
+        match c {
+            '{' => match closing_brace(&rest[1..]) {
+                Some(end) => {
+                    out.push_str(&expand_item(&rest[1..1 + end], ctx));
+                    rest = &rest[end + 2..];
+                }
+                None => { out.push('#'); }
+            },
+            '(' => match closing_paren(&rest[1..]) {
+                Some(end) => {
+                    out.push_str(&ctx.command(&expand(&rest[1..1 + end], ctx)));
+                    rest = &rest[end + 2..];
+                }
+                None => { out.push('#'); }
+            },
+            '#' | ',' | '}' => { out.push(c); rest = &rest[1..]; }
+            _ => match alias(c) {
+                Some(name) => { out.push_str(&ctx.var(name).unwrap_or_default()); rest = &rest[1..]; }
+                // `#[style]` and anything unknown are kept as written
+                None => out.push('#'),
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Apply strftime `%` sequences to `fmt`, leaving it alone if any are invalid.
+pub fn strftime(fmt: &str, now: &DateTime<Local>) -> String {
+    if !fmt.contains('%') { return fmt.to_string(); }
+    let items: Vec<Item> = StrftimeItems::new(fmt).collect();
+    if items.iter().any(|i| matches!(i, Item::Error)) { return fmt.to_string(); }
+    now.format_with_items(items.into_iter()).to_string()
+}
+
+/// The variables the single-letter `#X` forms stand for.
+fn alias(c: char) -> Option<&'static str> {
+    Some(match c {
+        'D' => "pane_id",
+        'F' => "window_flags",
+        'I' => "window_index",
+        'P' => "pane_index",
+        'S' => "session_name",
+        'T' => "pane_title",
+        'W' => "window_name",
+        _ => return None,
+    })
+}
+
+/// Byte offset of the `)` closing a `#(` whose contents start `s`.
+fn closing_paren(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, b) in s.bytes().enumerate() {
+        match b {
+            b'(' => depth += 1,
+            b')' if depth == 0 => return Some(i),
+            b')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Byte offset of the `}` closing a `#{` whose contents start `s`.
+fn closing_brace(s: &str) -> Option<usize> {
+    let b = s.as_bytes();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < b.len() {
+        match b[i] {
+            b'#' if b.get(i + 1) == Some(&b'{') => { depth += 1; i += 1; }
+            // a command may hold any characters
+            b'#' if b.get(i + 1) == Some(&b'(') => { i += 2 + closing_paren(&s[i + 2..]).unwrap_or(b.len()); }
+            // escaped: `##`, `#,`, `#}`
+            b'#' => { i += 1; }
+            b'}' if depth == 0 => return Some(i),
+            b'}' => { depth -= 1; }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Split `s` at the commas that aren't escaped or inside a nested `#{...}`.
+fn split_args(s: &str) -> Vec<&str> {
+    let b = s.as_bytes();
+    let mut out = Vec::new();
+    let (mut depth, mut start, mut i) = (0, 0, 0);
+    while i < b.len() {
+        match b[i] {
+            b'#' if b.get(i + 1) == Some(&b'{') => { depth += 1; i += 1; }
+            b'#' if b.get(i + 1) == Some(&b'(') => { i += 2 + closing_paren(&s[i + 2..]).unwrap_or(b.len()); }
+            b'#' => { i += 1; }
+            b'}' if depth > 0 => { depth -= 1; }
+            b',' if depth == 0 => { out.push(&s[start..i]); start = i + 1; }
+            _ => {}
+        }
+        i += 1;
+    }
+    out.push(&s[start..]);
+    out
+}
+
+/// Expand the contents of one `#{...}`.
+fn expand_item(body: &str, ctx: &dyn Context) -> String {
+    if let Some(rest) = body.strip_prefix('?') {
+        // #{?cond,then,else}, or #{?c1,a,c2,b,else} to chain them
+        let args = split_args(rest);
+        let mut i = 0;
+        while i + 1 < args.len() {
+            if truthy(&value(args[i], ctx)) { return expand(args[i + 1], ctx); }
+            i += 2;
+        }
+        return args.get(i).map(|a| expand(a, ctx)).unwrap_or_default();
+    }
+    let Some((mods, rest)) = body.split_once(':').filter(|(m, _)| is_modifier_list(m)) else { return value(body, ctx) };
+    if let Some(op) = mods.split(';').find(|m| COMPARISONS.contains(m)) {
+        let args = split_args(rest);
+        let (a, b) = (expand(args[0], ctx), args.get(1).map(|b| expand(b, ctx)).unwrap_or_default());
+        return if compare(op, &a, &b) { "1" } else { "0" }.to_string();
+    }
+    let mut v = value(rest, ctx);
+    for m in mods.split(';') {
+        if m == "t" {
+            if let Some(t) = v.parse::<i64>().ok().and_then(|secs| Local.timestamp_opt(secs, 0).single()) {
+                v = t.format("%a %b %e %H:%M:%S %Y").to_string();
+            }
+        } else if let Some(n) = m.strip_prefix('=').and_then(|n| n.parse::<i32>().ok()) {
+            v = truncate(&v, n);
+        } else if let Some(n) = m.strip_prefix('p').and_then(|n| n.parse::<i32>().ok()) {
+            v = pad(&v, n);
+        }
+    }
+    v
+}
+
+const COMPARISONS: [&str; 8] = ["==", "!=", "<", ">", "<=", ">=", "||", "&&"];
+
+fn is_modifier_list(mods: &str) -> bool {
+    !mods.is_empty() && mods.split(';').all(|m| {
+        COMPARISONS.contains(&m)
+            || m == "t"
+            || m.strip_prefix('=').or_else(|| m.strip_prefix('p')).is_some_and(|n| n.parse::<i32>().is_ok())
+    })
+}
+
+/// A variable, or a format when `name` has `#` in it.
+fn value(name: &str, ctx: &dyn Context) -> String {
+    if name.contains('#') { expand(name, ctx) } else { ctx.var(name).unwrap_or_default() }
+}
+
+pub fn truthy(s: &str) -> bool {
+    !s.is_empty() && s != "0"
+}
+
+/// Compare two expanded values; numbers compare as numbers, anything else as text.
+fn compare(op: &str, a: &str, b: &str) -> bool {
+    let ord = match (a.parse::<i64>(), b.parse::<i64>()) {
+        (Ok(x), Ok(y)) => x.cmp(&y),
+        _ => a.cmp(b),
+    };
+    match op {
+        "==" => ord.is_eq(),
+        "!=" => ord.is_ne(),
+        "<" => ord.is_lt(),
+        ">" => ord.is_gt(),
+        "<=" => ord.is_le(),
+        ">=" => ord.is_ge(),
+        "||" => truthy(a) || truthy(b),
+        "&&" => truthy(a) && truthy(b),
+        _ => false,
+    }
+}
+
+/// The first `n` columns of `s`, or the last `-n` when `n` is negative.
+fn truncate(s: &str, n: i32) -> String {
+    let fit = |chars: &mut dyn Iterator<Item = char>| {
+        let mut width = 0;
+        chars.take_while(|c| { width += c.width().unwrap_or(0); width <= n.unsigned_abs() as usize }).collect::<Vec<char>>()
+    };
+    if n >= 0 { fit(&mut s.chars()).into_iter().collect() } else { fit(&mut s.chars().rev()).into_iter().rev().collect() }
+}
+
+/// `s` padded with spaces to `n` columns: after it when `n` is positive, before it when negative.
+fn pad(s: &str, n: i32) -> String {
+    let width: usize = s.chars().map(|c| c.width().unwrap_or(0)).sum();
+    let fill = " ".repeat((n.unsigned_abs() as usize).saturating_sub(width));
+    if n >= 0 { format!("{}{}", s, fill) } else { format!("{}{}", fill, s) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct Vars(HashMap<&'static str, &'static str>);
+
+    impl Context for Vars {
+        fn var(&self, name: &str) -> Option<String> {
+            self.0.get(name).map(|v| v.to_string())
+        }
+        fn command(&self, cmd: &str) -> String {
+            format!("<{}>", cmd)
+        }
+    }
+
+    fn check(cases: &[(&str, &str)]) {
+        let ctx = Vars(HashMap::from([
+            ("a", "x"), ("zero", "0"), ("empty", ""), ("long", "abcdef"), ("short", "ab"), ("wide", "日本語"),
+            ("session_name", "main"), ("window_index", "2"), ("nine", "9"), ("ten", "10"), ("epoch", "0"), ("a:b", "colon"),
+        ]));
+        for &(fmt, want) in cases {
+            assert_eq!(expand(fmt, &ctx), want, "{}", fmt);
+        }
+    }
+
+    #[test]
+    fn variables_and_aliases() {
+        check(&[
+            ("#{a}", "x"),
+            ("[#{unknown}]", "[]"),
+            ("#S:#I", "main:2"),
+            ("#Z", "#Z"),
+            ("#[fg=red]#{a}#[default]", "#[fg=red]x#[default]"),
+            ("#{a:b}", "colon"),
+            ("#(echo #{a})", "<echo x>"),
+            ("#(echo (nested) #{a})", "<echo (nested) x>"),
+        ]);
+    }
+
+    #[test]
+    fn conditionals() {
+        check(&[
+            ("#{?a,yes,no}", "yes"),
+            ("#{?zero,yes,no}", "no"),
+            ("#{?empty,yes,no}", "no"),
+            ("#{?unknown,yes}", ""),
+            ("#{?a,#{long},no}", "abcdef"),
+            ("#{?#{==:#{a},x},#{?empty,E,not empty},no}", "not empty"),
+            ("#{?zero,one,a,two,other}", "two"),
+            ("#{?zero,one,empty,two,other}", "other"),
+            ("#{?a,#{?zero,,#{?a,deep,}},}", "deep"),
+        ]);
+    }
+
+    #[test]
+    fn comparisons() {
+        check(&[
+            ("#{==:#{a},x}", "1"),
+            ("#{==:#{a},y}", "0"),
+            ("#{!=:a,b}", "1"),
+            ("#{<:#{nine},#{ten}}", "1"),
+            ("#{<:b,a}", "0"),
+            ("#{>=:10,10}", "1"),
+            ("#{>:abc,abd}", "0"),
+            ("#{<=:2,#{window_index}}", "1"),
+            ("#{||:0,}", "0"),
+            ("#{||:0,#{a}}", "1"),
+            ("#{&&:1,#{zero}}", "0"),
+        ]);
+    }
+
+    #[test]
+    fn modifiers() {
+        check(&[
+            ("#{=3:long}", "abc"),
+            ("#{=-3:long}", "def"),
+            ("#{=10:long}", "abcdef"),
+            ("#{=0:long}", ""),
+            ("#{=3:wide}", "日"),
+            ("#{=-4:wide}", "本語"),
+            ("#{p5:short}", "ab   "),
+            ("#{p-5:short}", "   ab"),
+            ("#{p1:short}", "ab"),
+            ("#{p3:wide}", "日本語"),
+            ("#{=3;p5:long}", "abc  "),
+            ("#{=2:#{long}#{short}}", "ab"),
+            ("#{t:a}", "x"),
+        ]);
+        let epoch = Local.timestamp_opt(0, 0).single().unwrap().format("%a %b %e %H:%M:%S %Y").to_string();
+        check(&[("#{t:epoch}", &epoch)]);
+    }
+
+    #[test]
+    fn escapes() {
+        check(&[
+            ("##", "#"),
+            ("100##", "100#"),
+            ("#,#}", ",}"),
+            ("#{?a,x#,y,z}", "x,y"),
+            ("#{?a,a#}b,c}", "a}b"),
+            ("#{?zero,a,b###,c}", "b#,c"),
+        ]);
+    }
+
+    #[test]
+    fn unterminated_input_is_kept() {
+        check(&[
+            ("end#", "end#"),
+            ("a#{b", "a#{b"),
+            ("#{?a,x", "#{?a,x"),
+            ("#(cmd", "#(cmd"),
+            ("#{a}#{", "x#{"),
+        ]);
+    }
+
+    #[test]
+    fn strftime_sequences() {
+        let now = Local.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).single().unwrap();
+        assert_eq!(strftime("%Y-%m-%d %H:%M:%S", &now), "2024-01-02 03:04:05");
+        assert_eq!(strftime("no sequences", &now), "no sequences");
+        assert_eq!(strftime("100%", &now), "100%");
+        assert_eq!(strftime("%Q %H", &now), "%Q %H");
+        assert_eq!(strftime("%% %H", &now), "% 03");
+    }
+}
@@ -0,0 +1,37 @@
+//! `*` and `?` wildcards, for `terminal-overrides` terminal names and
+//! `source-file` paths.
+
+/// Whether `text` matches `pattern`, where `*` matches any run of bytes and
+/// `?` any one byte.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        Some((b'?', rest)) => !text.is_empty() && glob_match(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_patterns() {
+        let cases = [
+            ("*", "", true),
+            ("*", "anything", true),
+            ("xterm*", "xterm", true),
+            ("xterm*", "xterm-256color", true),
+            ("?term", "xterm", true),
+            ("?term", "term", false),
+            ("x*m", "xterm", true),
+            ("x*z", "xterm", false),
+            ("*256*", "screen-256color", true),
+            ("screen", "screen-256color", false),
+        ];
+        for (pattern, text, want) in cases {
+            assert_eq!(glob_match(pattern.as_bytes(), text.as_bytes()), want, "{} ~ {}", pattern, text);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! `#(command)` in formats. Commands run on their own threads and the format
+//! shows the first line of their last output, so a slow command never holds
+//! up drawing. Each is run again once its output is `status-interval` old.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A command still running after this long is killed.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A command not asked for in this many status intervals is forgotten.
+const KEEP_INTERVALS: u32 = 3;
+
+#[derive(Default)]
+pub struct JobCache {
+    jobs: HashMap<String, Job>,
+}
+
+struct Job {
+    output: String,
+    started: Instant,
+    /// When the format last asked for it.
+    requested: Instant,
+    /// Set while the command runs.
+    running: Option<mpsc::Receiver<String>>,
+}
+
+impl JobCache {
+    /// What to show for `cmd`: its last output, or nothing before it first
+    /// finishes. Starts the command when it hasn't run in the last `interval`;
+    /// a zero interval runs it only once. Commands no longer asked for are
+    /// dropped after a few intervals.
+    pub fn output(&mut self, cmd: &str, interval: Duration) -> String {
+        if !interval.is_zero() { self.jobs.retain(|_, job| job.requested.elapsed() < interval * KEEP_INTERVALS); }
+        let job = self.jobs.entry(cmd.to_string()).or_insert_with(|| Job { output: String::new(), started: Instant::now(), requested: Instant::now(), running: Some(spawn(cmd)) });
+        job.requested = Instant::now();
+        job.collect();
+        if job.running.is_none() && !interval.is_zero() && job.started.elapsed() >= interval {
+            job.started = Instant::now();
+            job.running = Some(spawn(cmd));
+        }
+        job.output.clone()
+    }
+
+    /// Take the output of the commands that have finished since the last call;
+    /// `true` if there was any.
+    pub fn poll(&mut self) -> bool {
+        let mut done = false;
+        for job in self.jobs.values_mut() { done |= job.collect(); }
+        done
+    }
+}
+
+impl Job {
+    /// Take the command's output if it has finished; `true` if it just did.
+    fn collect(&mut self) -> bool {
+        let Some(rx) = &self.running else { return false };
+        self.output = match rx.try_recv() {
+            Ok(out) => out,
+            Err(mpsc::TryRecvError::Disconnected) => "<error>".to_string(),
+            Err(mpsc::TryRecvError::Empty) => return false,
+        };
+        self.running = None;
+        true
+    }
+}
+
+/// Run `cmd` through the shell on a new thread, which sends back the first
+/// line of its output or a short marker saying why there is none.
+fn spawn(cmd: &str) -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    let cmd = cmd.to_string();
+    thread::spawn(move || { let _ = tx.send(run(&cmd, TIMEOUT)); });
+    rx
+}
+
+fn run(cmd: &str, timeout: Duration) -> String {
+    let mut command = if cfg!(windows) { Command::new("cmd") } else { Command::new("sh") };
+    command.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(cmd);
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // its own group, so a timeout kills whatever the shell started too
+        command.process_group(0);
+    }
+    let Ok(mut child) = command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::null()).spawn() else { return "<error>".to_string() };
+    // read on another thread so a command that fills the pipe can't stall the wait
+    let mut stdout = child.stdout.take();
+    let reader = thread::spawn(move || {
+        let mut out = String::new();
+        if let Some(s) = stdout.as_mut() { let _ = s.read_to_string(&mut out); }
+        out
+    });
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() < timeout => thread::sleep(Duration::from_millis(20)),
+            _ => {
+                kill(&mut child);
+                let _ = child.wait();
+                return "<timeout>".to_string();
+            }
+        }
+    };
+    let out = reader.join().unwrap_or_default();
+    let first = out.lines().next().unwrap_or("").trim_end().to_string();
+    match status.code() {
+        Some(code) if code != 0 && first.is_empty() => format!("<exit {}>", code),
+        _ => first,
+    }
+}
+
+/// Kill a timed-out command and, on unix, everything else in its process
+/// group, which also closes the pipe the reader thread is blocked on.
+fn kill(child: &mut std::process::Child) {
+    #[cfg(unix)]
+    // SAFETY: kill has no memory-safety preconditions; the group is the one
+    // the child leads, and a stale id only makes the call fail.
+    unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL); }
+    let _ = child.kill();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The output of `cmd` once its current run has finished.
+    fn finished(cache: &mut JobCache, cmd: &str, interval: Duration) -> String {
+        let started = Instant::now();
+        let mut out = cache.output(cmd, interval);
+        while cache.jobs[cmd].running.is_some() && started.elapsed() < TIMEOUT {
+            thread::sleep(Duration::from_millis(10));
+            out = cache.output(cmd, interval);
+        }
+        out
+    }
+
+    #[test]
+    fn runs_commands() {
+        assert_eq!(run("echo hello", TIMEOUT), "hello");
+        assert_eq!(run("echo first; echo second", TIMEOUT), "first");
+        assert_eq!(run("exit 3", TIMEOUT), "<exit 3>");
+        assert_eq!(run("echo partial; exit 3", TIMEOUT), "partial");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kills_commands_that_time_out() {
+        let started = Instant::now();
+        assert_eq!(run("sleep 10; echo late", Duration::from_millis(100)), "<timeout>");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn a_lost_command_is_an_error() {
+        let (tx, rx) = mpsc::channel();
+        drop(tx);
+        let mut job = Job { output: String::new(), started: Instant::now(), requested: Instant::now(), running: Some(rx) };
+        assert!(job.collect());
+        assert_eq!(job.output, "<error>");
+    }
+
+    #[test]
+    fn reuses_output_within_the_interval() {
+        let mut cache = JobCache::default();
+        let interval = Duration::from_millis(300);
+        assert_eq!(cache.output("echo hi", interval), "");
+        assert_eq!(finished(&mut cache, "echo hi", interval), "hi");
+        let started = cache.jobs["echo hi"].started;
+        assert_eq!(cache.output("echo hi", interval), "hi");
+        assert_eq!(cache.jobs["echo hi"].started, started);
+        assert!(cache.jobs["echo hi"].running.is_none());
+
+        thread::sleep(interval);
+        assert_eq!(cache.output("echo hi", interval), "hi");
+        assert!(cache.jobs["echo hi"].started > started);
+        assert_eq!(finished(&mut cache, "echo hi", interval), "hi");
+    }
+
+    #[test]
+    fn a_zero_interval_runs_once() {
+        let mut cache = JobCache::default();
+        assert_eq!(finished(&mut cache, "echo once", Duration::ZERO), "once");
+        let started = cache.jobs["echo once"].started;
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(cache.output("echo once", Duration::ZERO), "once");
+        assert_eq!(cache.jobs["echo once"].started, started);
+    }
+
+    #[test]
+    fn forgets_commands_no_longer_asked_for() {
+        let mut cache = JobCache::default();
+        let interval = Duration::from_millis(50);
+        finished(&mut cache, "echo old", interval);
+        cache.output("echo new", interval);
+        assert!(cache.jobs.contains_key("echo old"));
+        thread::sleep(interval * KEEP_INTERVALS);
+        cache.output("echo new", interval);
+        assert!(!cache.jobs.contains_key("echo old"));
+        assert!(cache.jobs.contains_key("echo new"));
+    }
+}
@@ -0,0 +1,376 @@
+//! Key handling: turning crossterm key events into the bytes a terminal
+//! application expects, parsing tmux-style key names, and the wire form used
+//! between attached clients and the server.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventState, KeyModifiers};
+
+/// Bytes an xterm would send for `key`, given the pane's DECCKM (application
+/// cursor) and DECKPAM (application keypad) modes.
+pub fn encode_key(key: &KeyEvent, app_cursor: bool, app_keypad: bool) -> Vec<u8> {
+    let mods = key.modifiers;
+    let alt = mods.contains(KeyModifiers::ALT);
+    let ctrl = mods.contains(KeyModifiers::CONTROL);
+    // xterm modifier parameter: 1 + shift(1) + alt(2) + ctrl(4)
+    let param = 1
+        + if mods.contains(KeyModifiers::SHIFT) { 1 } else { 0 }
+        + if alt { 2 } else { 0 }
+        + if ctrl { 4 } else { 0 };
+    let keypad = key.state.contains(KeyEventState::KEYPAD);
+
+    if keypad && app_keypad && param == 1 {
+        let app = match key.code {
+            KeyCode::Char(c @ '0'..='9') => Some((b'p' + (c as u8 - b'0')) as char),
+            KeyCode::Char('+') => Some('k'),
+            KeyCode::Char('-') => Some('m'),
+            KeyCode::Char('*') => Some('j'),
+            KeyCode::Char('/') => Some('o'),
+            KeyCode::Char('.') => Some('n'),
+            KeyCode::Char('=') => Some('X'),
+            KeyCode::Enter => Some('M'),
+            _ => None,
+        };
+        if let Some(c) = app { return format!("\x1bO{}", c).into_bytes(); }
+    }
+
+    let mut out: Vec<u8> = Vec::new();
+    match key.code {
+        KeyCode::Char(c) => {
+            // Windows reports AltGr as Ctrl+Alt; those characters are already composed
+            if ctrl && alt && !c.is_ascii_alphabetic() {
+                let mut buf = [0u8; 4];
+                return c.encode_utf8(&mut buf).as_bytes().to_vec();
+            }
+            if alt { out.push(0x1b); }
+            match ctrl.then(|| ctrl_byte(c)).flatten() {
+                Some(b) => out.push(b),
+                None => {
+                    let mut buf = [0u8; 4];
+                    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+        }
+        KeyCode::Enter => { if alt { out.push(0x1b); } out.push(b'\r'); }
+        KeyCode::Tab if mods.contains(KeyModifiers::SHIFT) => out.extend_from_slice(b"\x1b[Z"),
+        KeyCode::Tab => { if alt { out.push(0x1b); } out.push(b'\t'); }
+        KeyCode::BackTab => out.extend_from_slice(b"\x1b[Z"),
+        KeyCode::Backspace => {
+            if alt { out.push(0x1b); }
+            out.push(if ctrl { 0x08 } else { 0x7f });
+        }
+        KeyCode::Esc => { if alt { out.push(0x1b); } out.push(0x1b); }
+        KeyCode::Up => out.extend(cursor_key('A', param, app_cursor)),
+        KeyCode::Down => out.extend(cursor_key('B', param, app_cursor)),
+        KeyCode::Right => out.extend(cursor_key('C', param, app_cursor)),
+        KeyCode::Left => out.extend(cursor_key('D', param, app_cursor)),
+        KeyCode::Home => out.extend(cursor_key('H', param, app_cursor)),
+        KeyCode::End => out.extend(cursor_key('F', param, app_cursor)),
+        KeyCode::Insert => out.extend(tilde_key(2, param)),
+        KeyCode::Delete => out.extend(tilde_key(3, param)),
+        KeyCode::PageUp => out.extend(tilde_key(5, param)),
+        KeyCode::PageDown => out.extend(tilde_key(6, param)),
+        KeyCode::F(n @ 1..=4) => {
+            let c = (b'P' + (n - 1)) as char;
+            if param == 1 { out.extend(format!("\x1bO{}", c).into_bytes()); } else { out.extend(format!("\x1b[1;{}{}", param, c).into_bytes()); }
+        }
+        KeyCode::F(n) => {
+            let code = match n {
+                5 => 15, 6 => 17, 7 => 18, 8 => 19, 9 => 20, 10 => 21, 11 => 23, 12 => 24,
+                13 => 25, 14 => 26, 15 => 28, 16 => 29, 17 => 31, 18 => 32, 19 => 33, 20 => 34,
+                _ => return out,
+            };
+            out.extend(tilde_key(code, param));
+        }
+        _ => {}
+    }
+    out
+}
+
+/// The C0 control byte for Ctrl+`c`, if there is one.
+fn ctrl_byte(c: char) -> Option<u8> {
+    match c {
+        'a'..='z' => Some(c as u8 - b'a' + 1),
+        'A'..='Z' => Some(c as u8 - b'A' + 1),
+        '@' | ' ' | '2' => Some(0x00),
+        '[' | '3' => Some(0x1b),
+        '\\' | '4' => Some(0x1c),
+        ']' | '5' => Some(0x1d),
+        '^' | '6' => Some(0x1e),
+        '_' | '-' | '7' | '/' => Some(0x1f),
+        '?' | '8' => Some(0x7f),
+        _ => None,
+    }
+}
+
+fn cursor_key(c: char, param: u8, app_cursor: bool) -> Vec<u8> {
+    if param > 1 { format!("\x1b[1;{}{}", param, c).into_bytes() }
+    else if app_cursor { format!("\x1bO{}", c).into_bytes() }
+    else { format!("\x1b[{}", c).into_bytes() }
+}
+
+fn tilde_key(code: u8, param: u8) -> Vec<u8> {
+    if param > 1 { format!("\x1b[{};{}~", code, param).into_bytes() } else { format!("\x1b[{}~", code).into_bytes() }
+}
+
+/// Parse a key name as accepted by `send-keys` and `bind-key`: `C-`, `M-` and `S-` prefixes
+/// followed by a single character or a named key (`Enter`, `Up`, `F5`, `PPage`, ...).
+pub fn parse_key_name(name: &str) -> Option<KeyEvent> {
+    let mut mods = KeyModifiers::NONE;
+    let mut rest = name;
+    loop {
+        if rest.len() > 2 {
+            if let Some(r) = rest.strip_prefix("C-").or_else(|| rest.strip_prefix("c-")) { mods |= KeyModifiers::CONTROL; rest = r; continue; }
+            if let Some(r) = rest.strip_prefix("M-").or_else(|| rest.strip_prefix("m-")) { mods |= KeyModifiers::ALT; rest = r; continue; }
+            if let Some(r) = rest.strip_prefix("S-").or_else(|| rest.strip_prefix("s-")) { mods |= KeyModifiers::SHIFT; rest = r; continue; }
+        } else if let Some(r) = rest.strip_prefix("^").filter(|r| r.chars().count() == 1) {
+            mods |= KeyModifiers::CONTROL;
+            rest = r;
+        }
+        break;
+    }
+    let mut chars = rest.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyEvent::new(KeyCode::Char(c), mods));
+    }
+    let lower = rest.to_ascii_lowercase();
+    let code = match lower.as_str() {
+        "enter" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "btab" | "backtab" => KeyCode::BackTab,
+        "bspace" | "backspace" => KeyCode::Backspace,
+        "escape" | "esc" => KeyCode::Esc,
+        "space" => KeyCode::Char(' '),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "ic" | "insert" => KeyCode::Insert,
+        "dc" | "delete" => KeyCode::Delete,
+        "ppage" | "pageup" | "pgup" => KeyCode::PageUp,
+        "npage" | "pagedown" | "pgdn" => KeyCode::PageDown,
+        _ => {
+            let n = lower.strip_prefix('f')?.parse::<u8>().ok().filter(|n| (1..=20).contains(n))?;
+            KeyCode::F(n)
+        }
+    };
+    Some(KeyEvent::new(code, mods))
+}
+
+/// The form of `key` that bindings are looked up by: control characters
+/// become `C-` and a letter, and shift is left to the case of a character.
+pub fn binding_key(key: &KeyEvent) -> (KeyCode, KeyModifiers) {
+    let mut mods = key.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+    let code = match key.code {
+        KeyCode::Char(c @ '\u{1}'..='\u{1a}') => { mods |= KeyModifiers::CONTROL; KeyCode::Char((c as u8 + b'a' - 1) as char) }
+        KeyCode::Char(c) => {
+            let shift = mods.contains(KeyModifiers::SHIFT);
+            mods.remove(KeyModifiers::SHIFT);
+            KeyCode::Char(if mods.contains(KeyModifiers::CONTROL) { c.to_ascii_lowercase() } else if shift { c.to_ascii_uppercase() } else { c })
+        }
+        code => code,
+    };
+    (code, mods)
+}
+
+/// The name `parse_key_name` reads back as `key`.
+pub fn key_name((code, mods): (KeyCode, KeyModifiers)) -> String {
+    let mut out = String::new();
+    if mods.contains(KeyModifiers::CONTROL) { out.push_str("C-"); }
+    if mods.contains(KeyModifiers::ALT) { out.push_str("M-"); }
+    if mods.contains(KeyModifiers::SHIFT) { out.push_str("S-"); }
+    match code {
+        KeyCode::Char(' ') => out.push_str("Space"),
+        KeyCode::Char(c) => out.push(c),
+        KeyCode::F(n) => out.push_str(&format!("F{}", n)),
+        code => out.push_str(match code {
+            KeyCode::Enter => "Enter",
+            KeyCode::Tab => "Tab",
+            KeyCode::BackTab => "BTab",
+            KeyCode::Backspace => "BSpace",
+            KeyCode::Esc => "Escape",
+            KeyCode::Up => "Up",
+            KeyCode::Down => "Down",
+            KeyCode::Left => "Left",
+            KeyCode::Right => "Right",
+            KeyCode::Home => "Home",
+            KeyCode::End => "End",
+            KeyCode::Insert => "IC",
+            KeyCode::Delete => "DC",
+            KeyCode::PageUp => "PPage",
+            KeyCode::PageDown => "NPage",
+            _ => "None",
+        }),
+    }
+    out
+}
+
+/// Encode a key event as modifier bits and a code name for the wire.
+pub fn key_to_wire(key: &KeyEvent) -> (u8, String) {
+    let code = match key.code {
+        KeyCode::Char(c) => format!("char:{}", c as u32),
+        KeyCode::F(n) => format!("f:{}", n),
+        KeyCode::Enter => "enter".to_string(),
+        KeyCode::Tab => "tab".to_string(),
+        KeyCode::BackTab => "backtab".to_string(),
+        KeyCode::Backspace => "backspace".to_string(),
+        KeyCode::Esc => "esc".to_string(),
+        KeyCode::Left => "left".to_string(),
+        KeyCode::Right => "right".to_string(),
+        KeyCode::Up => "up".to_string(),
+        KeyCode::Down => "down".to_string(),
+        KeyCode::Home => "home".to_string(),
+        KeyCode::End => "end".to_string(),
+        KeyCode::PageUp => "pageup".to_string(),
+        KeyCode::PageDown => "pagedown".to_string(),
+        KeyCode::Delete => "delete".to_string(),
+        KeyCode::Insert => "insert".to_string(),
+        _ => "none".to_string(),
+    };
+    // keys from the numeric keypad are marked so application keypad mode can apply
+    let code = if key.state.contains(KeyEventState::KEYPAD) { format!("kp:{}", code) } else { code };
+    (key.modifiers.bits(), code)
+}
+
+pub fn key_from_wire(mods: u8, code: &str) -> Option<KeyEvent> {
+    let modifiers = KeyModifiers::from_bits_truncate(mods);
+    let (code, state) = match code.strip_prefix("kp:") { Some(c) => (c, KeyEventState::KEYPAD), None => (code, KeyEventState::NONE) };
+    let code = if let Some(c) = code.strip_prefix("char:") { KeyCode::Char(char::from_u32(c.parse().ok()?)?) }
+        else if let Some(n) = code.strip_prefix("f:") { KeyCode::F(n.parse().ok()?) }
+        else {
+            match code {
+                "enter" => KeyCode::Enter,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "backspace" => KeyCode::Backspace,
+                "esc" => KeyCode::Esc,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                "delete" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                _ => return None,
+            }
+        };
+    Some(KeyEvent::new_with_kind_and_state(code, modifiers, crossterm::event::KeyEventKind::Press, state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> KeyEvent {
+        parse_key_name(name).unwrap_or_else(|| panic!("unparsed key {}", name))
+    }
+
+    #[test]
+    fn encodes_named_keys() {
+        let cases: &[(&str, bool, &[u8])] = &[
+            ("a", false, b"a"),
+            ("C-a", false, b"\x01"),
+            ("C-Space", false, b"\x00"),
+            ("M-x", false, b"\x1bx"),
+            ("C-M-x", false, b"\x1b\x18"),
+            ("Enter", false, b"\r"),
+            ("M-Enter", false, b"\x1b\r"),
+            ("Tab", false, b"\t"),
+            ("S-Tab", false, b"\x1b[Z"),
+            ("BTab", false, b"\x1b[Z"),
+            ("BSpace", false, b"\x7f"),
+            ("C-BSpace", false, b"\x08"),
+            ("Escape", false, b"\x1b"),
+            ("Up", false, b"\x1b[A"),
+            ("Up", true, b"\x1bOA"),
+            ("C-Up", false, b"\x1b[1;5A"),
+            ("C-Up", true, b"\x1b[1;5A"),
+            ("S-Left", false, b"\x1b[1;2D"),
+            ("M-Right", false, b"\x1b[1;3C"),
+            ("Home", true, b"\x1bOH"),
+            ("DC", false, b"\x1b[3~"),
+            ("C-NPage", false, b"\x1b[6;5~"),
+            ("F1", false, b"\x1bOP"),
+            ("C-F1", false, b"\x1b[1;5P"),
+            ("F5", false, b"\x1b[15~"),
+            ("S-F5", false, b"\x1b[15;2~"),
+            ("C-F5", false, b"\x1b[15;5~"),
+            ("C-M-S-F5", false, b"\x1b[15;8~"),
+            ("F12", false, b"\x1b[24~"),
+        ];
+        for &(name, app_cursor, want) in cases {
+            assert_eq!(encode_key(&key(name), app_cursor, false), want, "{} (app cursor {})", name, app_cursor);
+        }
+    }
+
+    #[test]
+    fn altgr_characters_are_sent_as_typed() {
+        let altgr = KeyModifiers::CONTROL | KeyModifiers::ALT;
+        assert_eq!(encode_key(&KeyEvent::new(KeyCode::Char('@'), altgr), false, false), b"@");
+        assert_eq!(encode_key(&KeyEvent::new(KeyCode::Char('€'), altgr), false, false), "€".as_bytes());
+        assert_eq!(encode_key(&KeyEvent::new(KeyCode::Char('q'), altgr), false, false), b"\x1b\x11");
+    }
+
+    #[test]
+    fn keypad_keys_follow_application_keypad() {
+        let five = KeyEvent::new_with_kind_and_state(KeyCode::Char('5'), KeyModifiers::NONE, crossterm::event::KeyEventKind::Press, KeyEventState::KEYPAD);
+        assert_eq!(encode_key(&five, false, true), b"\x1bOu");
+        assert_eq!(encode_key(&five, false, false), b"5");
+    }
+
+    #[test]
+    fn parses_key_names() {
+        let cases = [
+            ("a", KeyCode::Char('a'), KeyModifiers::NONE),
+            ("^b", KeyCode::Char('b'), KeyModifiers::CONTROL),
+            ("c-M-x", KeyCode::Char('x'), KeyModifiers::CONTROL | KeyModifiers::ALT),
+            ("S-Tab", KeyCode::Tab, KeyModifiers::SHIFT),
+            ("Space", KeyCode::Char(' '), KeyModifiers::NONE),
+            ("pgup", KeyCode::PageUp, KeyModifiers::NONE),
+            ("F20", KeyCode::F(20), KeyModifiers::NONE),
+            ("-", KeyCode::Char('-'), KeyModifiers::NONE),
+            ("C--", KeyCode::Char('-'), KeyModifiers::CONTROL),
+        ];
+        for (name, code, mods) in cases {
+            assert_eq!(parse_key_name(name), Some(KeyEvent::new(code, mods)), "{}", name);
+        }
+        for bad in ["F21", "F0", "Nope", "C-Nope", ""] {
+            assert_eq!(parse_key_name(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn binding_keys_fold_control_characters_and_shift() {
+        let cases = [
+            (KeyEvent::new(KeyCode::Char('\u{2}'), KeyModifiers::NONE), (KeyCode::Char('b'), KeyModifiers::CONTROL)),
+            (KeyEvent::new(KeyCode::Char('B'), KeyModifiers::CONTROL | KeyModifiers::SHIFT), (KeyCode::Char('b'), KeyModifiers::CONTROL)),
+            (KeyEvent::new(KeyCode::Char('a'), KeyModifiers::SHIFT), (KeyCode::Char('A'), KeyModifiers::NONE)),
+            (KeyEvent::new(KeyCode::Up, KeyModifiers::SHIFT), (KeyCode::Up, KeyModifiers::SHIFT)),
+        ];
+        for (event, want) in cases {
+            assert_eq!(binding_key(&event), want, "{:?}", event);
+        }
+    }
+
+    #[test]
+    fn key_names_round_trip() {
+        for name in ["a", "A", "%", "C-b", "M-x", "C-M-x", "Space", "C-Space", "Enter", "Tab", "BTab", "S-Tab", "BSpace", "Escape",
+            "Up", "C-Up", "M-S-Down", "Home", "End", "IC", "DC", "PPage", "NPage", "F1", "S-F5", "C-M-F12"] {
+            let key = binding_key(&key(name));
+            assert_eq!(key_name(key), name);
+            assert_eq!(binding_key(&parse_key_name(&key_name(key)).unwrap()), key, "{}", name);
+        }
+    }
+
+    #[test]
+    fn wire_round_trip() {
+        for name in ["a", "C-b", "M-€", "Enter", "S-Tab", "C-Up", "F5", "NPage"] {
+            let (mods, code) = key_to_wire(&key(name));
+            assert_eq!(key_from_wire(mods, &code), Some(key(name)), "{}", name);
+        }
+        assert_eq!(key_from_wire(0, "bogus"), None);
+    }
+}
@@ -1,8 +1,10 @@
 use std::io::{self, Write};
 use std::sync::{Arc, Mutex, mpsc};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::thread;
 use std::time::{Duration, Instant};
-use std::net::TcpListener;
 use std::io::Read as _;
 use std::io::BufRead as _;
 
@@ -14,23 +16,104 @@ use ratatui::Terminal;
 use crossterm::terminal::{enable_raw_mode, disable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
 use crossterm::{execute};
 use crossterm::cursor::{EnableBlinking, DisableBlinking};
-use crossterm::event::EnableMouseCapture;
-use crossterm::event::DisableMouseCapture;
+use crossterm::event::{EnableMouseCapture, EnableBracketedPaste};
+use crossterm::event::{DisableMouseCapture, DisableBracketedPaste};
 use ratatui::style::{Style, Modifier};
-use unicode_width::UnicodeWidthStr;
 use chrono::Local;
 use std::env;
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::ffi::OsStr;
 use crossterm::style::Print;
+use unicode_width::UnicodeWidthStr;
 use serde::{Serialize, Deserialize};
 
+mod bindings;
+mod callbacks;
+mod colors;
+mod config;
+mod format;
+mod glob;
+mod jobs;
+mod keys;
+mod mouse;
+mod options;
+mod procinfo;
+mod protocol;
+mod transport;
+
+use callbacks::PaneParser;
+use colors::ColorDepth;
+use keys::{key_from_wire, key_to_wire};
+use options::Scope;
+use protocol::{ClientMsg, ServerMsg};
+use transport::Stream;
+
 struct Pane {
-    master: Box<dyn MasterPty>,
+    /// Input and resizes for the pane's writer thread, which owns the PTY master.
+    input: PaneWriter,
     child: Box<dyn portable_pty::Child>,
-    term: Arc<Mutex<vt100::Parser>>,
+    term: Arc<Mutex<PaneParser>>,
     last_rows: u16,
     last_cols: u16,
     id: usize,
     title: String,
+    /// Process id of the pane's shell, where the platform lets us find it.
+    pid: Option<u32>,
+    /// Bumped by the reader thread whenever the screen may have changed.
+    generation: Arc<AtomicU64>,
+    options: options::Options,
+}
+
+/// The server's end of a pane's writer thread.
+struct PaneWriter {
+    /// Input to write, and wake-ups for a new size.
+    tx: mpsc::Sender<Option<Vec<u8>>>,
+    /// The size to give the PTY before writing anything more.
+    size: Arc<Mutex<Option<PtySize>>>,
+}
+
+impl PaneWriter {
+    fn write(&self, bytes: Vec<u8>) {
+        let _ = self.tx.send(Some(bytes));
+    }
+
+    /// Resize the PTY. This doesn't wait behind queued input, and only the
+    /// latest size is applied.
+    fn resize(&self, size: PtySize) {
+        *self.size.lock().unwrap() = Some(size);
+        let _ = self.tx.send(None);
+    }
+}
+
+/// portable-pty's masters are a file descriptor on unix and handles behind a
+/// mutex on Windows, so they can move to another thread, but `MasterPty`
+/// doesn't say so.
+struct SendMaster(Box<dyn MasterPty>);
+
+// SAFETY: see above; the master is only ever used from the one writer thread.
+unsafe impl Send for SendMaster {}
+
+impl SendMaster {
+    fn into_inner(self) -> Box<dyn MasterPty> { self.0 }
+}
+
+/// Start the thread that writes to a pane's PTY and resizes it. A program
+/// that stops reading its input blocks that thread and the writes queued
+/// behind it, not the server.
+fn spawn_pane_writer(master: Box<dyn MasterPty>) -> PaneWriter {
+    let (tx, rx) = mpsc::channel::<Option<Vec<u8>>>();
+    let size: Arc<Mutex<Option<PtySize>>> = Arc::new(Mutex::new(None));
+    let pending = size.clone();
+    let master = SendMaster(master);
+    thread::spawn(move || {
+        let mut master = master.into_inner();
+        for input in rx {
+            if let Some(size) = pending.lock().unwrap().take() { let _ = master.resize(size); }
+            if let Some(bytes) = input { let _ = master.write_all(&bytes).and_then(|_| master.flush()); }
+        }
+    });
+    PaneWriter { tx, size }
 }
 
 #[derive(Clone, Copy)]
@@ -46,37 +129,52 @@ struct Window {
     active_path: Vec<usize>,
     name: String,
     id: usize,
+    /// Panes marked to receive synchronized input; if none are, all panes do.
+    sync_marked: HashSet<usize>,
+    options: options::Options,
 }
 
 enum Mode {
     Passthrough,
-    Prefix { armed_at: Instant },
-    CommandPrompt { input: String },
-    WindowChooser { selected: usize },
-    RenamePrompt { input: String },
-    CopyMode,
+    /// `command-prompt`: the input replaces `%%` in `template`, or is the command when that's empty.
+    CommandPrompt { input: String, prompt: Option<String>, template: String },
+    TreeChooser { selected: usize },
+    Copy,
     PaneChooser { opened_at: Instant },
 }
 
 #[derive(Clone, Copy)]
 enum FocusDir { Left, Right, Up, Down }
 
+/// A key table other than `root` that the next key is looked up in.
+struct ActiveTable {
+    name: String,
+    since: Instant,
+    /// Entered by a `-r` key, so only repeatable keys within `repeat-time` stay in it.
+    repeat: bool,
+}
+
 struct AppState {
     windows: Vec<Window>,
     active_idx: usize,
     mode: Mode,
-    escape_time_ms: u64,
-    prefix_key: (KeyCode, KeyModifiers),
     drag: Option<DragState>,
+    /// Pane that received a forwarded button press and gets the drags and release that follow.
+    mouse_grab: Option<Vec<usize>>,
     last_window_area: Rect,
-    mouse_enabled: bool,
     paste_buffers: Vec<String>,
-    status_left: String,
-    status_right: String,
+    jobs: RefCell<jobs::JobCache>,
+    /// Size of the attached client's terminal, status lines included.
+    client_size: (u16, u16),
+    last_rename_check: Instant,
     copy_anchor: Option<(u16,u16)>,
     copy_pos: Option<(u16,u16)>,
     display_map: Vec<(usize, Vec<usize>)>,
-    binds: Vec<Bind>,
+    key_tables: bindings::KeyTables,
+    /// Set after the prefix or by `switch-client -T`; `None` is the root table.
+    key_table: Option<ActiveTable>,
+    /// Set by `detach-client` for the client whose key or command ran it.
+    detach: bool,
     control_rx: Option<mpsc::Receiver<CtrlReq>>,
     control_port: Option<u16>,
     session_name: String,
@@ -85,7 +183,46 @@ struct AppState {
     next_win_id: usize,
     next_pane_id: usize,
     zoom_saved: Option<Vec<(Vec<usize>, Vec<u16>)>>,
-    sync_input: bool,
+    /// Copy mode was entered with the mouse wheel and ends once scrolled back to the bottom.
+    copy_exit_on_bottom: bool,
+    clients: Vec<AttachedClient>,
+    /// Set when something besides pane output may have changed what clients
+    /// show; cleared once frames are pushed.
+    frame_dirty: bool,
+    /// Generations of the visible panes when frames were last pushed.
+    frame_generations: Vec<(usize, u64)>,
+    status_cache: Option<StatusCache>,
+    server_options: options::Options,
+    /// Global session options, which `session_options` falls back to.
+    global_options: options::Options,
+    session_options: options::Options,
+    /// Global window and pane options, which each window's and pane's own fall back to.
+    global_window_options: options::Options,
+    /// Errors from configuration files, as `file:line: message`, not yet shown to a client.
+    config_errors: Vec<String>,
+}
+
+struct ClientPane {
+    generation: u64,
+    shadow: vt100::Parser,
+}
+
+/// Status lines and terminal title as last expanded.
+struct StatusCache {
+    expanded: Instant,
+    lines: Vec<StatusLineJson>,
+    title: Option<String>,
+}
+
+/// A client attached over a framed connection.
+struct AttachedClient {
+    id: usize,
+    tx: mpsc::Sender<ServerMsg>,
+    last_frame: String,
+    /// What this client was last sent for each pane, to diff new output against.
+    panes: HashMap<usize, ClientPane>,
+    /// Disconnects once the client's writer thread has flushed its last message.
+    done: mpsc::Receiver<()>,
 }
 
 struct DragState {
@@ -114,11 +251,11 @@ fn print_help() {
     println!(r#"{prog} - Terminal multiplexer for Windows (tmux alternative)
 
 USAGE:
-    {prog} [COMMAND] [OPTIONS]
+    {prog} [-f FILE] [COMMAND] [OPTIONS]
 
 COMMANDS:
     (no command)        Start a new session or attach to existing one
-    new-session         Create a new session
+    new-session         Create a new session and attach to it
         -s <name>       Session name (default: "default")
         -d              Start detached (in background)
     attach, attach-session
@@ -131,11 +268,32 @@ COMMANDS:
         -v              Split vertically (top/bottom, default)
     kill-pane           Close the current pane
     capture-pane        Capture the content of current pane
+    list-windows, lsw   List windows (JSON, or one line each with -F)
+    list-panes, lsp     List panes of the current window
+        -s              List the panes of every window
+        -F <format>     Format each line with tmux format variables
+    display-message     Show a message in the status line
+        -p <format>     Print the expanded format instead
+    source-file <path>  Run the commands in a configuration file
+        -q              Ignore paths that match no file
+    set-option, set     Set an option in the running session
+        -g              Set the global value
+        -w, -p, -s      Set a window, pane or server option
+        -u              Unset, going back to the inherited value
+        -a              Append to the current value
+    show-options, show  Show options (same flags, -v for values only)
+    bind-key, bind      Bind a key to commands: bind-key [-nr] [-T table] key command
+        -n              Bind in the root table (no prefix needed)
+        -r              Let the key repeat without the prefix for repeat-time
+        -T <table>      Bind in another table, entered with switch-client -T
+    unbind-key, unbind  Remove a binding (-a removes every one in the table)
+    list-keys, lsk      List key bindings as bind-key commands
     server              Run as a server (internal use)
     help                Show this help message
     version             Show version information
 
 OPTIONS:
+    -f <file>           Configuration file for a new server, instead of the default ones
     -h, --help          Show this help message
     -V, --version       Show version information
 
@@ -147,12 +305,14 @@ KEY BINDINGS (default prefix: Ctrl+B):
     prefix + %          Split pane vertically
     prefix + o          Switch to next pane
     prefix + x          Kill current pane
-    prefix + d          Detach from session
+    prefix + d          Detach from session (the session keeps running)
     prefix + [          Enter copy mode
     prefix + :          Enter command mode
     prefix + ,          Rename current window
-    prefix + w          Window chooser
+    prefix + t          Set the pane title
+    prefix + w          Window/pane chooser
     prefix + q          Display pane numbers
+    prefix + Ctrl+B     Send Ctrl+B to the pane (follows the prefix option)
 
 ENVIRONMENT VARIABLES:
     PMUX_SESSION_NAME       Default session name
@@ -161,8 +321,9 @@ ENVIRONMENT VARIABLES:
     PMUX_CURSOR_BLINK       Cursor blinking (true/false)
 
 CONFIG FILES:
-    %USERPROFILE%\.pmux.conf     Main configuration file
-    %USERPROFILE%\.pmuxrc        Alternative configuration file
+    ~/.pmux.conf                 Main configuration file
+    ~/.config/pmux/pmux.conf     Read as well when it exists
+    ~/.pmuxrc                    Default session name
 
 EXAMPLES:
     {prog}                          Start or attach to default session
@@ -183,8 +344,17 @@ fn print_version() {
 }
 
 fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    
+    let mut args: Vec<String> = env::args().collect();
+    // `-f file` before the command: the configuration a new server reads instead of the default files
+    let config_file = match args.get(1).map(String::as_str) {
+        Some("-f") if args.len() > 2 => {
+            let file = args.remove(2);
+            args.remove(1);
+            Some(env::current_dir().map(|cwd| cwd.join(&file)).unwrap_or_else(|_| PathBuf::from(file)))
+        }
+        _ => None,
+    };
+
     // Handle help and version flags first
     if args.len() > 1 {
         match args[1].as_str() {
@@ -199,70 +369,78 @@ fn main() -> io::Result<()> {
             _ => {}
         }
     }
-    
+
+    let mut session = env::var("PMUX_SESSION_NAME").unwrap_or_else(|_| "default".to_string());
+    let mut create_if_missing = true;
     if args.len() > 1 {
         match args[1].as_str() {
             "ls" | "list-sessions" => {
-                let home = env::var("USERPROFILE").or_else(|_| env::var("HOME")).unwrap_or_default();
-                let dir = format!("{}\\.pmux", home);
-                if let Ok(entries) = std::fs::read_dir(&dir) {
-                    for e in entries.flatten() {
-                        if let Some(name) = e.file_name().to_str() {
-                            if let Some((base, ext)) = name.rsplit_once('.') {
-                                if ext == "port" {
-                                    if let Ok(port_str) = std::fs::read_to_string(e.path()) {
-                                        if let Ok(_p) = port_str.trim().parse::<u16>() {
-                                            let addr = format!("127.0.0.1:{}", port_str.trim());
-                                            if let Ok(mut s) = std::net::TcpStream::connect(addr) {
-                                                let _ = std::io::Write::write_all(&mut s, b"session-info\n");
-                                                let mut br = std::io::BufReader::new(s);
-                                                let mut line = String::new();
-                                                let _ = br.read_line(&mut line);
-                                                if !line.trim().is_empty() { println!("{}", line.trim_end()); } else { println!("{}", base); }
-                                            } else { /* stale: skip */ }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+                for (base, _) in transport::list_sessions() {
+                    if let Ok(mut s) = transport::connect(&base) {
+                        let _ = std::io::Write::write_all(&mut s, b"session-info\n");
+                        let mut br = std::io::BufReader::new(s);
+                        let mut line = String::new();
+                        let _ = br.read_line(&mut line);
+                        if !line.trim().is_empty() { println!("{}", line.trim_end()); } else { println!("{}", base); }
+                    } else { /* stale: skip */ }
                 }
                 return Ok(());
             }
             "attach" | "attach-session" => {
-                let name = args
+                session = args
                     .iter()
                     .position(|a| a == "-t")
                     .and_then(|i| args.get(i + 1))
-                    .map(|s| s.clone())
+                    .cloned()
                     .or_else(resolve_default_session_name)
                     .or_else(resolve_last_session_name)
                     .unwrap_or_else(|| "default".to_string());
-                env::set_var("PMUX_SESSION_NAME", name);
-                env::set_var("PMUX_REMOTE_ATTACH", "1");
+                create_if_missing = false;
+            }
+            "server" => {
+                let name = args.iter().position(|a| a == "-s").and_then(|i| args.get(i+1)).cloned().unwrap_or_else(|| "default".to_string());
+                return run_server(name, config_file);
             }
-            "server" | "new-session" => {
-                let name = args.iter().position(|a| a == "-s").and_then(|i| args.get(i+1)).map(|s| s.clone()).unwrap_or_else(|| "default".to_string());
-                let detached = args.iter().any(|a| a == "-d");
-                if detached {
-                    let exe = std::env::current_exe().unwrap_or_else(|_| std::path::PathBuf::from("pmux"));
-                    let mut cmd = std::process::Command::new(exe);
-                    cmd.arg("server").arg("-s").arg(&name);
-                    let _child = cmd.spawn().map_err(|e| io::Error::new(io::ErrorKind::Other, format!("failed to spawn server: {e}")))?;
+            "new-session" => {
+                let name = args.iter().position(|a| a == "-s").and_then(|i| args.get(i+1)).cloned().unwrap_or_else(|| "default".to_string());
+                if transport::session_alive(&name) {
+                    eprintln!("pmux: duplicate session: {}", name);
                     return Ok(());
-                } else {
-                    return run_server(name);
                 }
+                if args.iter().any(|a| a == "-d") {
+                    return spawn_server(&name, config_file.as_deref());
+                }
+                session = name;
             }
-            "new-window" => { send_control("new-window\n".to_string())?; return Ok(()); }
+            "new-window" => { send_control(format!("new-window{}\n", cli_start_dir(&args)))?; return Ok(()); }
             "split-window" => {
                 let flag = if args.iter().any(|a| a == "-h") { "-h" } else { "-v" };
-                send_control(format!("split-window {}\n", flag))?; return Ok(());
+                send_control(format!("split-window {}{}\n", flag, cli_start_dir(&args)))?; return Ok(());
             }
             "kill-pane" => { send_control("kill-pane\n".to_string())?; return Ok(()); }
             "capture-pane" => {
                 let resp = send_control_with_response("capture-pane\n".to_string())?; print!("{}", resp); return Ok(());
             }
+            "source-file" | "source" => {
+                let words: Vec<String> = args[2..].iter().map(|a| match a.starts_with('-') {
+                    true => a.clone(),
+                    false => config::quote(&env::current_dir().map(|cwd| cwd.join(a)).unwrap_or_else(|_| PathBuf::from(a)).display().to_string()),
+                }).collect();
+                let resp = send_control_with_response(format!("source-file {}\n", words.join(" ")))?; eprint!("{}", resp); return Ok(());
+            }
+            "set-option" | "set" | "set-window-option" | "setw" | "show-options" | "show" | "show-window-options" | "showw"
+            | "bind-key" | "bind" | "unbind-key" | "unbind" | "list-keys" | "lsk" => {
+                let cmd = match args[1].as_str() {
+                    "set" => "set-option", "setw" => "set-window-option", "show" => "show-options", "showw" => "show-window-options",
+                    "bind" => "bind-key", "unbind" => "unbind-key", "lsk" => "list-keys", c => c,
+                };
+                let words: Vec<String> = args[2..].iter().map(|a| config::quote(a)).collect();
+                let resp = send_control_with_response(format!("{} {}\n", cmd, words.join(" ")))?; print!("{}", resp); return Ok(());
+            }
+            "list-windows" | "lsw" | "list-panes" | "lsp" | "display-message" | "display" => {
+                let cmd = match args[1].as_str() { "lsw" => "list-windows", "lsp" => "list-panes", "display" => "display-message", c => c };
+                let resp = send_control_with_response(format!("{} {}\n", cmd, args[2..].join(" ")))?; print!("{}", resp); return Ok(());
+            }
             _ => {}
         }
     }
@@ -270,555 +448,420 @@ fn main() -> io::Result<()> {
         eprintln!("pmux: nested sessions are not allowed");
         return Ok(());
     }
+    if create_if_missing && !transport::session_alive(&session) {
+        spawn_server(&session, config_file.as_deref())?;
+    }
     env::set_var("PMUX_ACTIVE", "1");
     let mut stdout = io::stdout();
     enable_raw_mode()?;
-    execute!(stdout, EnterAlternateScreen, EnableBlinking, EnableMouseCapture)?;
-    apply_cursor_style(&mut stdout)?;
+    execute!(stdout, EnterAlternateScreen, EnableBlinking, EnableMouseCapture, EnableBracketedPaste)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
-    let result = if env::var("PMUX_REMOTE_ATTACH").ok().as_deref() == Some("1") { run_remote(&mut terminal) } else { run(&mut terminal) };
+    let result = run_remote(&mut terminal, &session);
     disable_raw_mode()?;
-    execute!(terminal.backend_mut(), DisableBlinking, DisableMouseCapture, LeaveAlternateScreen)?;
+    // leave the shell with its own cursor shape, not the last pane's
+    execute!(terminal.backend_mut(), DisableBlinking, DisableMouseCapture, DisableBracketedPaste, LeaveAlternateScreen, Print("\x1b[0 q"))?;
     terminal.show_cursor()?;
-    result
-}
-
-fn run_remote(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> io::Result<()> {
-    let name = env::var("PMUX_SESSION_NAME").unwrap_or_else(|_| "default".to_string());
-    let home = env::var("USERPROFILE").or_else(|_| env::var("HOME")).unwrap_or_default();
-    let path = format!("{}\\.pmux\\{}.port", home, name);
-    let port = std::fs::read_to_string(&path).ok().and_then(|s| s.trim().parse::<u16>().ok()).ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no session"))?;
-    let addr = format!("127.0.0.1:{}", port);
-    let mut stream = std::net::TcpStream::connect(addr.clone())?;
-    let _ = std::io::Write::write_all(&mut stream, b"client-attach\n");
-    let last_path = format!("{}\\.pmux\\last_session", home);
-    let _ = std::fs::write(&last_path, &name);
-    let mut quit = false;
-    let mut prefix_armed = false;
-    let mut renaming = false;
-    let mut rename_buf = String::new();
-    let mut pane_renaming = false;
-    let mut pane_title_buf = String::new();
-    let mut chooser = false;
-    let mut choices: Vec<(usize, usize)> = Vec::new();
-    let mut tree_chooser = false;
-    let mut tree_entries: Vec<(bool, usize, usize, String)> = Vec::new();
-    let mut tree_selected: usize = 0;
+    match result? {
+        ClientExit::Detached => println!("[detached (from session {})]", session),
+        ClientExit::SessionEnded => println!("[exited]"),
+    }
+    Ok(())
+}
+
+/// ` -c <dir>` for a control line, with the `-c` argument resolved against our own directory.
+fn cli_start_dir(args: &[String]) -> String {
+    let Some(dir) = args.iter().position(|a| a == "-c").and_then(|i| args.get(i + 1)) else { return String::new() };
+    let dir = env::current_dir().map(|cwd| cwd.join(dir)).unwrap_or_else(|_| PathBuf::from(dir));
+    format!(" -c {}", config::quote(&dir.display().to_string()))
+}
+
+/// Start `pmux server` for `name` in the background and wait until it accepts connections.
+fn spawn_server(name: &str, config_file: Option<&Path>) -> io::Result<()> {
+    let exe = std::env::current_exe().unwrap_or_else(|_| std::path::PathBuf::from("pmux"));
+    let mut cmd = std::process::Command::new(exe);
+    if let Some(f) = config_file { cmd.arg("-f").arg(f); }
+    cmd.arg("server").arg("-s").arg(name);
+    cmd.stdin(std::process::Stdio::null()).stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // keep the server alive when the terminal that started it goes away
+        cmd.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x0000_0008;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        cmd.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+    }
+    let _child = cmd.spawn().map_err(|e| io::Error::other(format!("failed to spawn server: {e}")))?;
+    let started = Instant::now();
+    while !transport::session_alive(name) {
+        if started.elapsed() > Duration::from_secs(5) { return Err(io::Error::other(format!("server for session {} did not start", name))); }
+        thread::sleep(Duration::from_millis(20));
+    }
+    Ok(())
+}
+
+/// How an attached client's connection to its session ended.
+enum ClientExit { Detached, SessionEnded }
+
+fn run_remote(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, name: &str) -> io::Result<ClientExit> {
+    let mut stream = transport::connect(name).map_err(|_| io::Error::other(format!("no session: {}", name)))?;
+    stream.write_all(format!("{}\n", protocol::FRAMED_HELLO).as_bytes())?;
+    let area = terminal.size()?;
+    protocol::write_msg(&mut stream, &ClientMsg::Hello { version: protocol::PROTOCOL_VERSION, cols: area.width, rows: area.height })?;
+    let mut reader = stream.try_clone()?;
+    match protocol::read_msg::<ServerMsg>(&mut reader)? {
+        Some(ServerMsg::Welcome { .. }) => {}
+        Some(ServerMsg::Message { text }) => return Err(io::Error::other(text)),
+        _ => return Err(io::Error::other("server closed the connection during handshake")),
+    }
+    if let Ok(last_path) = transport::last_session_path() { let _ = std::fs::write(last_path, name); }
+    let (srx_tx, srx) = mpsc::channel::<ServerMsg>();
+    thread::spawn(move || {
+        while let Ok(Some(msg)) = protocol::read_msg::<ServerMsg>(&mut reader) {
+            if srx_tx.send(msg).is_err() { break; }
+        }
+    });
+    let mut frame: Option<FrameJson> = None;
+    let mut screens: HashMap<usize, vt100::Parser> = HashMap::new();
+    let mut message: Option<(String, Instant)> = None;
+    let mut cursor_style: Option<u8> = None;
+    let mut outer_title: Option<String> = None;
+    // captured since the client started, until the `mouse` option says otherwise
+    let mut mouse = true;
+    let term = env::var("TERM").unwrap_or_default();
+    let colorterm = env::var("COLORTERM").unwrap_or_default();
+    let mut dirty = false;
     loop {
-        terminal.draw(|f| {
-            let area = f.size();
-            let chunks = Layout::default().direction(Direction::Vertical).constraints([Constraint::Min(1), Constraint::Length(1)].as_ref()).split(area);
-            // update server with client size
-            let mut cs = std::net::TcpStream::connect(addr.clone()).unwrap();
-            let _ = std::io::Write::write_all(&mut cs, format!("client-size {} {}\n", chunks[0].width, chunks[0].height).as_bytes());
-            // fetch layout json
-            let mut s = std::net::TcpStream::connect(addr.clone()).unwrap();
-            let _ = std::io::Write::write_all(&mut s, b"dump-layout\n");
-            let mut buf = String::new();
-            let _ = std::io::Read::read_to_string(&mut s, &mut buf);
-            let root: LayoutJson = serde_json::from_str(&buf).unwrap_or(LayoutJson::Leaf { id: 0, rows: 0, cols: 0, cursor_row: 0, cursor_col: 0, content: Vec::new() });
-
-            fn render_json(f: &mut Frame, node: &LayoutJson, area: Rect) {
-                match node {
-                    LayoutJson::Leaf { id: _, rows: _, cols: _, cursor_row, cursor_col, content } => {
-                        let pane_block = Block::default().borders(Borders::ALL);
-                        let inner = pane_block.inner(area);
-                        let mut lines: Vec<Line> = Vec::new();
-                        for r in 0..inner.height.min(content.len() as u16) {
-                            let mut spans: Vec<Span> = Vec::new();
-                            let row = &content[r as usize];
-                            for c in 0..inner.width.min(row.len() as u16) {
-                                let cell = &row[c as usize];
-                                let mut fg = map_color(&cell.fg);
-                                let mut bg = map_color(&cell.bg);
-                                if cell.inverse { std::mem::swap(&mut fg, &mut bg); }
-                                let mut style = Style::default().fg(fg).bg(bg);
-                                if cell.bold { style = style.add_modifier(Modifier::BOLD); }
-                                if cell.italic { style = style.add_modifier(Modifier::ITALIC); }
-                                if cell.underline { style = style.add_modifier(Modifier::UNDERLINED); }
-                                spans.push(Span::styled(cell.text.clone(), style));
-                            }
-                            lines.push(Line::from(spans));
-                        }
-                        f.render_widget(pane_block, area);
-                        f.render_widget(Clear, inner);
-                        let para = Paragraph::new(Text::from(lines)).wrap(Wrap { trim: false });
-                        f.render_widget(para, inner);
-                        let cy = inner.y + (*cursor_row).min(inner.height.saturating_sub(1));
-                        let cx = inner.x + (*cursor_col).min(inner.width.saturating_sub(1));
-                        f.set_cursor(cx, cy);
-                    }
-                    LayoutJson::Split { kind, sizes, children } => {
-                        let constraints: Vec<Constraint> = if sizes.len() == children.len() { sizes.iter().map(|p| Constraint::Percentage(*p)).collect() } else { vec![Constraint::Percentage((100 / children.len() as u16) as u16); children.len()] };
-                        let rects = if kind == "Horizontal" { Layout::default().direction(Direction::Horizontal).constraints(constraints).split(area) } else { Layout::default().direction(Direction::Vertical).constraints(constraints).split(area) };
-                        for (i, child) in children.iter().enumerate() { render_json(f, child, rects[i]); }
-                    }
+        loop {
+            match srx.try_recv() {
+                Ok(ServerMsg::Frame(f)) => { frame = Some(*f); dirty = true; }
+                Ok(ServerMsg::PaneUpdate { id, rows, cols, full, data }) => {
+                    if full { screens.insert(id, vt100::Parser::new(rows, cols, 0)); }
+                    if let Some(p) = screens.get_mut(&id) { p.process(data.as_bytes()); }
+                    dirty = true;
                 }
+                Ok(ServerMsg::PaneClosed { id }) => { screens.remove(&id); }
+                Ok(ServerMsg::Message { text }) => { message = Some((text, Instant::now())); dirty = true; }
+                Ok(ServerMsg::Detached) => return Ok(ClientExit::Detached),
+                Ok(ServerMsg::Exited) | Err(mpsc::TryRecvError::Disconnected) => return Ok(ClientExit::SessionEnded),
+                Ok(ServerMsg::Welcome { .. }) => {}
+                Err(mpsc::TryRecvError::Empty) => break,
             }
-
-            render_json(f, &root, chunks[0]);
-            if tree_chooser {
-                let overlay = Block::default().borders(Borders::ALL).title("choose-tree");
-                let oa = centered_rect(60, 30, chunks[0]);
-                f.render_widget(Clear, oa);
-                f.render_widget(&overlay, oa);
-                let mut lines: Vec<Line> = Vec::new();
-                for (i, (is_win, wid, pid, name)) in tree_entries.iter().enumerate() {
-                    let marker = if *is_win { format!("@{}", wid) } else { format!("%{}", pid) };
-                    let prefix = if *is_win { "".to_string() } else { "  ".to_string() };
-                    let line = if i == tree_selected { Line::from(Span::styled(format!("{}{} {}", prefix, marker, name), Style::default().bg(Color::Yellow).fg(Color::Black))) } else { Line::from(format!("{}{} {}", prefix, marker, name)) };
-                    lines.push(line);
-                }
-                let para = Paragraph::new(Text::from(lines));
-                f.render_widget(para, overlay.inner(oa));
+        }
+        if message.as_ref().is_some_and(|(_, at)| at.elapsed() > Duration::from_secs(3)) { message = None; dirty = true; }
+        if let (true, Some(frame)) = (dirty, frame.as_ref()) {
+            if frame.title.is_some() && frame.title != outer_title {
+                outer_title = frame.title.clone();
+                execute!(terminal.backend_mut(), crossterm::terminal::SetTitle(outer_title.as_deref().unwrap_or_default()))?;
+            }
+            if mouse != frame.mouse {
+                mouse = frame.mouse;
+                if mouse { execute!(terminal.backend_mut(), EnableMouseCapture)?; } else { execute!(terminal.backend_mut(), DisableMouseCapture)?; }
+            }
+            if cursor_style != Some(frame.cursor_style) {
+                execute!(terminal.backend_mut(), Print(format!("\x1b[{} q", frame.cursor_style)))?;
+                cursor_style = Some(frame.cursor_style);
             }
-            if chooser {
-                let mut rects: Vec<(usize, Rect)> = Vec::new();
-                fn rec(node: &LayoutJson, area: Rect, out: &mut Vec<(usize, Rect)>) {
-                    match node {
-                        LayoutJson::Leaf { id, .. } => { out.push((*id, area)); }
-                        LayoutJson::Split { kind, sizes, children } => {
-                            let constraints: Vec<Constraint> = if sizes.len() == children.len() { sizes.iter().map(|p| Constraint::Percentage(*p)).collect() } else { vec![Constraint::Percentage((100 / children.len() as u16) as u16); children.len()] };
-                            let rects = if kind == "Horizontal" { Layout::default().direction(Direction::Horizontal).constraints(constraints).split(area) } else { Layout::default().direction(Direction::Vertical).constraints(constraints).split(area) };
-                            for (i, child) in children.iter().enumerate() { rec(child, rects[i], out); }
+            let msg = message.as_ref().map(|(t, _)| t.as_str());
+            let depth = colors::detect(&term, &colorterm, &frame.terminal_overrides);
+            terminal.draw(|f| render_frame(f, frame, &screens, msg, depth))?;
+            dirty = false;
+        }
+        if event::poll(Duration::from_millis(10))? {
+            let msg = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    let (mods, code) = key_to_wire(&key);
+                    Some(ClientMsg::Key { mods, code })
+                }
+                Event::Mouse(me) => mouse_to_wire(&me).map(|kind| ClientMsg::Mouse { kind, mods: me.modifiers.bits(), x: me.column, y: me.row }),
+                Event::Paste(text) => Some(ClientMsg::Paste { text }),
+                Event::Resize(cols, rows) => { dirty = true; Some(ClientMsg::Resize { cols, rows }) }
+                _ => None,
+            };
+            // a failed write means the server went away; the reader reports how
+            if let Some(msg) = msg { let _ = protocol::write_msg(&mut stream, &msg); }
+        }
+    }
+}
+
+fn render_frame(f: &mut Frame, frame: &FrameJson, screens: &HashMap<usize, vt100::Parser>, message: Option<&str>, depth: ColorDepth) {
+    let area = f.size();
+    let lines = (frame.status.len() as u16).min(area.height);
+    let (main, status_area) = if frame.status_top {
+        let chunks = Layout::default().direction(Direction::Vertical).constraints([Constraint::Length(lines), Constraint::Min(1)]).split(area);
+        (chunks[1], chunks[0])
+    } else {
+        let chunks = Layout::default().direction(Direction::Vertical).constraints([Constraint::Min(1), Constraint::Length(lines)]).split(area);
+        (chunks[0], chunks[1])
+    };
+
+    fn render_json(f: &mut Frame, node: &LayoutJson, area: Rect, active: usize, screens: &HashMap<usize, vt100::Parser>, copy: Option<(usize, usize)>, depth: ColorDepth) {
+        match node {
+            LayoutJson::Leaf { id, synchronized, .. } => {
+                let is_active = *id == active;
+                let title = match (is_active, *synchronized) {
+                    (true, true) => "* pane [sync]",
+                    (true, false) => "* pane",
+                    (false, true) => " pane [sync]",
+                    (false, false) => " pane",
+                };
+                let mut pane_block = Block::default().borders(Borders::ALL).title(title);
+                if *synchronized { pane_block = pane_block.border_style(Style::default().fg(Color::Yellow)); }
+                let inner = pane_block.inner(area);
+                f.render_widget(pane_block, area);
+                f.render_widget(Clear, inner);
+                let Some(parser) = screens.get(id) else { return };
+                let screen = parser.screen();
+                let mut lines: Vec<Line> = Vec::with_capacity(inner.height as usize);
+                for r in 0..inner.height {
+                    let mut spans: Vec<Span> = Vec::with_capacity(inner.width as usize);
+                    let mut c = 0;
+                    while c < inner.width {
+                        let Some(cell) = screen.cell(r, c) else { spans.push(Span::raw(" ")); c += 1; continue };
+                        let mut fg = colors::downgrade(vt_to_color(cell.fgcolor()), depth);
+                        let mut bg = colors::downgrade(vt_to_color(cell.bgcolor()), depth);
+                        if cell.inverse() { std::mem::swap(&mut fg, &mut bg); }
+                        let mut style = Style::default().fg(fg).bg(bg);
+                        if cell.bold() { style = style.add_modifier(Modifier::BOLD); }
+                        if cell.italic() { style = style.add_modifier(Modifier::ITALIC); }
+                        if cell.underline() { style = style.add_modifier(Modifier::UNDERLINED); }
+                        let text = cell.contents();
+                        let w = UnicodeWidthStr::width(text) as u16;
+                        if w == 0 {
+                            spans.push(Span::styled(" ", style));
+                            c += 1;
+                        } else {
+                            spans.push(Span::styled(text.to_string(), style));
+                            // a wide character covers the continuation cell after it
+                            c += w.min(2);
                         }
                     }
+                    lines.push(Line::from(spans));
+                }
+                let para = Paragraph::new(Text::from(lines));
+                f.render_widget(para, inner);
+                if let (true, Some((pos, total))) = (is_active, copy) {
+                    let label = format!("[{}/{}]", pos, total);
+                    let w = (label.len() as u16).min(inner.width);
+                    let r = Rect { x: inner.x + inner.width - w, y: inner.y, width: w, height: 1 };
+                    f.render_widget(Paragraph::new(label).style(Style::default().bg(Color::Yellow).fg(Color::Black)), r);
+                }
+                if is_active && !screen.hide_cursor() {
+                    let (cr, cc) = screen.cursor_position();
+                    let cy = inner.y + cr.min(inner.height.saturating_sub(1));
+                    let cx = inner.x + cc.min(inner.width.saturating_sub(1));
+                    f.set_cursor(cx, cy);
                 }
-                rec(&root, chunks[0], &mut rects);
-                choices.clear();
-                for (i,(pid,r)) in rects.iter().enumerate() { if i<10 { choices.push((i+1,*pid)); let bw=7u16; let bh=3u16; let bx=r.x + r.width.saturating_sub(bw)/2; let by=r.y + r.height.saturating_sub(bh)/2; let b=Rect{ x:bx, y:by, width:bw, height:bh }; let block=Block::default().borders(Borders::ALL).style(Style::default().bg(Color::Yellow).fg(Color::Black)); let inner=block.inner(b); let disp=if i+1==10 {0} else {i+1}; let line=Line::from(Span::styled(format!(" {} ",disp), Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD))); let para=Paragraph::new(line).alignment(Alignment::Center); f.render_widget(Clear,b); f.render_widget(block,b); f.render_widget(para,inner);} }
             }
-            let status_bar = Paragraph::new(Line::from(vec![Span::raw(format!("session:{}", name))])).style(Style::default().bg(Color::Green).fg(Color::Black));
-            f.render_widget(Clear, chunks[1]);
-            f.render_widget(status_bar, chunks[1]);
-            if pane_renaming {
-                let overlay = Block::default().borders(Borders::ALL).title("set pane title");
-                let oa = centered_rect(60, 3, chunks[0]);
-                f.render_widget(Clear, oa);
-                f.render_widget(&overlay, oa);
-                let para = Paragraph::new(format!("title: {}", pane_title_buf));
-                f.render_widget(para, overlay.inner(oa));
+            LayoutJson::Split { kind, sizes, children } => {
+                let rects = split_json_rects(kind, sizes, children.len(), area);
+                for (i, child) in children.iter().enumerate() { render_json(f, child, rects[i], active, screens, copy, depth); }
             }
-        })?;
-        if event::poll(Duration::from_millis(50))? {
-            match event::read()? { Event::Key(key) if key.kind == KeyEventKind::Press => {
-                if matches!(key.code, KeyCode::Char('q')) && key.modifiers.contains(KeyModifiers::CONTROL) { quit = true; }
-                else if matches!(key.code, KeyCode::Char('b')) && key.modifiers.contains(KeyModifiers::CONTROL) { prefix_armed = true; }
-                else if prefix_armed {
-                    // tmux-like prefix mappings
-                    match key.code {
-                        KeyCode::Char('c') => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"new-window\n"); }
-                        KeyCode::Char('%') => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"split-window -h\n"); }
-                        KeyCode::Char('"') => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"split-window -v\n"); }
-                        KeyCode::Char('x') => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"kill-pane\n"); }
-                        KeyCode::Char('z') => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"zoom-pane\n"); }
-                        KeyCode::Char('[') => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"copy-enter\n"); }
-                        KeyCode::Char('n') => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"next-window\n"); }
-                        KeyCode::Char('p') => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"previous-window\n"); }
-                        KeyCode::Char(',') => { renaming = true; rename_buf.clear(); }
-                        KeyCode::Char('t') => { pane_renaming = true; pane_title_buf.clear(); }
-                        KeyCode::Char('w') => { tree_chooser = true; tree_entries.clear(); tree_selected = 0; let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"list-tree\n"); let mut buf = String::new(); let _ = std::io::Read::read_to_string(&mut s, &mut buf); let infos: Vec<WinTree> = serde_json::from_str(&buf).unwrap_or(Vec::new()); for wi in infos.into_iter() { tree_entries.push((true, wi.id, 0, wi.name)); for pi in wi.panes.into_iter() { tree_entries.push((false, wi.id, pi.id, pi.title)); } } }
-                        KeyCode::Char('s') => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"toggle-sync\n"); }
-                        KeyCode::Char('q') => { chooser = true; }
-                        KeyCode::Left => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"copy-move -1 0\n"); }
-                        KeyCode::Right => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"copy-move 1 0\n"); }
-                        KeyCode::Up => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"copy-move 0 -1\n"); }
-                        KeyCode::Down => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"copy-move 0 1\n"); }
-                        KeyCode::Char('v') => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"copy-anchor\n"); }
-                        KeyCode::Char('y') => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"copy-yank\n"); }
-                        _ => {}
-                    }
-                    prefix_armed = false;
-                } else {
-                    match key.code {
-                        KeyCode::Up if tree_chooser => { if tree_selected>0 { tree_selected-=1; } }
-                        KeyCode::Down if tree_chooser => { if tree_selected+1 < tree_entries.len() { tree_selected+=1; } }
-                        KeyCode::Enter if tree_chooser => { if let Some((is_win, wid, pid, _)) = tree_entries.get(tree_selected) { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); if *is_win { let _ = std::io::Write::write_all(&mut s, format!("focus-window {}\n", wid).as_bytes()); } else { let _ = std::io::Write::write_all(&mut s, format!("focus-pane {}\n", pid).as_bytes()); } tree_chooser=false; } }
-                        KeyCode::Esc if tree_chooser => { tree_chooser=false; }
-                        KeyCode::Char(c) if renaming && !key.modifiers.contains(KeyModifiers::CONTROL) => { rename_buf.push(c); }
-                        KeyCode::Char(c) if pane_renaming && !key.modifiers.contains(KeyModifiers::CONTROL) => { pane_title_buf.push(c); }
-                        KeyCode::Backspace if renaming => { let _ = rename_buf.pop(); }
-                        KeyCode::Backspace if pane_renaming => { let _ = pane_title_buf.pop(); }
-                        KeyCode::Enter if renaming => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, format!("rename-window {}\n", rename_buf).as_bytes()); renaming=false; }
-                        KeyCode::Enter if pane_renaming => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, format!("set-pane-title {}\n", pane_title_buf).as_bytes()); pane_renaming=false; }
-                        KeyCode::Esc if renaming => { renaming=false; }
-                        KeyCode::Esc if pane_renaming => { pane_renaming=false; }
-                        KeyCode::Char(d) if chooser && d.is_ascii_digit() => {
-                            let raw = d.to_digit(10).unwrap() as usize;
-                            let choice = if raw==0 {10} else {raw};
-                            if let Some((_,pid)) = choices.iter().find(|(n,_)| *n==choice) { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, format!("focus-pane {}\n", pid).as_bytes()); chooser=false; }
-                        }
-                        KeyCode::Esc if chooser => { chooser=false; }
-                        KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
-                            let mut s = std::net::TcpStream::connect(addr.clone()).unwrap();
-                            let _ = std::io::Write::write_all(&mut s, format!("send-text {}\n", c).as_bytes());
-                        }
-                        KeyCode::Enter => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"send-key enter\n"); }
-                        KeyCode::Tab => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"send-key tab\n"); }
-                        KeyCode::Backspace => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"send-key backspace\n"); }
-                        KeyCode::Esc => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"send-key esc\n"); }
-                        KeyCode::Left => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"send-key left\n"); }
-                        KeyCode::Right => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"send-key right\n"); }
-                        KeyCode::Up => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"send-key up\n"); }
-                        KeyCode::Down => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"send-key down\n"); }
-                        _ => {}
+        }
+    }
+
+    render_json(f, &frame.layout, main, frame.active_pane, screens, frame.copy_position, depth);
+    match &frame.overlay {
+        Some(OverlayJson::Prompt { title, text }) => {
+            let overlay = Paragraph::new(text.clone()).block(Block::default().borders(Borders::ALL).title(title.clone()));
+            let oa = centered_rect(80, 3, main);
+            f.render_widget(Clear, oa);
+            f.render_widget(overlay, oa);
+        }
+        Some(OverlayJson::List { title, items, selected }) => {
+            let overlay = Block::default().borders(Borders::ALL).title(title.clone());
+            let oa = centered_rect(60, (items.len() as u16 + 2).min(main.height), main);
+            f.render_widget(Clear, oa);
+            f.render_widget(&overlay, oa);
+            let mut lines: Vec<Line> = Vec::new();
+            for (i, item) in items.iter().enumerate() {
+                let line = if i == *selected { Line::from(Span::styled(item.clone(), Style::default().bg(Color::Yellow).fg(Color::Black))) } else { Line::from(item.clone()) };
+                lines.push(line);
+            }
+            let para = Paragraph::new(Text::from(lines));
+            f.render_widget(para, overlay.inner(oa));
+        }
+        Some(OverlayJson::PaneNumbers) => {
+            let mut rects: Vec<(usize, Rect)> = Vec::new();
+            fn rec(node: &LayoutJson, area: Rect, out: &mut Vec<(usize, Rect)>) {
+                match node {
+                    LayoutJson::Leaf { id, .. } => { out.push((*id, area)); }
+                    LayoutJson::Split { kind, sizes, children } => {
+                        let rects = split_json_rects(kind, sizes, children.len(), area);
+                        for (i, child) in children.iter().enumerate() { rec(child, rects[i], out); }
                     }
                 }
-            } Event::Mouse(me) => {
-                use crossterm::event::{MouseEventKind,MouseButton};
-                match me.kind {
-                    MouseEventKind::Down(MouseButton::Left) => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, format!("mouse-down {} {}\n", me.column, me.row).as_bytes()); }
-                    MouseEventKind::Drag(MouseButton::Left) => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, format!("mouse-drag {} {}\n", me.column, me.row).as_bytes()); }
-                    MouseEventKind::Up(MouseButton::Left) => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, format!("mouse-up {} {}\n", me.column, me.row).as_bytes()); }
-                    MouseEventKind::ScrollUp => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"scroll-up\n"); }
-                    MouseEventKind::ScrollDown => { let mut s = std::net::TcpStream::connect(addr.clone()).unwrap(); let _ = std::io::Write::write_all(&mut s, b"scroll-down\n"); }
-                    _ => {}
-                }
-            } _ => {} }
+            }
+            rec(&frame.layout, main, &mut rects);
+            for (i, (_, r)) in rects.iter().enumerate() {
+                let n = i + 1;
+                if n > 10 { break; }
+                let bw = 7u16;
+                let bh = 3u16;
+                let bx = r.x + r.width.saturating_sub(bw) / 2;
+                let by = r.y + r.height.saturating_sub(bh) / 2;
+                let b = Rect { x: bx, y: by, width: bw, height: bh };
+                let block = Block::default().borders(Borders::ALL).style(Style::default().bg(Color::Yellow).fg(Color::Black));
+                let inner = block.inner(b);
+                let disp = if n == 10 { 0 } else { n };
+                let line = Line::from(Span::styled(format!(" {} ", disp), Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD)));
+                let para = Paragraph::new(line).alignment(Alignment::Center);
+                f.render_widget(Clear, b);
+                f.render_widget(block, b);
+                f.render_widget(para, inner);
+            }
         }
-        if reap_children_placeholder()? { /* no-op */ }
-        if quit { break; }
+        None => {}
     }
-    let _ = std::net::TcpStream::connect(addr).and_then(|mut s| { std::io::Write::write_all(&mut s, b"client-detach\n"); Ok(()) });
-    Ok(())
+    f.render_widget(Clear, status_area);
+    for (i, line) in frame.status.iter().enumerate().take(status_area.height as usize) {
+        let r = Rect { y: status_area.y + i as u16, height: 1, ..status_area };
+        f.render_widget(Paragraph::new(status_line(line, r.width, depth)).style(styled_json_style(&line.style, depth)), r);
+    }
+    if let Some(text) = message {
+        // over the first status line, or the bottom row when the status bar is off
+        let y = if lines > 0 { status_area.y } else { main.bottom().saturating_sub(1) };
+        let r = Rect { x: area.x, y, width: area.width, height: 1 };
+        f.render_widget(Paragraph::new(text.to_string()).style(Style::default().bg(Color::Yellow).fg(Color::Black)), r);
+    }
+}
+
+/// Lay one status line out across `width` columns: the left region, the window
+/// list where `status-justify` puts it, and the right region against the edge.
+fn status_line(line: &StatusLineJson, width: u16, depth: ColorDepth) -> Line<'static> {
+    let base = styled_json_style(&line.style, depth);
+    let spans = |v: &[StyledJson]| -> Vec<Span<'static>> { v.iter().map(|s| Span::styled(s.text.clone(), base.patch(styled_json_style(s, depth)))).collect() };
+    let width = width as usize;
+    let (left, windows, right) = (spans(&line.left), spans(&line.windows), spans(&line.right));
+    let right_w = spans_width(&right).min(width);
+    let left_w = spans_width(&left).min(width - right_w);
+    let list_w = spans_width(&windows);
+    let free = width - left_w - right_w;
+    let list_start = match line.justify.as_str() {
+        "centre" | "center" => left_w + free.saturating_sub(list_w) / 2,
+        "right" => left_w + free.saturating_sub(list_w),
+        "absolute-centre" => (width.saturating_sub(list_w) / 2).clamp(left_w, left_w + free.saturating_sub(list_w)),
+        _ => left_w,
+    };
+    let mut out = clip_spans(left, left_w);
+    out.push(Span::styled(" ".repeat(list_start - left_w), base));
+    let list = clip_spans(windows, left_w + free - list_start);
+    let gap = width - right_w - list_start - spans_width(&list);
+    out.extend(list);
+    out.push(Span::styled(" ".repeat(gap), base));
+    out.extend(clip_spans(right, right_w));
+    Line::from(out)
+}
+
+fn spans_width(spans: &[Span]) -> usize {
+    spans.iter().map(|s| UnicodeWidthStr::width(s.content.as_ref())).sum()
+}
+
+/// The leading `width` columns of `spans`.
+fn clip_spans(spans: Vec<Span<'static>>, width: usize) -> Vec<Span<'static>> {
+    let mut left = width;
+    let mut out = Vec::new();
+    for s in spans {
+        let w = UnicodeWidthStr::width(s.content.as_ref());
+        if w <= left { left -= w; out.push(s); continue; }
+        let mut text = String::new();
+        for c in s.content.chars() {
+            let cw = unicode_width::UnicodeWidthChar::width(c).unwrap_or(0);
+            if cw > left { break; }
+            left -= cw;
+            text.push(c);
+        }
+        out.push(Span::styled(text, s.style));
+        break;
+    }
+    out
 }
 
-fn reap_children_placeholder() -> io::Result<bool> { Ok(false) }
+fn split_json_rects(kind: &str, sizes: &[u16], n: usize, area: Rect) -> std::rc::Rc<[Rect]> {
+    let constraints: Vec<Constraint> = if sizes.len() == n { sizes.iter().map(|p| Constraint::Percentage(*p)).collect() } else { vec![Constraint::Percentage(100 / n as u16); n] };
+    if kind == "Horizontal" { Layout::default().direction(Direction::Horizontal).constraints(constraints).split(area) } else { Layout::default().direction(Direction::Vertical).constraints(constraints).split(area) }
+}
 
 fn send_control(line: String) -> io::Result<()> {
-    let home = env::var("USERPROFILE").or_else(|_| env::var("HOME")).unwrap_or_default();
     let target = env::var("PMUX_TARGET_SESSION").ok().unwrap_or_else(|| "default".to_string());
-    let path = format!("{}\\.pmux\\{}.port", home, target);
-    let port = std::fs::read_to_string(&path).ok().and_then(|s| s.trim().parse::<u16>().ok()).ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no session"))?;
-    let addr = format!("127.0.0.1:{}", port);
-    let mut stream = std::net::TcpStream::connect(addr)?;
+    let mut stream = transport::connect(&target).map_err(|_| io::Error::other(format!("no session: {}", target)))?;
     let _ = write!(stream, "{}", line);
     Ok(())
 }
 
 fn send_control_with_response(line: String) -> io::Result<String> {
-    let home = env::var("USERPROFILE").or_else(|_| env::var("HOME")).unwrap_or_default();
     let target = env::var("PMUX_TARGET_SESSION").ok().unwrap_or_else(|| "default".to_string());
-    let path = format!("{}\\.pmux\\{}.port", home, target);
-    let port = std::fs::read_to_string(&path).ok().and_then(|s| s.trim().parse::<u16>().ok()).ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no session"))?;
-    let addr = format!("127.0.0.1:{}", port);
-    let mut stream = std::net::TcpStream::connect(addr)?;
+    let mut stream = transport::connect(&target).map_err(|_| io::Error::other(format!("no session: {}", target)))?;
     let _ = write!(stream, "{}", line);
     let mut buf = String::new();
     let _ = std::io::Read::read_to_string(&mut stream, &mut buf);
     Ok(buf)
 }
 
-fn run(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> io::Result<()> {
-    let pty_system = PtySystemSelection::default()
-        .get()
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("pty system error: {e}")))?;
-
-    let mut app = AppState {
+fn new_app_state(session_name: String) -> AppState {
+    // the cursor variables predate the options and still give their defaults
+    let mut global_options = options::Options::default();
+    if let Ok(style) = env::var("PMUX_CURSOR_STYLE") { global_options.set("cursor-style", style); }
+    if let Ok(blink) = env::var("PMUX_CURSOR_BLINK") { global_options.set("cursor-blink", if matches!(blink.as_str(), "0" | "false" | "off") { "off" } else { "on" }.to_string()); }
+    AppState {
         windows: Vec::new(),
         active_idx: 0,
         mode: Mode::Passthrough,
-        escape_time_ms: 500,
-        prefix_key: (KeyCode::Char('b'), KeyModifiers::CONTROL),
         drag: None,
+        mouse_grab: None,
         last_window_area: Rect { x: 0, y: 0, width: 0, height: 0 },
-        mouse_enabled: true,
         paste_buffers: Vec::new(),
-        status_left: "pmux:#I".to_string(),
-        status_right: "%H:%M".to_string(),
+        jobs: RefCell::new(jobs::JobCache::default()),
+        client_size: (0, 0),
+        last_rename_check: Instant::now(),
         copy_anchor: None,
         copy_pos: None,
         display_map: Vec::new(),
-        binds: Vec::new(),
+        key_tables: bindings::KeyTables::with_defaults(),
+        key_table: None,
+        detach: false,
         control_rx: None,
         control_port: None,
-        session_name: env::var("PMUX_SESSION_NAME").unwrap_or_else(|_| "default".to_string()),
-        attached_clients: 1,
+        session_name,
+        attached_clients: 0,
         created_at: Local::now(),
         next_win_id: 1,
         next_pane_id: 1,
         zoom_saved: None,
-        sync_input: false,
-    };
-
-    load_config(&mut app);
-
-    create_window(&*pty_system, &mut app)?;
-
-    let (tx, rx) = mpsc::channel::<CtrlReq>();
-    app.control_rx = Some(rx);
-    let listener = TcpListener::bind(("127.0.0.1", 0))?;
-    let port = listener.local_addr()?.port();
-    app.control_port = Some(port);
-    let home = env::var("USERPROFILE").or_else(|_| env::var("HOME")).unwrap_or_default();
-    let dir = format!("{}\\.pmux", home);
-    let _ = std::fs::create_dir_all(&dir);
-    let regpath = format!("{}\\{}.port", dir, app.session_name);
-    let _ = std::fs::write(&regpath, port.to_string());
-    thread::spawn(move || {
-        for conn in listener.incoming() {
-            if let Ok(mut stream) = conn {
-                let mut line = String::new();
-                let mut r = io::BufReader::new(stream.try_clone().unwrap());
-                let _ = r.read_line(&mut line);
-                let mut parts = line.split_whitespace();
-                let cmd = parts.next().unwrap_or("");
-                // parse optional target specifier
-                let mut args: Vec<&str> = parts.by_ref().collect();
-                let mut target_win: Option<usize> = None;
-                let mut target_pane: Option<usize> = None;
-                let mut start_line: Option<u16> = None;
-                let mut end_line: Option<u16> = None;
-                let mut i = 0;
-                while i < args.len() {
-                    if args[i] == "-t" {
-                        if let Some(v) = args.get(i+1) {
-                            if v.starts_with('%') { if let Ok(pid) = v[1..].parse::<usize>() { target_pane = Some(pid); } }
-                            else if v.starts_with('@') { if let Ok(wid) = v[1..].parse::<usize>() { target_win = Some(wid); } }
-                        }
-                        i += 2; continue;
-                    } else if args[i] == "-S" {
-                        if let Some(v) = args.get(i+1) { if let Ok(n) = v.parse::<u16>() { start_line = Some(n); } }
-                        i += 2; continue;
-                    } else if args[i] == "-E" {
-                        if let Some(v) = args.get(i+1) { if let Ok(n) = v.parse::<u16>() { end_line = Some(n); } }
-                        i += 2; continue;
-                    }
-                    i += 1;
-                }
-                if let Some(wid) = target_win { let _ = tx.send(CtrlReq::FocusWindow(wid)); }
-                if let Some(pid) = target_pane { let _ = tx.send(CtrlReq::FocusPane(pid)); }
-                match cmd {
-                    "new-window" => { let _ = tx.send(CtrlReq::NewWindow); }
-                    "split-window" => {
-                        let kind = if args.iter().any(|a| *a == "-h") { LayoutKind::Horizontal } else { LayoutKind::Vertical };
-                        let _ = tx.send(CtrlReq::SplitWindow(kind));
-                    }
-                    "kill-pane" => { let _ = tx.send(CtrlReq::KillPane); }
-                    "capture-pane" => {
-                        let (rtx, rrx) = mpsc::channel::<String>();
-                        if start_line.is_some() || end_line.is_some() { let _ = tx.send(CtrlReq::CapturePaneRange(rtx, start_line, end_line)); }
-                        else { let _ = tx.send(CtrlReq::CapturePane(rtx)); }
-                        if let Ok(text) = rrx.recv() { let _ = write!(stream, "{}", text); }
-                    }
-                    "client-attach" => { let _ = tx.send(CtrlReq::ClientAttach); let _ = write!(stream, "ok\n"); }
-                    "client-detach" => { let _ = tx.send(CtrlReq::ClientDetach); let _ = write!(stream, "ok\n"); }
-                    "session-info" => {
-                        let (rtx, rrx) = mpsc::channel::<String>();
-                        let _ = tx.send(CtrlReq::SessionInfo(rtx));
-                        if let Ok(line) = rrx.recv() { let _ = write!(stream, "{}", line); let _ = stream.flush(); }
-                    }
-                    _ => {}
-                }
-            }
-        }
-    });
-
-    let mut last_resize = Instant::now();
-    let mut quit = false;
-    loop {
-        terminal.draw(|f| {
-            let area = f.size();
-            let chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints([Constraint::Min(1), Constraint::Length(1)].as_ref())
-                .split(area);
-
-            app.last_window_area = chunks[0];
-            render_window(f, &mut app, chunks[0]);
-
-            let _mode_str = match app.mode { Mode::Passthrough => "", Mode::Prefix { .. } => "PREFIX", Mode::CommandPrompt { .. } => ":", Mode::WindowChooser { .. } => "W", Mode::RenamePrompt { .. } => "REN", Mode::CopyMode => "CPY", Mode::PaneChooser { .. } => "PANE" };
-            let time_str = Local::now().format("%H:%M").to_string();
-            let mut windows_list = String::new();
-            for (i, _) in app.windows.iter().enumerate() {
-                if i == app.active_idx { windows_list.push_str(&format!(" #[{}]", i+1)); } else { windows_list.push_str(&format!(" {}", i+1)); }
-            }
-            let status_spans = parse_status(&app.status_left, &app, &time_str);
-            let mut right_spans = parse_status(&app.status_right, &app, &time_str);
-            let mut combined: Vec<Span<'static>> = status_spans;
-            combined.push(Span::raw(" "));
-            combined.append(&mut right_spans);
-            let status_bar = Paragraph::new(Line::from(combined)).style(Style::default().bg(Color::Green).fg(Color::Black));
-            f.render_widget(Clear, chunks[1]);
-            f.render_widget(status_bar, chunks[1]);
-
-            if let Mode::CommandPrompt { input } = &app.mode {
-                let overlay = Paragraph::new(format!(":{}", input)).block(Block::default().borders(Borders::ALL).title("command"));
-                let oa = centered_rect(80, 3, area);
-                f.render_widget(Clear, oa);
-                f.render_widget(overlay, oa);
-            }
-
-            if let Mode::WindowChooser { selected } = app.mode {
-                let mut lines: Vec<Line> = Vec::new();
-                for (i,w) in app.windows.iter().enumerate() {
-                    let marker = if i == selected { ">" } else { " " };
-                    lines.push(Line::from(format!("{} [{}] {}", marker, i+1, w.name)));
-                }
-                let overlay = Paragraph::new(Text::from(lines)).block(Block::default().borders(Borders::ALL).title("windows"));
-                let oa = centered_rect(60, (app.windows.len() as u16 + 2).min(10), area);
-                f.render_widget(Clear, oa);
-                f.render_widget(overlay, oa);
-            }
-
-            if let Mode::RenamePrompt { input } = &app.mode {
-                let overlay = Paragraph::new(format!("rename: {}", input)).block(Block::default().borders(Borders::ALL).title("rename window"));
-                let oa = centered_rect(60, 3, area);
-                f.render_widget(Clear, oa);
-                f.render_widget(overlay, oa);
-            }
-
-            if let Mode::PaneChooser { .. } = &app.mode {
-                let win = &app.windows[app.active_idx];
-                let mut rects: Vec<(Vec<usize>, Rect)> = Vec::new();
-                compute_rects(&win.root, app.last_window_area, &mut rects);
-                for (i, (_, r)) in rects.iter().enumerate() {
-                    let n = i + 1;
-                    if n > 9 { break; }
-                    let bw = 7u16;
-                    let bh = 3u16;
-                    let bx = r.x + r.width.saturating_sub(bw) / 2;
-                    let by = r.y + r.height.saturating_sub(bh) / 2;
-                    let b = Rect { x: bx, y: by, width: bw, height: bh };
-                    let block = Block::default().borders(Borders::ALL).style(Style::default().bg(Color::Yellow).fg(Color::Black));
-                    let inner = block.inner(b);
-                    let disp = if n == 10 { 0 } else { n };
-                    let line = Line::from(Span::styled(format!(" {} ", disp), Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD)));
-                    let para = Paragraph::new(line).alignment(Alignment::Center);
-                    f.render_widget(Clear, b);
-                    f.render_widget(block, b);
-                    f.render_widget(para, inner);
-                }
-            }
-        })?;
-
-        if let Mode::PaneChooser { opened_at } = &app.mode {
-            if opened_at.elapsed() > Duration::from_millis(1500) { app.mode = Mode::Passthrough; }
-        }
-
-        if event::poll(Duration::from_millis(20))? {
-            match event::read()? {
-                Event::Key(key) if key.kind == KeyEventKind::Press => {
-                    if handle_key(&mut app, key)? {
-                        quit = true;
-                    }
-                }
-                Event::Mouse(me) => {
-                    let area = app.last_window_area;
-                    handle_mouse(&mut app, me, area)?;
-                }
-                Event::Resize(cols, rows) => {
-                    if last_resize.elapsed() > Duration::from_millis(50) {
-                        let win = &mut app.windows[app.active_idx];
-                        if let Some(pane) = active_pane_mut(&mut win.root, &win.active_path) {
-                            let _ = pane.master.resize(PtySize { rows: rows as u16, cols: cols as u16, pixel_width: 0, pixel_height: 0 });
-                            let mut parser = pane.term.lock().unwrap();
-                            parser.screen_mut().set_size(rows, cols);
-                        }
-                        last_resize = Instant::now();
-                    }
-                }
-                _ => {}
-            }
-        }
-
-        loop {
-            let req = if let Some(rx) = app.control_rx.as_ref() { rx.try_recv().ok() } else { None };
-            let Some(req) = req else { break; };
-            match req {
-                CtrlReq::NewWindow => {
-                    let pty_system = PtySystemSelection::default().get().map_err(|e| io::Error::new(io::ErrorKind::Other, format!("pty system error: {e}")))?;
-                    create_window(&*pty_system, &mut app)?;
-                }
-                CtrlReq::SplitWindow(k) => { let _ = split_active(&mut app, k); }
-                CtrlReq::KillPane => { let _ = kill_active_pane(&mut app); }
-                CtrlReq::CapturePane(resp) => {
-                    if let Some(text) = capture_active_pane_text(&mut app)? { let _ = resp.send(text); } else { let _ = resp.send(String::new()); }
-                }
-                CtrlReq::CapturePaneRange(resp, s, e) => {
-                    if let Some(text) = capture_active_pane_range(&mut app, s, e)? { let _ = resp.send(text); } else { let _ = resp.send(String::new()); }
-                }
-                CtrlReq::FocusWindow(wid) => { if let Some(idx) = find_window_index_by_id(&app, wid) { app.active_idx = idx; } }
-                CtrlReq::FocusPane(pid) => { focus_pane_by_id(&mut app, pid); }
-                CtrlReq::SessionInfo(resp) => {
-                    let attached = if app.attached_clients > 0 { "(attached)" } else { "(detached)" };
-                    let windows = app.windows.len();
-                    let (w,h) = {
-                        let win = &mut app.windows[app.active_idx];
-                        let mut size = (0,0);
-                        if let Some(p) = active_pane_mut(&mut win.root, &win.active_path) { size = (p.last_cols as i32, p.last_rows as i32); }
-                        size
-                    };
-                    let created = app.created_at.format("%a %b %e %H:%M:%S %Y");
-                    let line = format!("{}: {} windows (created {}) [{}x{}] {}\n", app.session_name, windows, created, w, h, attached);
-                    let _ = resp.send(line);
-                }
-                CtrlReq::ClientAttach => { app.attached_clients = app.attached_clients.saturating_add(1); }
-                CtrlReq::ClientDetach => { app.attached_clients = app.attached_clients.saturating_sub(1); }
-                CtrlReq::DumpLayout(resp) => {
-                    let json = dump_layout_json(&mut app)?;
-                    let _ = resp.send(json);
-                }
-                CtrlReq::SendText(s) => { send_text_to_active(&mut app, &s)?; }
-                CtrlReq::SendKey(k) => { send_key_to_active(&mut app, &k)?; }
-                CtrlReq::ZoomPane => { toggle_zoom(&mut app); }
-                CtrlReq::CopyEnter => { enter_copy_mode(&mut app); }
-                CtrlReq::CopyMove(dx, dy) => { move_copy_cursor(&mut app, dx, dy); }
-                CtrlReq::CopyAnchor => { if let Some((r,c)) = current_prompt_pos(&mut app) { app.copy_anchor = Some((r,c)); app.copy_pos = Some((r,c)); } }
-                CtrlReq::CopyYank => { let _ = yank_selection(&mut app); app.mode = Mode::Passthrough; }
-                CtrlReq::ClientSize(w, h) => { app.last_window_area = Rect { x: 0, y: 0, width: w, height: h }; }
-                CtrlReq::FocusPaneCmd(pid) => { focus_pane_by_id(&mut app, pid); }
-                CtrlReq::FocusWindowCmd(wid) => { if let Some(idx) = find_window_index_by_id(&app, wid) { app.active_idx = idx; } }
-                CtrlReq::MouseDown(x,y) => { remote_mouse_down(&mut app, x, y); }
-                CtrlReq::MouseDrag(x,y) => { remote_mouse_drag(&mut app, x, y); }
-                CtrlReq::MouseUp(_,_) => { app.drag = None; }
-                CtrlReq::ScrollUp => { remote_scroll_up(&mut app); }
-                CtrlReq::ScrollDown => { remote_scroll_down(&mut app); }
-                CtrlReq::NextWindow => { if !app.windows.is_empty() { app.active_idx = (app.active_idx + 1) % app.windows.len(); } }
-                CtrlReq::PrevWindow => { if !app.windows.is_empty() { app.active_idx = (app.active_idx + app.windows.len() - 1) % app.windows.len(); } }
-                CtrlReq::RenameWindow(name) => { let win = &mut app.windows[app.active_idx]; win.name = name; }
-                CtrlReq::ListWindows(resp) => { let json = list_windows_json(&app)?; let _ = resp.send(json); }
-                CtrlReq::ListTree(resp) => { let json = list_tree_json(&app)?; let _ = resp.send(json); }
-                CtrlReq::ToggleSync => { app.sync_input = !app.sync_input; }
-                CtrlReq::SetPaneTitle(title) => {
-                    let win = &mut app.windows[app.active_idx];
-                    if let Some(p) = active_pane_mut(&mut win.root, &win.active_path) { p.title = title; }
-                }
-            }
-        }
-
-        if reap_children(&mut app)? {
-            quit = true;
-        }
-
-        if quit { break; }
-    }
-    // teardown: kill all pane children
-    for win in app.windows.iter_mut() {
-        kill_all_children(&mut win.root);
+        copy_exit_on_bottom: false,
+        clients: Vec::new(),
+        frame_dirty: true,
+        frame_generations: Vec::new(),
+        status_cache: None,
+        server_options: options::Options::default(),
+        global_options,
+        session_options: options::Options::default(),
+        global_window_options: options::Options::default(),
+        config_errors: Vec::new(),
     }
-    Ok(())
 }
 
-fn create_window(pty_system: &dyn portable_pty::PtySystem, app: &mut AppState) -> io::Result<()> {
+fn create_window(pty_system: &dyn portable_pty::PtySystem, app: &mut AppState, cwd: Option<PathBuf>) -> io::Result<()> {
     let size = PtySize { rows: 30, cols: 120, pixel_width: 0, pixel_height: 0 };
     let pair = pty_system
         .openpty(size)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("openpty error: {e}")))?;
+        .map_err(|e| io::Error::other(format!("openpty error: {e}")))?;
 
-    let shell_cmd = detect_shell();
-    let child = pair
-        .slave
-        .spawn_command(shell_cmd)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("spawn shell error: {e}")))?;
+    let (child, pid) = spawn_shell(&*pair.slave, cwd.or_else(|| active_pane_path(app)), app.next_pane_id)?;
 
-    let term: Arc<Mutex<vt100::Parser>> = Arc::new(Mutex::new(vt100::Parser::new(size.rows, size.cols, 0)));
+    let term: Arc<Mutex<PaneParser>> = Arc::new(Mutex::new(callbacks::new_parser(size.rows, size.cols, option_number(app, "history-limit") as usize)));
     let term_reader = term.clone();
+    let generation = Arc::new(AtomicU64::new(0));
+    let gen_reader = generation.clone();
     let mut reader = pair
         .master
         .try_clone_reader()
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("clone reader error: {e}")))?;
+        .map_err(|e| io::Error::other(format!("clone reader error: {e}")))?;
 
     thread::spawn(move || {
         let mut local = [0u8; 8192];
@@ -827,6 +870,7 @@ fn create_window(pty_system: &dyn portable_pty::PtySystem, app: &mut AppState) -
                 Ok(n) if n > 0 => {
                     let mut parser = term_reader.lock().unwrap();
                     parser.process(&local[..n]);
+                    gen_reader.fetch_add(1, Ordering::Relaxed);
                 }
                 Ok(_) => thread::sleep(Duration::from_millis(5)),
                 Err(_) => break,
@@ -834,153 +878,45 @@ fn create_window(pty_system: &dyn portable_pty::PtySystem, app: &mut AppState) -
         }
     });
 
-    let pane = Pane { master: pair.master, child, term, last_rows: size.rows, last_cols: size.cols, id: app.next_pane_id, title: format!("pane %{}", app.next_pane_id) };
+    let pane = Pane { input: spawn_pane_writer(pair.master), child, term, last_rows: size.rows, last_cols: size.cols, id: app.next_pane_id, title: format!("pane %{}", app.next_pane_id), pid, generation, options: options::Options::default() };
     app.next_pane_id += 1;
-    app.windows.push(Window { root: Node::Leaf(pane), active_path: vec![], name: format!("win {}", app.windows.len()+1), id: app.next_win_id });
+    app.windows.push(Window { root: Node::Leaf(pane), active_path: vec![], name: format!("win {}", app.windows.len()+1), id: app.next_win_id, sync_marked: HashSet::new(), options: options::Options::default() });
     app.next_win_id += 1;
     app.active_idx = app.windows.len() - 1;
     Ok(())
 }
 
+/// Handle one key from an attached client. Returns `true` when that client should detach.
 fn handle_key(app: &mut AppState, key: KeyEvent) -> io::Result<bool> {
-    if matches!(key.code, KeyCode::Char('q')) && key.modifiers.contains(KeyModifiers::CONTROL) {
-        return Ok(true);
-    }
-
     match app.mode {
-        Mode::Passthrough => {
-            let is_ctrl_b = (key.code, key.modifiers) == app.prefix_key
-                || matches!(key.code, KeyCode::Char(c) if c == '\u{0002}');
-            if is_ctrl_b {
-                app.mode = Mode::Prefix { armed_at: Instant::now() };
-                return Ok(false);
-            }
-            forward_key_to_active(app, key)?;
-            Ok(false)
-        }
-        Mode::Prefix { armed_at } => {
-            let elapsed = armed_at.elapsed().as_millis() as u64;
-            let handled = match key.code {
-                KeyCode::Left => { move_focus(app, FocusDir::Left); true }
-                KeyCode::Right => { move_focus(app, FocusDir::Right); true }
-                KeyCode::Up => { move_focus(app, FocusDir::Up); true }
-                KeyCode::Down => { move_focus(app, FocusDir::Down); true }
-                KeyCode::Char(d) if d.is_ascii_digit() => {
-                    let idx = d.to_digit(10).unwrap() as usize;
-                    if idx > 0 && idx <= app.windows.len() { app.active_idx = idx - 1; }
-                    true
-                }
-                KeyCode::Char('c') => {
-                    let pty_system = PtySystemSelection::default()
-                        .get()
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("pty system error: {e}")))?;
-                    create_window(&*pty_system, app)?;
-                    true
-                }
-                KeyCode::Char('n') => {
-                    if !app.windows.is_empty() {
-                        app.active_idx = (app.active_idx + 1) % app.windows.len();
-                    }
-                    true
-                }
-                KeyCode::Char('p') => {
-                    if !app.windows.is_empty() {
-                        app.active_idx = (app.active_idx + app.windows.len() - 1) % app.windows.len();
-                    }
-                    true
-                }
-                KeyCode::Char('%') => {
-                    split_active(app, LayoutKind::Horizontal)?;
-                    true
-                }
-                KeyCode::Char('"') => {
-                    split_active(app, LayoutKind::Vertical)?;
-                    true
-                }
-                KeyCode::Char('x') => {
-                    kill_active_pane(app)?;
-                    true
-                }
-                KeyCode::Char('d') => {
-                    // detach: exit pmux cleanly
-                    return Ok(true);
-                }
-                KeyCode::Char('w') => { app.mode = Mode::WindowChooser { selected: app.active_idx }; true }
-                KeyCode::Char(',') => { app.mode = Mode::RenamePrompt { input: String::new() }; true }
-                KeyCode::Char(' ') => { cycle_top_layout(app); true }
-                KeyCode::Char('[') => { enter_copy_mode(app); true }
-                KeyCode::Char(']') => { paste_latest(app)?; app.mode = Mode::Passthrough; true }
-                KeyCode::Char(':') => {
-                    app.mode = Mode::CommandPrompt { input: String::new() };
-                    true
-                }
-                KeyCode::Char('q') => {
-                    let win = &app.windows[app.active_idx];
-                    let mut rects: Vec<(Vec<usize>, Rect)> = Vec::new();
-                    compute_rects(&win.root, app.last_window_area, &mut rects);
-                    app.display_map.clear();
-                    for (i, (path, _)) in rects.into_iter().enumerate() {
-                        let n = i + 1;
-                        if n <= 10 { app.display_map.push((n, path)); } else { break; }
-                    }
-                    app.mode = Mode::PaneChooser { opened_at: Instant::now() };
-                    true
-                }
-                _ => false,
-            };
-
-            if matches!(app.mode, Mode::Prefix { .. }) {
-                if !handled && elapsed < app.escape_time_ms {
-                    // Unrecognized after prefix: do not send '^B'; swallow and return
-                    return Ok(false);
-                }
-                app.mode = Mode::Passthrough;
-            }
-            Ok(false)
-        }
+        Mode::Passthrough | Mode::Copy => table_key(app, key),
         Mode::CommandPrompt { .. } => {
             match key.code {
                 KeyCode::Esc => { app.mode = Mode::Passthrough; }
                 KeyCode::Enter => { execute_command_prompt(app)?; }
                 KeyCode::Backspace => {
-                    if let Mode::CommandPrompt { input } = &mut app.mode { let _ = input.pop(); }
+                    if let Mode::CommandPrompt { input, .. } = &mut app.mode { let _ = input.pop(); }
                 }
                 KeyCode::Char(c) => {
-                    if let Mode::CommandPrompt { input } = &mut app.mode { input.push(c); }
+                    if let Mode::CommandPrompt { input, .. } = &mut app.mode { input.push(c); }
                 }
                 _ => {}
             }
             Ok(false)
         }
-        Mode::WindowChooser { selected } => {
+        Mode::TreeChooser { selected } => {
+            let entries = tree_entries(app);
             match key.code {
                 KeyCode::Esc => { app.mode = Mode::Passthrough; }
-                KeyCode::Up | KeyCode::Left => { if selected > 0 { if let Mode::WindowChooser { selected: s } = &mut app.mode { *s -= 1; } } }
-                KeyCode::Down | KeyCode::Right => { if selected + 1 < app.windows.len() { if let Mode::WindowChooser { selected: s } = &mut app.mode { *s += 1; } } }
-                KeyCode::Enter => { if let Mode::WindowChooser { selected: s } = &mut app.mode { app.active_idx = *s; app.mode = Mode::Passthrough; } }
-                _ => {}
-            }
-            Ok(false)
-        }
-        Mode::RenamePrompt { .. } => {
-            match key.code {
-                KeyCode::Esc => { app.mode = Mode::Passthrough; }
-                KeyCode::Enter => { if let Mode::RenamePrompt { input } = &mut app.mode { app.windows[app.active_idx].name = input.clone(); app.mode = Mode::Passthrough; } }
-                KeyCode::Backspace => { if let Mode::RenamePrompt { input } = &mut app.mode { let _ = input.pop(); } }
-                KeyCode::Char(c) => { if let Mode::RenamePrompt { input } = &mut app.mode { input.push(c); } }
-                _ => {}
-            }
-            Ok(false)
-        }
-        Mode::CopyMode => {
-            match key.code {
-                KeyCode::Esc | KeyCode::Char(']') => { app.mode = Mode::Passthrough; app.copy_anchor = None; app.copy_pos = None; }
-                KeyCode::Left => { move_copy_cursor(app, -1, 0); }
-                KeyCode::Right => { move_copy_cursor(app, 1, 0); }
-                KeyCode::Up => { move_copy_cursor(app, 0, -1); }
-                KeyCode::Down => { move_copy_cursor(app, 0, 1); }
-                KeyCode::Char('v') => { if let Some((r,c)) = current_prompt_pos(app) { app.copy_anchor = Some((r,c)); app.copy_pos = Some((r,c)); } }
-                KeyCode::Char('y') => { yank_selection(app)?; app.mode = Mode::Passthrough; }
+                KeyCode::Up | KeyCode::Left if selected > 0 => { app.mode = Mode::TreeChooser { selected: selected - 1 }; }
+                KeyCode::Down | KeyCode::Right if selected + 1 < entries.len() => { app.mode = Mode::TreeChooser { selected: selected + 1 }; }
+                KeyCode::Enter => {
+                    if let Some((is_win, wid, pid, _)) = entries.get(selected) {
+                        if let Some(idx) = find_window_index_by_id(app, *wid) { app.active_idx = idx; }
+                        if !*is_win { focus_pane_by_id(app, *pid); }
+                    }
+                    app.mode = Mode::Passthrough;
+                }
                 _ => {}
             }
             Ok(false)
@@ -1004,6 +940,100 @@ fn handle_key(app: &mut AppState, key: KeyEvent) -> io::Result<bool> {
     }
 }
 
+/// Number the panes of the active window so one can be picked with a digit.
+fn display_panes(app: &mut AppState) {
+    let win = &app.windows[app.active_idx];
+    let mut rects: Vec<(Vec<usize>, Rect)> = Vec::new();
+    compute_rects(&win.root, app.last_window_area, &mut rects);
+    app.display_map.clear();
+    for (i, (path, _)) in rects.into_iter().enumerate() {
+        let n = i + 1;
+        if n <= 10 { app.display_map.push((n, path)); } else { break; }
+    }
+    app.mode = Mode::PaneChooser { opened_at: Instant::now() };
+}
+
+/// Look `key` up in the active key table, then in `root` and, in copy mode,
+/// the copy mode table, and run what it is bound to. Keys bound nowhere go to the active pane.
+fn table_key(app: &mut AppState, key: KeyEvent) -> io::Result<bool> {
+    let k = keys::binding_key(&key);
+    if let Some(active) = app.key_table.take() {
+        let elapsed = active.since.elapsed().as_millis() as u64;
+        if !active.repeat || elapsed <= option_number(app, "repeat-time") {
+            match app.key_tables.get(&active.name, k).map(|b| b.repeat) {
+                Some(repeat) if repeat || !active.repeat => return Ok(run_binding(app, &active.name, k).unwrap_or(false)),
+                None if !active.repeat => {
+                    // an unbound key is dropped; one pressed straight after the prefix leaves it active
+                    if active.name == "prefix" && elapsed < option_number(app, "escape-time") { app.key_table = Some(active); }
+                    return Ok(false);
+                }
+                // any other key ends a repeat and is handled as if the table weren't there
+                _ => {}
+            }
+        }
+    }
+    if Some(k) == prefix_key(app, "prefix") || Some(k) == prefix_key(app, "prefix2") {
+        app.key_table = Some(ActiveTable { name: "prefix".to_string(), since: Instant::now(), repeat: false });
+        return Ok(false);
+    }
+    if let Some(detach) = run_binding(app, "root", k) { return Ok(detach); }
+    if matches!(app.mode, Mode::Copy) {
+        let table = if option(app, "mode-keys") == "vi" { "copy-mode-vi" } else { "copy-mode" };
+        return Ok(run_binding(app, table, k).unwrap_or(false));
+    }
+    forward_key_to_active(app, key)?;
+    Ok(false)
+}
+
+/// `resize-pane -L/-R/-U/-D`: move the border of the active pane nearest that way `cells` cells.
+fn resize_active_pane(app: &mut AppState, dir: FocusDir, cells: u16) {
+    let horizontal = matches!(dir, FocusDir::Left | FocusDir::Right);
+    let win = &mut app.windows[app.active_idx];
+    let mut rects: Vec<(Vec<usize>, Rect)> = Vec::new();
+    compute_rects(&win.root, app.last_window_area, &mut rects);
+    for depth in (0..win.active_path.len()).rev() {
+        let parent = win.active_path[..depth].to_vec();
+        let child = win.active_path[depth];
+        let Some(Node::Split { kind, sizes, children }) = get_split_mut(&mut win.root, &parent) else { continue };
+        if matches!(kind, LayoutKind::Horizontal) != horizontal || sizes.len() != children.len() { continue; }
+        // the split's extent in cells, to turn them into its percentages
+        let spans = rects.iter().filter(|(p, _)| p.starts_with(&parent)).map(|(_, r)| if horizontal { (r.x, r.x + r.width) } else { (r.y, r.y + r.height) });
+        let (start, end) = spans.fold((u16::MAX, 0), |(s, e), (a, b)| (s.min(a), e.max(b)));
+        let pct = (cells as u32 * 100 / end.saturating_sub(start).max(1) as u32).clamp(1, 100) as i32;
+        let border = child.min(children.len() - 2);
+        let total = (sizes[border] + sizes[border + 1]) as i32;
+        let delta = if matches!(dir, FocusDir::Right | FocusDir::Down) { pct } else { -pct };
+        let left = (sizes[border] as i32 + delta).clamp(5.min(total), (total - 5).max(0));
+        sizes[border] = left as u16;
+        sizes[border + 1] = (total - left) as u16;
+        return;
+    }
+}
+
+/// `send-keys -X`: a command for copy mode.
+fn copy_mode_command(app: &mut AppState, cmd: &str) -> Result<(), String> {
+    if !matches!(app.mode, Mode::Copy) { return Err("not in copy mode".to_string()); }
+    match cmd {
+        "cancel" => exit_copy_mode(app),
+        "page-up" => scroll_history_pages(app, 1, 1),
+        "page-down" => scroll_history_pages(app, -1, 1),
+        "halfpage-up" => scroll_history_pages(app, 1, 2),
+        "halfpage-down" => scroll_history_pages(app, -1, 2),
+        "scroll-up" => scroll_history(app, 1),
+        "scroll-down" => scroll_history(app, -1),
+        "history-top" => scroll_history(app, isize::MAX),
+        "history-bottom" => scroll_history(app, isize::MIN),
+        "cursor-left" => move_copy_cursor(app, -1, 0),
+        "cursor-right" => move_copy_cursor(app, 1, 0),
+        "cursor-up" => move_copy_cursor(app, 0, -1),
+        "cursor-down" => move_copy_cursor(app, 0, 1),
+        "begin-selection" => { if let Some((r,c)) = current_prompt_pos(app) { app.copy_anchor = Some((r,c)); app.copy_pos = Some((r,c)); } }
+        "copy-selection-and-cancel" => { yank_selection(app).map_err(|e| e.to_string())?; exit_copy_mode(app); }
+        _ => return Err(format!("unknown copy mode command: {}", cmd)),
+    }
+    Ok(())
+}
+
 fn move_focus(app: &mut AppState, dir: FocusDir) {
     let win = &mut app.windows[app.active_idx];
     let mut rects: Vec<(Vec<usize>, Rect)> = Vec::new();
@@ -1023,51 +1053,82 @@ fn move_focus(app: &mut AppState, dir: FocusDir) {
             FocusDir::Up => if r.y + r.height <= arect.y { Some((arect.y - (r.y + r.height)) as u32) } else { None },
             FocusDir::Down => if r.y >= arect.y + arect.height { Some((r.y - (arect.y + arect.height)) as u32) } else { None },
         };
-        if let Some(dist) = candidate { if best.map_or(true, |(_,bd)| dist < bd) { best = Some((i, dist)); } }
+        if let Some(dist) = candidate { if best.is_none_or(|(_,bd)| dist < bd) { best = Some((i, dist)); } }
     }
     if let Some((ni, _)) = best { win.active_path = rects[ni].0.clone(); }
 }
 
 fn forward_key_to_active(app: &mut AppState, key: KeyEvent) -> io::Result<()> {
-    let win = &mut app.windows[app.active_idx];
-    let Some(active) = active_pane_mut(&mut win.root, &win.active_path) else { return Ok(()); };
-    match key.code {
-        KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
-            let _ = write!(active.master, "{}", c);
-        }
-        KeyCode::Enter => { let _ = write!(active.master, "\r"); }
-        KeyCode::Tab => { let _ = write!(active.master, "\t"); }
-        KeyCode::Backspace => { let _ = write!(active.master, "\x08"); }
-        KeyCode::Esc => { let _ = write!(active.master, "\x1b"); }
-        KeyCode::Left => { let _ = write!(active.master, "\x1b[D"); }
-        KeyCode::Right => { let _ = write!(active.master, "\x1b[C"); }
-        KeyCode::Up => { let _ = write!(active.master, "\x1b[A"); }
-        KeyCode::Down => { let _ = write!(active.master, "\x1b[B"); }
-        _ => {}
-    }
+    let sync = synchronized(app, app.active_idx);
+    for p in input_panes(&mut app.windows[app.active_idx], sync) { write_key(p, &key); }
     Ok(())
 }
 
-fn split_active(app: &mut AppState, kind: LayoutKind) -> io::Result<()> {
-    let pty_system = PtySystemSelection::default().get().map_err(|e| io::Error::new(io::ErrorKind::Other, format!("pty system error: {e}")))?;
-    let size = PtySize { rows: 30, cols: 120, pixel_width: 0, pixel_height: 0 };
-    let pair = pty_system.openpty(size).map_err(|e| io::Error::new(io::ErrorKind::Other, format!("openpty error: {e}")))?;
-    let shell_cmd = detect_shell();
-    let child = pair.slave.spawn_command(shell_cmd).map_err(|e| io::Error::new(io::ErrorKind::Other, format!("spawn shell error: {e}")))?;
-    let term: Arc<Mutex<vt100::Parser>> = Arc::new(Mutex::new(vt100::Parser::new(size.rows, size.cols, 0)));
-    let term_reader = term.clone();
-    let mut reader = pair.master.try_clone_reader().map_err(|e| io::Error::new(io::ErrorKind::Other, format!("clone reader error: {e}")))?;
-    thread::spawn(move || {
-        let mut local = [0u8; 8192];
-        loop {
-            match reader.read(&mut local) {
-                Ok(n) if n > 0 => { let mut parser = term_reader.lock().unwrap(); parser.process(&local[..n]); }
-                Ok(_) => thread::sleep(Duration::from_millis(5)),
-                Err(_) => break,
+/// Panes that input for the active pane goes to: that pane and, with
+/// `sync` on, the marked panes of the window or all of them.
+fn input_panes(win: &mut Window, sync: bool) -> Vec<&mut Pane> {
+    fn collect<'a>(node: &'a mut Node, out: &mut Vec<&'a mut Pane>) {
+        match node {
+            Node::Leaf(p) => out.push(p),
+            Node::Split { children, .. } => { for c in children.iter_mut() { collect(c, out); } }
+        }
+    }
+    let active_id = active_pane_mut(&mut win.root, &win.active_path).map(|p| p.id);
+    let mut panes = Vec::new();
+    collect(&mut win.root, &mut panes);
+    let marked = &win.sync_marked;
+    let any_marked = panes.iter().any(|p| marked.contains(&p.id));
+    panes.retain(|p| Some(p.id) == active_id || (sync && (!any_marked || marked.contains(&p.id))));
+    panes
+}
+
+fn toggle_sync(app: &mut AppState) {
+    let on = synchronized(app, app.active_idx);
+    app.windows[app.active_idx].options.set("synchronize-panes", if on { "off" } else { "on" }.to_string());
+}
+
+/// Whether window `win_idx` has `synchronize-panes` on.
+fn synchronized(app: &AppState, win_idx: usize) -> bool {
+    option_value(app, win_idx, None, "synchronize-panes") == "on"
+}
+
+fn toggle_pane_mark(app: &mut AppState) {
+    let win = &mut app.windows[app.active_idx];
+    let Some(id) = active_pane_mut(&mut win.root, &win.active_path).map(|p| p.id) else { return };
+    if !win.sync_marked.remove(&id) { win.sync_marked.insert(id); }
+}
+
+/// Write `key` to the pane encoded for its current cursor and keypad modes.
+fn write_key(pane: &mut Pane, key: &KeyEvent) {
+    let bytes = {
+        let parser = pane.term.lock().unwrap();
+        let screen = parser.screen();
+        keys::encode_key(key, screen.application_cursor(), screen.application_keypad())
+    };
+    if !bytes.is_empty() { pane.input.write(bytes); }
+}
+
+fn split_active(app: &mut AppState, kind: LayoutKind, cwd: Option<PathBuf>) -> io::Result<()> {
+    let pty_system = PtySystemSelection::default().get().map_err(|e| io::Error::other(format!("pty system error: {e}")))?;
+    let size = PtySize { rows: 30, cols: 120, pixel_width: 0, pixel_height: 0 };
+    let pair = pty_system.openpty(size).map_err(|e| io::Error::other(format!("openpty error: {e}")))?;
+    let (child, pid) = spawn_shell(&*pair.slave, cwd.or_else(|| active_pane_path(app)), app.next_pane_id)?;
+    let term: Arc<Mutex<PaneParser>> = Arc::new(Mutex::new(callbacks::new_parser(size.rows, size.cols, option_number(app, "history-limit") as usize)));
+    let term_reader = term.clone();
+    let generation = Arc::new(AtomicU64::new(0));
+    let gen_reader = generation.clone();
+    let mut reader = pair.master.try_clone_reader().map_err(|e| io::Error::other(format!("clone reader error: {e}")))?;
+    thread::spawn(move || {
+        let mut local = [0u8; 8192];
+        loop {
+            match reader.read(&mut local) {
+                Ok(n) if n > 0 => { let mut parser = term_reader.lock().unwrap(); parser.process(&local[..n]); gen_reader.fetch_add(1, Ordering::Relaxed); }
+                Ok(_) => thread::sleep(Duration::from_millis(5)),
+                Err(_) => break,
             }
         }
     });
-    let new_leaf = Node::Leaf(Pane { master: pair.master, child, term, last_rows: size.rows, last_cols: size.cols, id: app.next_pane_id, title: format!("pane %{}", app.next_pane_id) });
+    let new_leaf = Node::Leaf(Pane { input: spawn_pane_writer(pair.master), child, term, last_rows: size.rows, last_cols: size.cols, id: app.next_pane_id, title: format!("pane %{}", app.next_pane_id), pid, generation, options: options::Options::default() });
     app.next_pane_id += 1;
     let win = &mut app.windows[app.active_idx];
     replace_leaf_with_split(&mut win.root, &win.active_path, kind, new_leaf);
@@ -1079,6 +1140,7 @@ fn split_active(app: &mut AppState, kind: LayoutKind) -> io::Result<()> {
 }
 
 fn handle_mouse(app: &mut AppState, me: crossterm::event::MouseEvent, window_area: Rect) -> io::Result<()> {
+    use crossterm::event::{MouseEventKind, MouseButton};
     let win = &mut app.windows[app.active_idx];
     // compute leaf rects from split tree
     let mut rects: Vec<(Vec<usize>, Rect)> = Vec::new();
@@ -1086,101 +1148,340 @@ fn handle_mouse(app: &mut AppState, me: crossterm::event::MouseEvent, window_are
     // compute borders for splits
     let mut borders: Vec<(Vec<usize>, LayoutKind, usize, u16)> = Vec::new();
     compute_split_borders(&win.root, window_area, &mut borders);
+    let pos = ratatui::layout::Position { x: me.column, y: me.row };
+    let inner_of = |r: Rect| Block::default().borders(Borders::ALL).inner(r);
+    let hit = rects.iter().find(|(_, r)| r.contains(pos)).cloned();
+
+    // a border drag owns the left button until it is released
+    if let Some(d) = &app.drag {
+        match me.kind {
+            MouseEventKind::Drag(MouseButton::Left) => { adjust_split_sizes(&mut win.root, d, me.column, me.row); return Ok(()); }
+            MouseEventKind::Up(MouseButton::Left) => { app.drag = None; return Ok(()); }
+            _ => {}
+        }
+    }
 
-    use crossterm::event::{MouseEventKind, MouseButton};
-    match me.kind {
-        MouseEventKind::Down(MouseButton::Left) => {
-            // focus pane
-            for (path, area) in rects.iter() {
-                if area.contains(ratatui::layout::Position { x: me.column, y: me.row }) { win.active_path = path.clone(); }
-            }
-            // check border resize start
+    if let MouseEventKind::Down(button) = me.kind {
+        // check border resize start; cells inside a pane belong to the pane
+        if button == MouseButton::Left && !hit.as_ref().is_some_and(|(_, r)| inner_of(*r).contains(pos)) {
             let tol = 1u16;
-            for (path, kind, idx, pos) in borders.iter() {
-                match kind {
-                    LayoutKind::Horizontal => {
-                        if me.column >= pos.saturating_sub(tol) && me.column <= pos + tol {
-                            // record initial sizes from split node
-                            if let Some((left,right)) = split_sizes_at(&win.root, path.clone(), *idx) {
-                                app.drag = Some(DragState { split_path: path.clone(), kind: *kind, index: *idx, start_x: me.column, start_y: me.row, left_initial: left, _right_initial: right });
-                            }
-                            break;
-                        }
-                    }
-                    LayoutKind::Vertical => {
-                        if me.row >= pos.saturating_sub(tol) && me.row <= pos + tol {
-                            if let Some((left,right)) = split_sizes_at(&win.root, path.clone(), *idx) {
-                                app.drag = Some(DragState { split_path: path.clone(), kind: *kind, index: *idx, start_x: me.column, start_y: me.row, left_initial: left, _right_initial: right });
-                            }
-                            break;
-                        }
+            for (path, kind, idx, at) in borders.iter() {
+                let near = match kind {
+                    LayoutKind::Horizontal => me.column >= at.saturating_sub(tol) && me.column <= at + tol,
+                    LayoutKind::Vertical => me.row >= at.saturating_sub(tol) && me.row <= at + tol,
+                };
+                if near {
+                    // record initial sizes from split node
+                    if let Some((left,right)) = split_sizes_at(&win.root, path.clone(), *idx) {
+                        app.drag = Some(DragState { split_path: path.clone(), kind: *kind, index: *idx, start_x: me.column, start_y: me.row, left_initial: left, _right_initial: right });
                     }
+                    break;
                 }
             }
         }
-        MouseEventKind::Drag(MouseButton::Left) => {
-            if let Some(d) = &app.drag {
-                adjust_split_sizes(&mut win.root, d, me.column, me.row);
+    }
+    // clicks and the wheel act on the pane under the pointer
+    if matches!(me.kind, MouseEventKind::Down(_) | MouseEventKind::ScrollUp | MouseEventKind::ScrollDown) {
+        if let Some((path, _)) = &hit {
+            if *path != win.active_path {
+                if matches!(app.mode, Mode::Copy) { exit_copy_mode(app); }
+                app.windows[app.active_idx].active_path = path.clone();
             }
         }
-        MouseEventKind::Up(MouseButton::Left) => { app.drag = None; }
-        MouseEventKind::ScrollUp => {
-            if let Some(active) = active_pane_mut(&mut win.root, &win.active_path) { let _ = write!(active.master, "\x1b[A"); }
-        }
-        MouseEventKind::ScrollDown => {
-            if let Some(active) = active_pane_mut(&mut win.root, &win.active_path) { let _ = write!(active.master, "\x1b[B"); }
+    }
+    if app.drag.is_some() { return Ok(()); }
+
+    // drags and the release go to the pane that saw the press, even once the pointer leaves it
+    let grab = if matches!(me.kind, MouseEventKind::Up(_)) { app.mouse_grab.take() } else { app.mouse_grab.clone() };
+    let grabbed = matches!(me.kind, MouseEventKind::Drag(_) | MouseEventKind::Up(_)) && grab.is_some();
+    let target = if grabbed { grab.and_then(|g| rects.into_iter().find(|(p, _)| *p == g)) } else { hit };
+    let Some((path, rect)) = target else { return Ok(()) };
+    let inner = inner_of(rect);
+    if !grabbed && !inner.contains(pos) { return Ok(()); }
+
+    let win = &mut app.windows[app.active_idx];
+    let in_copy_mode = matches!(app.mode, Mode::Copy) && path == win.active_path;
+    let Some(pane) = active_pane_mut(&mut win.root, &path) else { return Ok(()) };
+    let (mode, encoding) = {
+        let parser = pane.term.lock().unwrap();
+        (parser.screen().mouse_protocol_mode(), parser.screen().mouse_protocol_encoding())
+    };
+    if mode == vt100::MouseProtocolMode::None || in_copy_mode {
+        match me.kind {
+            MouseEventKind::ScrollUp => wheel_scroll(app, true),
+            MouseEventKind::ScrollDown => wheel_scroll(app, false),
+            _ => {}
         }
-        _ => {}
+        return Ok(());
+    }
+    if matches!(me.kind, MouseEventKind::Down(_)) { app.mouse_grab = Some(path); }
+    let col = me.column.clamp(inner.x, inner.right().saturating_sub(1)) - inner.x;
+    let row = me.row.clamp(inner.y, inner.bottom().saturating_sub(1)) - inner.y;
+    if let Some(bytes) = mouse::encode_mouse(me.kind, me.modifiers, col, row, mode, encoding) {
+        pane.input.write(bytes);
     }
     Ok(())
 }
 
-// TODO: implement split-border detection and per-node size adjustment
-
 fn kill_active_pane(app: &mut AppState) -> io::Result<()> {
     let win = &mut app.windows[app.active_idx];
     kill_leaf(&mut win.root, &win.active_path);
     Ok(())
 }
 
-fn detect_shell() -> CommandBuilder {
+/// Start the shell for pane `pane_id` in `cwd`, returning it with its pid if that can be found.
+fn spawn_shell(slave: &dyn portable_pty::SlavePty, cwd: Option<PathBuf>, pane_id: usize) -> io::Result<(Box<dyn portable_pty::Child>, Option<u32>)> {
+    let mut cmd = shell_command(&detect_shell(), cwd.filter(|d| d.is_dir()).as_deref());
+    // portable-pty doesn't report the pid, so the shell is found by this instead
+    let marker = format!("%{}", pane_id);
+    cmd.env("PMUX_PANE", &marker);
+    let child = slave.spawn_command(cmd).map_err(|e| io::Error::other(format!("spawn shell error: {e}")))?;
+    Ok((child, procinfo::child_with_env("PMUX_PANE", &marker)))
+}
+
+/// A command starting `shell` in `cwd`. portable-pty 0.2 can't set a child's
+/// directory, and changing the server's own would leak into `#()` jobs and
+/// other panes starting at the same time, so the shell changes into it itself.
+#[cfg(unix)]
+fn shell_command(shell: &str, cwd: Option<&Path>) -> CommandBuilder {
+    let Some(dir) = cwd else { return CommandBuilder::new(shell) };
+    let mut cmd = CommandBuilder::new("/bin/sh");
+    // exec keeps the pid, so the shell is still the pane's process
+    cmd.args([OsStr::new("-c"), OsStr::new("cd -- \"$1\" 2>/dev/null; exec \"$0\""), OsStr::new(shell), dir.as_os_str()]);
+    cmd
+}
+
+#[cfg(windows)]
+fn shell_command(shell: &str, cwd: Option<&Path>) -> CommandBuilder {
+    let mut cmd = CommandBuilder::new(shell);
+    let Some(dir) = cwd.map(|d| d.display().to_string()) else { return cmd };
+    let name = Path::new(shell).file_stem().map(|n| n.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+    if name == "cmd" {
+        cmd.args(["/K".to_string(), format!("cd /d \"{}\"", dir)]);
+    } else {
+        cmd.args(["-NoExit".to_string(), "-Command".to_string(), format!("Set-Location -LiteralPath '{}'", dir.replace('\'', "''"))]);
+    }
+    cmd
+}
+
+/// Directory the pane is working in: what the shell last reported with OSC 7, or
+/// failing that what the OS says about the shell process.
+fn pane_current_path(p: &Pane) -> Option<PathBuf> {
+    let reported = p.term.lock().unwrap().callbacks().cwd().map(Path::to_path_buf);
+    reported.or_else(|| procinfo::cwd(p.pid?))
+}
+
+fn active_pane_path(app: &AppState) -> Option<PathBuf> {
+    let win = app.windows.get(app.active_idx)?;
+    active_pane_ref(&win.root, &win.active_path).and_then(pane_current_path)
+}
+
+fn detect_shell() -> String {
     let pwsh = which::which("pwsh").ok().map(|p| p.to_string_lossy().into_owned());
     let cmd = which::which("cmd").ok().map(|p| p.to_string_lossy().into_owned());
-    match pwsh.or(cmd) {
-        Some(path) => CommandBuilder::new(path),
-        None => CommandBuilder::new("pwsh.exe"),
-    }
+    pwsh.or(cmd).unwrap_or_else(|| "pwsh.exe".to_string())
 }
 
 fn execute_command_prompt(app: &mut AppState) -> io::Result<()> {
-    let cmdline = match &app.mode { Mode::CommandPrompt { input } => input.clone(), _ => String::new() };
-    app.mode = Mode::Passthrough;
-    let parts: Vec<&str> = cmdline.split_whitespace().collect();
-    if parts.is_empty() { return Ok(()); }
-    match parts[0] {
+    let cmdline = match std::mem::replace(&mut app.mode, Mode::Passthrough) {
+        Mode::CommandPrompt { input, template, .. } if !template.is_empty() => template.replace("%%", &input),
+        Mode::CommandPrompt { input, .. } => input,
+        _ => String::new(),
+    };
+    match config::tokenize(&cmdline) {
+        Ok(commands) => run_commands(app, &commands),
+        Err(e) => display_message(app, e),
+    }
+    Ok(())
+}
+
+/// Run commands from the prompt or a key binding, showing any errors in the status line.
+fn run_commands(app: &mut AppState, commands: &[Vec<String>]) {
+    if let Err(e) = commands.iter().try_for_each(|args| run_command(app, args)) { app.config_errors.push(e); }
+    // errors from the commands themselves and from any file they sourced
+    if !app.config_errors.is_empty() {
+        let text = std::mem::take(&mut app.config_errors).join("; ");
+        display_message(app, text);
+    }
+}
+
+/// Run what `key` is bound to in `table`: `None` when it isn't bound, else
+/// whether the client that pressed it should detach.
+fn run_binding(app: &mut AppState, table: &str, key: (KeyCode, KeyModifiers)) -> Option<bool> {
+    let binding = app.key_tables.get(table, key)?;
+    let (commands, repeat) = (binding.commands.clone(), binding.repeat);
+    app.detach = false;
+    run_commands(app, &commands);
+    // a repeatable key keeps its table for the next key, unless it switched to another
+    if repeat && table != "root" && app.key_table.is_none() {
+        app.key_table = Some(ActiveTable { name: table.to_string(), since: Instant::now(), repeat: true });
+    }
+    Some(std::mem::take(&mut app.detach))
+}
+
+/// Run one command, as typed at the command prompt or read from a configuration file.
+fn run_command(app: &mut AppState, args: &[String]) -> Result<(), String> {
+    let parts: Vec<&str> = args.iter().map(String::as_str).collect();
+    let Some(&cmd) = parts.first() else { return Ok(()) };
+    let io_err = |e: io::Error| e.to_string();
+    // the configuration runs before the first window is opened
+    let needs_window = matches!(cmd, "split-window" | "kill-pane" | "capture-pane" | "next-window" | "previous-window" | "rename-window" | "select-pane" | "choose-tree" | "select-window"
+        | "resize-pane" | "next-layout" | "copy-mode" | "paste-buffer" | "display-panes" | "send-keys" | "send" | "send-prefix");
+    if app.windows.is_empty() && needs_window {
+        return Err(format!("no current window: {}", cmd));
+    }
+    match cmd {
+        "set" | "set-option" | "setw" | "set-window-option" => { set_option(app, &parts[1..], cmd.starts_with("setw") || cmd == "set-window-option")?; }
+        "show" | "show-options" | "showw" | "show-window-options" => {
+            let text = show_options(app, &parts[1..], cmd.starts_with("showw") || cmd == "show-window-options")?;
+            display_message(app, text.lines().collect::<Vec<_>>().join("; "));
+        }
+        "source-file" | "source" => {
+            let cwd = env::current_dir().unwrap_or_default();
+            config::source_command(&args[1..], &cwd, app)?;
+        }
+        "bind-key" | "bind" => {
+            let (table, binding) = bindings::parse_bind(&args[1..])?;
+            app.key_tables.bind(&table, binding);
+        }
+        "unbind-key" | "unbind" => {
+            let (table, key) = bindings::parse_unbind(&args[1..])?;
+            app.key_tables.unbind(&table, key);
+        }
+        "list-keys" | "lsk" => {
+            let text = list_keys(app, &parts[1..])?;
+            display_message(app, text.lines().collect::<Vec<_>>().join("; "));
+        }
+        "command-prompt" => {
+            let (mut prompt, mut template, mut i) = (None, String::new(), 1);
+            while i < parts.len() {
+                match parts[i] {
+                    "-p" => { prompt = parts.get(i + 1).map(|p| p.to_string()); i += 1; }
+                    t => template = t.to_string(),
+                }
+                i += 1;
+            }
+            app.mode = Mode::CommandPrompt { input: String::new(), prompt, template };
+        }
+        "detach-client" | "detach" => { app.detach = true; }
+        "send-prefix" => {
+            let key = option(app, if parts.contains(&"-2") { "prefix2" } else { "prefix" });
+            send_key_to_active(app, &key).map_err(io_err)?;
+        }
+        "switch-client" | "switchc" => {
+            let Some(table) = parts.iter().position(|p| *p == "-T").and_then(|i| parts.get(i + 1)) else { return Err("usage: switch-client -T table".to_string()) };
+            if *table != "root" && !app.key_tables.has_table(table) { return Err(format!("table {} doesn't exist", table)); }
+            app.key_table = (*table != "root").then(|| ActiveTable { name: table.to_string(), since: Instant::now(), repeat: false });
+        }
         "new-window" => {
-            let pty_system = PtySystemSelection::default().get().map_err(|e| io::Error::new(io::ErrorKind::Other, format!("pty system error: {e}")))?;
-            create_window(&*pty_system, app)?;
+            let pty_system = PtySystemSelection::default().get().map_err(|e| format!("pty system error: {e}"))?;
+            create_window(&*pty_system, app, start_dir_arg(&parts)).map_err(io_err)?;
         }
         "split-window" => {
-            let kind = if parts.iter().any(|p| *p == "-h") { LayoutKind::Horizontal } else { LayoutKind::Vertical };
-            split_active(app, kind)?;
+            let kind = if parts.contains(&"-h") { LayoutKind::Horizontal } else { LayoutKind::Vertical };
+            split_active(app, kind, start_dir_arg(&parts)).map_err(io_err)?;
         }
-        "kill-pane" => { kill_active_pane(app)?; }
-        "capture-pane" => { capture_active_pane(app)?; }
-        "save-buffer" => { if let Some(file) = parts.get(1) { save_latest_buffer(app, file)?; } }
+        "kill-pane" => { kill_active_pane(app).map_err(io_err)?; }
+        "capture-pane" => { capture_active_pane(app).map_err(io_err)?; }
+        "save-buffer" => { if let Some(file) = parts.get(1) { save_latest_buffer(app, file).map_err(io_err)?; } }
         "list-sessions" => { println!("default"); }
         "attach-session" => { /* already attached */ }
         "next-window" => { app.active_idx = (app.active_idx + 1) % app.windows.len(); }
         "previous-window" => { app.active_idx = (app.active_idx + app.windows.len() - 1) % app.windows.len(); }
+        "rename-window" => {
+            let Some(name) = parts.get(1) else { return Err("usage: rename-window name".to_string()) };
+            rename_window(&mut app.windows[app.active_idx], name.to_string());
+        }
+        "select-pane" if parts.contains(&"-m") => { toggle_pane_mark(app); }
+        "select-pane" if parts.contains(&"-M") => { app.windows[app.active_idx].sync_marked.clear(); }
+        "select-pane" => {
+            for flag in &parts[1..] {
+                match *flag {
+                    "-L" => move_focus(app, FocusDir::Left),
+                    "-R" => move_focus(app, FocusDir::Right),
+                    "-U" => move_focus(app, FocusDir::Up),
+                    "-D" => move_focus(app, FocusDir::Down),
+                    _ => {}
+                }
+            }
+            if let Some(title) = parts.iter().position(|p| *p == "-T").and_then(|i| parts.get(i + 1)) {
+                let win = &mut app.windows[app.active_idx];
+                if let Some(p) = active_pane_mut(&mut win.root, &win.active_path) { p.title = title.to_string(); }
+            }
+        }
+        "resize-pane" if parts.contains(&"-Z") => { toggle_zoom(app); }
+        "resize-pane" => {
+            let dir = match parts.get(1).copied() {
+                Some("-L") => FocusDir::Left,
+                Some("-R") => FocusDir::Right,
+                Some("-U") => FocusDir::Up,
+                Some("-D") => FocusDir::Down,
+                _ => return Err("usage: resize-pane [-L|-R|-U|-D] [cells] or resize-pane -Z".to_string()),
+            };
+            let cells = match parts.get(2) { Some(n) => n.parse::<u16>().map_err(|_| format!("invalid size: {}", n))?, None => 1 };
+            resize_active_pane(app, dir, cells);
+        }
+        "next-layout" => { cycle_top_layout(app); }
+        "copy-mode" => { enter_copy_mode(app); }
+        "paste-buffer" => { paste_latest(app).map_err(io_err)?; }
+        "display-panes" => { display_panes(app); }
+        "send-keys" | "send" if parts.get(1) == Some(&"-X") => {
+            let Some(command) = parts.get(2) else { return Err("usage: send-keys -X command".to_string()) };
+            copy_mode_command(app, command)?;
+        }
+        "send-keys" | "send" => {
+            let literal = parts.contains(&"-l");
+            for k in parts[1..].iter().filter(|a| **a != "-l") {
+                // words that aren't key names are typed as they are
+                match keys::parse_key_name(k).filter(|_| !literal) {
+                    Some(_) => send_key_to_active(app, k),
+                    None => send_text_to_active(app, k),
+                }.map_err(io_err)?;
+            }
+        }
+        "choose-tree" => { app.mode = Mode::TreeChooser { selected: 0 }; }
+        "display-message" | "display" => {
+            let text = parts[1..].iter().copied().filter(|w| *w != "-p").collect::<Vec<_>>().join(" ");
+            let text = expand_status(if text.is_empty() { DISPLAY_MESSAGE_FORMAT } else { &text }, app);
+            display_message(app, text);
+        }
         "select-window" => {
             if let Some(tidx) = parts.iter().position(|p| *p == "-t").and_then(|i| parts.get(i+1)) { if let Ok(n) = tidx.parse::<usize>() { if n>0 && n<=app.windows.len() { app.active_idx = n-1; } } }
         }
-        _ => {}
+        _ => return Err(format!("unknown command: {}", cmd)),
     }
     Ok(())
 }
 
+impl config::Commands for AppState {
+    fn run(&mut self, args: &[String]) -> Result<(), String> {
+        run_command(self, args)
+    }
+
+    fn expand(&mut self, fmt: &str) -> String {
+        expand_status(fmt, self)
+    }
+
+    fn error(&mut self, msg: String) {
+        self.config_errors.push(msg);
+    }
+}
+
+/// The format given with `-F`: the rest of the line, so it may contain spaces.
+fn format_arg(line: &str) -> Option<String> {
+    line.split_once(" -F ").map(|(_, fmt)| fmt.trim().to_string())
+}
+
+/// Show `text` in the status line of every attached client.
+fn display_message(app: &mut AppState, text: String) {
+    for c in app.clients.iter() { let _ = c.tx.send(ServerMsg::Message { text: text.clone() }); }
+}
+
+/// The directory given with `-c`, for commands that start a shell.
+fn start_dir_arg(parts: &[&str]) -> Option<PathBuf> {
+    parts.iter().position(|p| *p == "-c").and_then(|i| parts.get(i + 1)).map(PathBuf::from)
+}
+
 fn centered_rect(percent_x: u16, height: u16, r: Rect) -> Rect {
     let popup_layout = Layout::default()
         .direction(Direction::Vertical)
@@ -1197,6 +1498,8 @@ fn centered_rect(percent_x: u16, height: u16, r: Rect) -> Rect {
 }
 
 fn reap_children(app: &mut AppState) -> io::Result<bool> {
+    let panes = |app: &AppState| app.windows.iter().map(|w| leaf_panes(&w.root).len()).sum::<usize>();
+    let before = panes(app);
     // Transform each window's split tree by pruning exited leaves.
     for i in (0..app.windows.len()).rev() {
         let root = std::mem::replace(&mut app.windows[i].root, Node::Split { kind: LayoutKind::Horizontal, sizes: vec![], children: vec![] });
@@ -1211,151 +1514,210 @@ fn reap_children(app: &mut AppState) -> io::Result<bool> {
             None => { app.windows.remove(i); }
         }
     }
+    if app.active_idx >= app.windows.len() { app.active_idx = app.windows.len().saturating_sub(1); }
+    if panes(app) != before { app.frame_dirty = true; }
     Ok(app.windows.is_empty())
 }
 
-fn vt_to_color(c: vt100::Color) -> Color {
-    match c {
-        vt100::Color::Default => Color::Reset,
-        vt100::Color::Idx(i) => match i {
-            0 => Color::Black,
-            1 => Color::Red,
-            2 => Color::Green,
-            3 => Color::Yellow,
-            4 => Color::Blue,
-            5 => Color::Magenta,
-            6 => Color::Cyan,
-            7 => Color::Gray,
-            8 => Color::DarkGray,
-            9 => Color::LightRed,
-            10 => Color::LightGreen,
-            11 => Color::LightYellow,
-            12 => Color::LightBlue,
-            13 => Color::LightMagenta,
-            14 => Color::LightCyan,
-            15 => Color::White,
-            _ => Color::Reset,
-        },
-        vt100::Color::Rgb(r, g, b) => Color::Rgb(r, g, b),
-    }
-}
-
-fn apply_cursor_style<W: Write>(out: &mut W) -> io::Result<()> {
-    let style = env::var("PMUX_CURSOR_STYLE").unwrap_or_else(|_| "bar".to_string());
-    let blink = env::var("PMUX_CURSOR_BLINK").unwrap_or_else(|_| "1".to_string()) != "0";
-    let code = match style.as_str() {
+/// DECSCUSR code for the configured `cursor-style`/`cursor-blink`.
+fn cursor_style_code(app: &AppState) -> u8 {
+    let blink = option_flag(app, "cursor-blink");
+    match option(app, "cursor-style").as_str() {
         "block" => if blink { 1 } else { 2 },
         "underline" => if blink { 3 } else { 4 },
         "bar" | "beam" => if blink { 5 } else { 6 },
         _ => if blink { 5 } else { 6 },
+    }
+}
+
+fn enter_copy_mode(app: &mut AppState) { app.mode = Mode::Copy; app.copy_exit_on_bottom = false; }
+
+/// Leave copy mode and return the active pane's view to the live screen.
+fn exit_copy_mode(app: &mut AppState) {
+    app.mode = Mode::Passthrough;
+    app.copy_anchor = None;
+    app.copy_pos = None;
+    app.copy_exit_on_bottom = false;
+    scroll_history(app, isize::MIN);
+}
+
+/// Scroll the active pane's history with the wheel, entering copy mode on the way up.
+fn wheel_scroll(app: &mut AppState, up: bool) {
+    const WHEEL_LINES: isize = 3;
+    if up {
+        if !matches!(app.mode, Mode::Copy) {
+            enter_copy_mode(app);
+            app.copy_exit_on_bottom = true;
+        }
+        scroll_history(app, WHEEL_LINES);
+    } else if matches!(app.mode, Mode::Copy) {
+        scroll_history(app, -WHEEL_LINES);
+    }
+}
+
+/// Move the active pane's view `lines` rows back into its history, or forward when negative.
+fn scroll_history(app: &mut AppState, lines: isize) {
+    let win = &mut app.windows[app.active_idx];
+    let Some(p) = active_pane_mut(&mut win.root, &win.active_path) else { return };
+    let at_bottom = {
+        let mut parser = p.term.lock().unwrap();
+        let screen = parser.screen_mut();
+        let cur = screen.scrollback();
+        // vt100 clamps the offset to the history it actually holds
+        screen.set_scrollback(if lines >= 0 { cur.saturating_add(lines as usize) } else { cur.saturating_sub(lines.unsigned_abs()) });
+        if screen.scrollback() != cur { p.generation.fetch_add(1, Ordering::Relaxed); }
+        screen.scrollback() == 0
     };
-    execute!(out, Print(format!("\x1b[{} q", code)))?;
-    Ok(())
+    if at_bottom && lines < 0 && app.copy_exit_on_bottom && matches!(app.mode, Mode::Copy) { exit_copy_mode(app); }
 }
 
-fn render_window(f: &mut Frame, app: &mut AppState, area: Rect) {
+/// Scroll by `1/divisor` of the active pane's height in direction `dir`.
+fn scroll_history_pages(app: &mut AppState, dir: isize, divisor: u16) {
     let win = &mut app.windows[app.active_idx];
-    render_node(f, &mut win.root, &win.active_path, &mut Vec::new(), area);
+    let rows = active_pane_mut(&mut win.root, &win.active_path).map(|p| p.last_rows).unwrap_or(1);
+    scroll_history(app, dir * (rows / divisor).max(1) as isize);
 }
 
-fn enter_copy_mode(app: &mut AppState) { app.mode = Mode::CopyMode; }
+/// Current scrollback offset and total history length of the active pane.
+fn history_position(app: &mut AppState) -> Option<(usize, usize)> {
+    let win = &mut app.windows[app.active_idx];
+    let p = active_pane_mut(&mut win.root, &win.active_path)?;
+    let mut parser = p.term.lock().ok()?;
+    let screen = parser.screen_mut();
+    let cur = screen.scrollback();
+    screen.set_scrollback(usize::MAX);
+    let total = screen.scrollback();
+    screen.set_scrollback(cur);
+    Some((cur, total))
+}
 
 fn cycle_top_layout(app: &mut AppState) {
     let win = &mut app.windows[app.active_idx];
     // toggle parent of active path, else toggle root
     if !win.active_path.is_empty() {
-        let parent_path = &win.active_path[..win.active_path.len()-1].to_vec();
-        if let Some(Node::Split { kind, sizes, .. }) = get_split_mut(&mut win.root, &parent_path.to_vec()) {
+        let parent_path = win.active_path[..win.active_path.len()-1].to_vec();
+        if let Some(Node::Split { kind, sizes, .. }) = get_split_mut(&mut win.root, &parent_path) {
             *kind = match *kind { LayoutKind::Horizontal => LayoutKind::Vertical, LayoutKind::Vertical => LayoutKind::Horizontal };
             *sizes = vec![50,50];
         }
-    } else {
-        if let Node::Split { kind, sizes, .. } = &mut win.root { *kind = match *kind { LayoutKind::Horizontal => LayoutKind::Vertical, LayoutKind::Vertical => LayoutKind::Horizontal }; *sizes = vec![50,50]; }
+    } else if let Node::Split { kind, sizes, .. } = &mut win.root {
+        *kind = match *kind { LayoutKind::Horizontal => LayoutKind::Vertical, LayoutKind::Vertical => LayoutKind::Horizontal };
+        *sizes = vec![50,50];
+    }
+}
+
+/// Answer terminal queries the reader threads have seen. The reader threads
+/// don't hold the pane's input, so the replies are queued from here.
+fn write_pane_replies(app: &mut AppState) {
+    fn rec(node: &mut Node) {
+        match node {
+            Node::Leaf(p) => {
+                let replies = p.term.lock().unwrap().callbacks_mut().take_replies();
+                if !replies.is_empty() { p.input.write(replies); }
+            }
+            Node::Split { children, .. } => { for c in children.iter_mut() { rec(c); } }
+        }
+    }
+    for win in app.windows.iter_mut() { rec(&mut win.root); }
+}
+
+/// Pick up titles set by programs with OSC 0/2. With `allow-rename` on, the
+/// active pane's title also renames its window.
+fn update_titles(app: &mut AppState) {
+    fn rec(node: &mut Node, active: Option<usize>, win_name: &mut Option<String>, changed: &mut bool) {
+        match node {
+            Node::Leaf(p) => {
+                let Some(title) = p.term.lock().unwrap().callbacks_mut().take_title() else { return };
+                if Some(p.id) == active { *win_name = Some(title.clone()); }
+                p.title = title;
+                *changed = true;
+            }
+            Node::Split { children, .. } => { for c in children.iter_mut() { rec(c, active, win_name, changed); } }
+        }
     }
+    let allow: Vec<bool> = app.windows.iter().enumerate().map(|(i, w)| option_value(app, i, active_pane_ref(&w.root, &w.active_path), "allow-rename") == "on").collect();
+    for (win, allow) in app.windows.iter_mut().zip(allow) {
+        let active = active_pane_mut(&mut win.root, &win.active_path).map(|p| p.id);
+        let mut win_name = None;
+        rec(&mut win.root, active, &mut win_name, &mut app.frame_dirty);
+        if let (true, Some(name)) = (allow, win_name) { rename_window(win, name); }
+    }
+}
+
+/// Rename a window by hand, which turns its `automatic-rename` off.
+fn rename_window(win: &mut Window, name: String) {
+    win.name = name;
+    win.options.set("automatic-rename", "off".to_string());
+}
+
+/// Keep windows with `automatic-rename` named after their active pane's foreground command.
+fn automatic_rename(app: &mut AppState) {
+    if app.last_rename_check.elapsed() < Duration::from_millis(500) { return; }
+    app.last_rename_check = Instant::now();
+    let on: Vec<bool> = (0..app.windows.len()).map(|i| option_value(app, i, None, "automatic-rename") == "on").collect();
+    for (win, _) in app.windows.iter_mut().zip(on).filter(|(_, on)| *on) {
+        if let Some(cmd) = active_pane_ref(&win.root, &win.active_path).and_then(pane_current_command).filter(|cmd| *cmd != win.name) {
+            win.name = cmd;
+            app.frame_dirty = true;
+        }
+    }
+}
+
+/// Command running in the foreground of the pane, or its shell when nothing else is.
+fn pane_current_command(p: &Pane) -> Option<String> {
+    let pid = p.pid?;
+    procinfo::command_name(procinfo::foreground_pid(pid).unwrap_or(pid))
+}
+
+/// Place the pane area in the client's terminal around the status lines.
+fn update_window_area(app: &mut AppState) {
+    let (width, height) = app.client_size;
+    let lines = (status_line_count(app) as u16).min(height);
+    let top = option(app, "status-position") == "top";
+    app.last_window_area = Rect { x: 0, y: if top { lines } else { 0 }, width, height: height - lines };
+}
+
+/// Status lines shown, from the `status` option; 0 hides the status bar.
+fn status_line_count(app: &AppState) -> usize {
+    match option(app, "status").as_str() { "off" => 0, "on" => 1, n => n.parse::<usize>().unwrap_or(1).min(5) }
 }
 
-fn render_node(f: &mut Frame, node: &mut Node, active_path: &Vec<usize>, cur_path: &mut Vec<usize>, area: Rect) {
-    match node {
-        Node::Leaf(pane) => {
-            let is_active = *cur_path == *active_path;
-            let title = if is_active { "* pane" } else { " pane" };
-            let pane_block = Block::default().borders(Borders::ALL).title(title);
-            let inner = pane_block.inner(area);
+/// Resize every pane's PTY and emulator to the cell area it occupies in the attached client.
+fn resize_panes(app: &mut AppState) {
+    let area = app.last_window_area;
+    if area.width == 0 || area.height == 0 { return; }
+    for win in app.windows.iter_mut() {
+        let mut rects: Vec<(Vec<usize>, Rect)> = Vec::new();
+        compute_rects(&win.root, area, &mut rects);
+        for (path, r) in rects.iter() {
+            let Some(pane) = active_pane_mut(&mut win.root, path) else { continue };
+            let inner = Block::default().borders(Borders::ALL).inner(*r);
             let target_rows = inner.height.max(1);
             let target_cols = inner.width.max(1);
             if pane.last_rows != target_rows || pane.last_cols != target_cols {
-                let _ = pane.master.resize(PtySize { rows: target_rows, cols: target_cols, pixel_width: 0, pixel_height: 0 });
+                pane.input.resize(PtySize { rows: target_rows, cols: target_cols, pixel_width: 0, pixel_height: 0 });
                 let mut parser = pane.term.lock().unwrap();
                 parser.screen_mut().set_size(target_rows, target_cols);
+                pane.generation.fetch_add(1, Ordering::Relaxed);
                 pane.last_rows = target_rows;
                 pane.last_cols = target_cols;
             }
-            let parser = pane.term.lock().unwrap();
-            let screen = parser.screen();
-            let mut lines: Vec<Line> = Vec::with_capacity(target_rows as usize);
-            for r in 0..target_rows {
-                let mut spans: Vec<Span> = Vec::with_capacity(target_cols as usize);
-                let mut c = 0;
-                while c < target_cols {
-                    if let Some(cell) = screen.cell(r, c) {
-                        let mut fg = vt_to_color(cell.fgcolor());
-                        let mut bg = vt_to_color(cell.bgcolor());
-                        if cell.inverse() { std::mem::swap(&mut fg, &mut bg); }
-                        let mut style = Style::default().fg(fg).bg(bg);
-                        if cell.bold() { style = style.add_modifier(Modifier::BOLD); }
-                        if cell.italic() { style = style.add_modifier(Modifier::ITALIC); }
-                        if cell.underline() { style = style.add_modifier(Modifier::UNDERLINED); }
-                        let text = cell.contents().to_string();
-                        let w = UnicodeWidthStr::width(text.as_str()) as u16;
-                        if w == 0 {
-                            spans.push(Span::styled(" ", style));
-                            c += 1;
-                        } else if w >= 2 && c + 1 < target_cols {
-                            spans.push(Span::styled(text, style));
-                            spans.push(Span::styled(" ", style));
-                            c += 2;
-                        } else {
-                            spans.push(Span::styled(text, style));
-                            c += 1;
-                        }
-                    } else {
-                        spans.push(Span::raw(" "));
-                        c += 1;
-                    }
-                }
-                lines.push(Line::from(spans));
-            }
-            f.render_widget(pane_block, area);
-            f.render_widget(Clear, inner);
-            let para = Paragraph::new(Text::from(lines)).wrap(Wrap { trim: false });
-            f.render_widget(para, inner);
-            if is_active {
-                let (cr, cc) = screen.cursor_position();
-                let cr = cr.min(target_rows.saturating_sub(1));
-                let cc = cc.min(target_cols.saturating_sub(1));
-                let cx = inner.x + cc;
-                let cy = inner.y + cr;
-                f.set_cursor(cx, cy);
-            }
         }
-        Node::Split { kind, sizes, children } => {
-            let constraints: Vec<Constraint> = if sizes.len() == children.len() {
-                sizes.iter().map(|p| Constraint::Percentage(*p)).collect()
-            } else { vec![Constraint::Percentage((100 / children.len() as u16) as u16); children.len()] };
-            let rects = match *kind {
-                LayoutKind::Horizontal => Layout::default().direction(Direction::Horizontal).constraints(constraints).split(area),
-                LayoutKind::Vertical => Layout::default().direction(Direction::Vertical).constraints(constraints).split(area),
-            };
-            for (i, child) in children.iter_mut().enumerate() {
-                cur_path.push(i);
-                render_node(f, child, active_path, cur_path, rects[i]);
-                cur_path.pop();
-            }
+    }
+}
+
+fn active_pane_ref<'a>(node: &'a Node, path: &[usize]) -> Option<&'a Pane> {
+    let mut cur = node;
+    for &idx in path.iter() {
+        match cur {
+            Node::Split { children, .. } => { cur = children.get(idx)?; }
+            Node::Leaf(_) => return None,
         }
     }
+    match cur { Node::Leaf(p) => Some(p), _ => None }
 }
 
-fn active_pane_mut<'a>(node: &'a mut Node, path: &Vec<usize>) -> Option<&'a mut Pane> {
+fn active_pane_mut<'a>(node: &'a mut Node, path: &[usize]) -> Option<&'a mut Pane> {
     let mut cur = node;
     for &idx in path.iter() {
         match cur {
@@ -1366,7 +1728,7 @@ fn active_pane_mut<'a>(node: &'a mut Node, path: &Vec<usize>) -> Option<&'a mut
     match cur { Node::Leaf(p) => Some(p), _ => None }
 }
 
-fn replace_leaf_with_split(node: &mut Node, path: &Vec<usize>, kind: LayoutKind, new_leaf: Node) {
+fn replace_leaf_with_split(node: &mut Node, path: &[usize], kind: LayoutKind, new_leaf: Node) {
     if path.is_empty() {
         let old = std::mem::replace(node, Node::Split { kind, sizes: vec![50,50], children: vec![] });
         if let Node::Split { children, .. } = node { children.push(old); children.push(new_leaf); }
@@ -1387,11 +1749,11 @@ fn replace_leaf_with_split(node: &mut Node, path: &Vec<usize>, kind: LayoutKind,
     }
 }
 
-fn kill_leaf(node: &mut Node, path: &Vec<usize>) {
+fn kill_leaf(node: &mut Node, path: &[usize]) {
     *node = remove_node(std::mem::replace(node, Node::Split { kind: LayoutKind::Horizontal, sizes: vec![], children: vec![] }), path);
 }
 
-fn remove_node(n: Node, path: &Vec<usize>) -> Node {
+fn remove_node(n: Node, path: &[usize]) -> Node {
     match n {
         Node::Leaf(p) => {
             // if path points here, removing leaf yields an empty split collapse handled by parent; return leaf
@@ -1403,7 +1765,7 @@ fn remove_node(n: Node, path: &Vec<usize>) -> Node {
             let mut new_children: Vec<Node> = Vec::new();
             for (i, child) in children.into_iter().enumerate() {
                 if i == idx {
-                    if path.len() > 1 { new_children.push(remove_node(child, &path[1..].to_vec())); }
+                    if path.len() > 1 { new_children.push(remove_node(child, &path[1..])); }
                     // else: drop this child (removed)
                 } else { new_children.push(child); }
             }
@@ -1426,7 +1788,7 @@ fn compute_rects(node: &Node, area: Rect, out: &mut Vec<(Vec<usize>, Rect)>) {
             Node::Split { kind, sizes, children } => {
                 let constraints: Vec<Constraint> = if sizes.len() == children.len() {
                     sizes.iter().map(|p| Constraint::Percentage(*p)).collect()
-                } else { vec![Constraint::Percentage((100 / children.len() as u16) as u16); children.len()] };
+                } else { vec![Constraint::Percentage(100 / children.len() as u16); children.len()] };
                 let rects = match *kind {
                     LayoutKind::Horizontal => Layout::default().direction(Direction::Horizontal).constraints(constraints).split(area),
                     LayoutKind::Vertical => Layout::default().direction(Direction::Vertical).constraints(constraints).split(area),
@@ -1439,13 +1801,6 @@ fn compute_rects(node: &Node, area: Rect, out: &mut Vec<(Vec<usize>, Rect)>) {
     rec(node, area, &mut path, out);
 }
 
-fn kill_all_children(node: &mut Node) {
-    match node {
-        Node::Leaf(p) => { let _ = p.child.kill(); }
-        Node::Split { children, .. } => { for child in children.iter_mut() { kill_all_children(child); } }
-    }
-}
-
 fn compute_split_borders(node: &Node, area: Rect, out: &mut Vec<(Vec<usize>, LayoutKind, usize, u16)>) {
     fn rec(node: &Node, area: Rect, path: &mut Vec<usize>, out: &mut Vec<(Vec<usize>, LayoutKind, usize, u16)>) {
         match node {
@@ -1453,15 +1808,15 @@ fn compute_split_borders(node: &Node, area: Rect, out: &mut Vec<(Vec<usize>, Lay
             Node::Split { kind, sizes, children } => {
                 let constraints: Vec<Constraint> = if sizes.len() == children.len() {
                     sizes.iter().map(|p| Constraint::Percentage(*p)).collect()
-                } else { vec![Constraint::Percentage((100 / children.len() as u16) as u16); children.len()] };
+                } else { vec![Constraint::Percentage(100 / children.len() as u16); children.len()] };
                 let rects = match *kind {
                     LayoutKind::Horizontal => Layout::default().direction(Direction::Horizontal).constraints(constraints).split(area),
                     LayoutKind::Vertical => Layout::default().direction(Direction::Vertical).constraints(constraints).split(area),
                 };
-                for i in 0..children.len()-1 {
+                for (i, r) in rects.iter().enumerate().take(children.len().saturating_sub(1)) {
                     let pos = match *kind {
-                        LayoutKind::Horizontal => rects[i].x + rects[i].width,
-                        LayoutKind::Vertical => rects[i].y + rects[i].height,
+                        LayoutKind::Horizontal => r.x + r.width,
+                        LayoutKind::Vertical => r.y + r.height,
                     };
                     out.push((path.clone(), *kind, i, pos));
                 }
@@ -1473,7 +1828,7 @@ fn compute_split_borders(node: &Node, area: Rect, out: &mut Vec<(Vec<usize>, Lay
     rec(node, area, &mut path, out);
 }
 
-fn split_sizes_at<'a>(node: &'a Node, path: Vec<usize>, idx: usize) -> Option<(u16,u16)> {
+fn split_sizes_at(node: &Node, path: Vec<usize>, idx: usize) -> Option<(u16,u16)> {
     let mut cur = node;
     for &i in path.iter() {
         match cur { Node::Split { children, .. } => { cur = children.get(i)?; } _ => return None }
@@ -1498,7 +1853,7 @@ fn adjust_split_sizes(root: &mut Node, d: &DragState, x: u16, y: u16) {
     }
 }
 
-fn get_split_mut<'a>(node: &'a mut Node, path: &Vec<usize>) -> Option<&'a mut Node> {
+fn get_split_mut<'a>(node: &'a mut Node, path: &[usize]) -> Option<&'a mut Node> {
     let mut cur = node;
     for &idx in path.iter() {
         match cur { Node::Split { children, .. } => { cur = children.get_mut(idx)?; } _ => return None }
@@ -1514,11 +1869,14 @@ fn prune_exited(n: Node) -> Option<Node> {
                 _ => Some(Node::Leaf(p)),
             }
         }
-        Node::Split { kind, sizes: _sizes, children } => {
+        Node::Split { kind, sizes, children } => {
+            let count = children.len();
             let mut new_children: Vec<Node> = Vec::new();
             for child in children { if let Some(c) = prune_exited(child) { new_children.push(c); } }
             if new_children.is_empty() { None }
             else if new_children.len() == 1 { Some(new_children.remove(0)) }
+            // nothing exited here: keep the sizes the user dragged to
+            else if new_children.len() == count { Some(Node::Split { kind, sizes, children: new_children }) }
             else {
                 // equalize sizes
                 let mut eq = vec![100 / new_children.len() as u16; new_children.len()];
@@ -1530,89 +1888,412 @@ fn prune_exited(n: Node) -> Option<Node> {
     }
 }
 
-fn expand_status(fmt: &str, app: &AppState, time_str: &str) -> String {
-    let mut s = fmt.to_string();
-    let window = &app.windows[app.active_idx];
-    s = s.replace("#I", &(app.active_idx + 1).to_string());
-    s = s.replace("#W", &window.name);
-    s = s.replace("#S", "pmux");
-    s = s.replace("%H:%M", time_str);
-    s
-}
-
-fn load_config(app: &mut AppState) {
-    let home = env::var("USERPROFILE").or_else(|_| env::var("HOME")).unwrap_or_default();
-    let path = format!("{}\\.pmux.conf", home);
-    if let Ok(content) = std::fs::read_to_string(&path) {
-        for line in content.lines() {
-            let l = line.trim();
-            if l.is_empty() || l.starts_with('#') { continue; }
-            if l.starts_with("set -g ") {
-                let rest = &l[7..];
-                if let Some((key, value)) = rest.split_once(' ') {
-                    match key.trim() {
-                        "status-left" => app.status_left = value.trim().to_string(),
-                        "status-right" => app.status_right = value.trim().to_string(),
-                        "mouse" => app.mouse_enabled = matches!(value.trim(), "on" | "true"),
-                        "cursor-style" => env::set_var("PMUX_CURSOR_STYLE", value.trim()),
-                        "cursor-blink" => env::set_var("PMUX_CURSOR_BLINK", if matches!(value.trim(), "on"|"true") { "1" } else { "0" }),
-                        "prefix" => {
-                            if value.trim().eq_ignore_ascii_case("C-b") { app.prefix_key = (KeyCode::Char('b'), KeyModifiers::CONTROL); }
-                            if value.trim().eq_ignore_ascii_case("C-a") { app.prefix_key = (KeyCode::Char('a'), KeyModifiers::CONTROL); }
-                        }
-                        _ => {}
-                    }
-                }
+/// Expand a status-line format, strftime sequences included, for the active window and pane.
+fn expand_status(fmt: &str, app: &AppState) -> String {
+    let pane = app.windows.get(app.active_idx).and_then(|w| active_pane_ref(&w.root, &w.active_path));
+    expand_format(app, app.active_idx, pane, &format::strftime(fmt, &Local::now()))
+}
+
+/// Expand `fmt` for window `win_idx` and one of its panes.
+fn expand_format(app: &AppState, win_idx: usize, pane: Option<&Pane>, fmt: &str) -> String {
+    format::expand(fmt, &FormatTarget { app, win_idx, pane })
+}
+
+/// A window of the session and one of its panes, which formats are expanded against.
+struct FormatTarget<'a> { app: &'a AppState, win_idx: usize, pane: Option<&'a Pane> }
+
+impl format::Context for FormatTarget<'_> {
+    fn var(&self, name: &str) -> Option<String> {
+        format_var(self.app, self.win_idx, self.pane, name)
+    }
+
+    fn command(&self, cmd: &str) -> String {
+        self.app.jobs.borrow_mut().output(cmd, Duration::from_secs(option_number(self.app, "status-interval")))
+    }
+}
+
+/// The value of format variable `name` for window `win_idx` and `pane`.
+fn format_var(app: &AppState, win_idx: usize, pane: Option<&Pane>, name: &str) -> Option<String> {
+    let flag = |b: bool| if b { "1" } else { "0" }.to_string();
+    let session = match name {
+        "session_name" => Some(app.session_name.clone()),
+        "session_windows" => Some(app.windows.len().to_string()),
+        "session_attached" => Some((app.attached_clients + app.clients.len()).to_string()),
+        "session_created" => Some(app.created_at.timestamp().to_string()),
+        "client_width" => Some(app.client_size.0.to_string()),
+        "client_height" => Some(app.client_size.1.to_string()),
+        "client_prefix" => Some(flag(app.key_table.as_ref().is_some_and(|t| t.name == "prefix"))),
+        "client_key_table" => Some(app.key_table.as_ref().map_or("root", |t| t.name.as_str()).to_string()),
+        "pid" => Some(std::process::id().to_string()),
+        "version" => Some(VERSION.to_string()),
+        // options by name, as they apply to this window and pane
+        n if n.starts_with('@') || options::find(n).is_some() => Some(option_value(app, win_idx, pane, n)),
+        _ => None,
+    };
+    if session.is_some() { return session; }
+    let win = app.windows.get(win_idx)?;
+    let active = win_idx == app.active_idx;
+    let v = match name {
+        "window_index" => (win_idx + 1).to_string(),
+        "window_id" => format!("@{}", win.id),
+        "window_name" => win.name.clone(),
+        "window_active" => flag(active),
+        "window_panes" => leaf_panes(&win.root).len().to_string(),
+        "window_zoomed_flag" => flag(active && app.zoom_saved.is_some()),
+        "window_flags" => format!("{}{}", if active { "*" } else { "" }, if active && app.zoom_saved.is_some() { "Z" } else { "" }),
+        _ => {
+            let p = pane?;
+            let panes = leaf_panes(&win.root);
+            let index = panes.iter().position(|(_, q)| q.id == p.id)?;
+            let pane_active = panes[index].0 == win.active_path;
+            match name {
+                "pane_id" => format!("%{}", p.id),
+                "pane_index" => index.to_string(),
+                "pane_title" => p.title.clone(),
+                "pane_active" => flag(pane_active),
+                "pane_width" => p.last_cols.to_string(),
+                "pane_height" => p.last_rows.to_string(),
+                "pane_pid" => p.pid?.to_string(),
+                "pane_current_command" => pane_current_command(p)?,
+                "pane_current_path" => pane_current_path(p)?.display().to_string(),
+                "pane_in_mode" => flag(active && pane_active && matches!(app.mode, Mode::Copy)),
+                "pane_synchronized" => flag(synchronized(app, win_idx)),
+                _ => return None,
             }
         }
+    };
+    Some(v)
+}
+
+/// Every pane of a split tree with its path, in layout order.
+fn leaf_panes(node: &Node) -> Vec<(Vec<usize>, &Pane)> {
+    fn rec<'a>(node: &'a Node, path: &mut Vec<usize>, out: &mut Vec<(Vec<usize>, &'a Pane)>) {
+        match node {
+            Node::Leaf(p) => out.push((path.clone(), p)),
+            Node::Split { children, .. } => {
+                for (i, c) in children.iter().enumerate() { path.push(i); rec(c, path, out); path.pop(); }
+            }
+        }
+    }
+    let mut out = Vec::new();
+    rec(node, &mut Vec::new(), &mut out);
+    out
+}
+
+/// Run the configuration: `file` when given with `-f`, otherwise whichever
+/// of the default files exist.
+fn load_config(app: &mut AppState, file: Option<&Path>) {
+    match file {
+        Some(f) => config::source_file(f, app),
+        None => { for f in config::default_files().iter().filter(|f| f.exists()) { config::source_file(f, app); } }
+    }
+    for e in &app.config_errors { server_log(&app.session_name, e); }
+}
+
+/// Option `name` for window `win_idx` and `pane`, as `options::Levels` finds it.
+fn option_value(app: &AppState, win_idx: usize, pane: Option<&Pane>, name: &str) -> String {
+    options::Levels {
+        pane: pane.map(|p| &p.options),
+        window: app.windows.get(win_idx).map(|w| &w.options),
+        global_window: &app.global_window_options,
+        session: &app.session_options,
+        global: &app.global_options,
+        server: &app.server_options,
+    }.value(name)
+}
+
+/// Option `name` where the active window and pane are.
+fn option(app: &AppState, name: &str) -> String {
+    let pane = app.windows.get(app.active_idx).and_then(|w| active_pane_ref(&w.root, &w.active_path));
+    option_value(app, app.active_idx, pane, name)
+}
+
+fn option_flag(app: &AppState, name: &str) -> bool {
+    option(app, name) == "on"
+}
+
+fn option_number(app: &AppState, name: &str) -> u64 {
+    option(app, name).parse().unwrap_or(0)
+}
+
+/// The key set in option `name`, `prefix` or `prefix2`; `None` turns it off.
+fn prefix_key(app: &AppState, name: &str) -> Option<(KeyCode, KeyModifiers)> {
+    keys::parse_key_name(&option(app, name)).map(|key| keys::binding_key(&key))
+}
+
+/// The window and pane a `-t` target names: `N` or `:N` for a window by
+/// index, `@N` for a window and `%N` for a pane by id. None is the active pane.
+fn resolve_target(app: &AppState, target: Option<&str>) -> Result<(usize, Option<Vec<usize>>), String> {
+    let active = |idx: usize| app.windows.get(idx).map(|w| w.active_path.clone());
+    let Some(t) = target else { return Ok((app.active_idx, active(app.active_idx))) };
+    let found = if let Some(id) = t.strip_prefix('%').and_then(|n| n.parse::<usize>().ok()) {
+        app.windows.iter().enumerate().find_map(|(i, w)| leaf_panes(&w.root).into_iter().find(|(_, p)| p.id == id).map(|(path, _)| (i, Some(path))))
+    } else if let Some(id) = t.strip_prefix('@').and_then(|n| n.parse::<usize>().ok()) {
+        find_window_index_by_id(app, id).map(|i| (i, active(i)))
+    } else {
+        t.trim_start_matches(':').parse::<usize>().ok().and_then(|n| n.checked_sub(1)).filter(|&i| i < app.windows.len()).map(|i| (i, active(i)))
+    };
+    found.ok_or_else(|| format!("can't find target: {}", t))
+}
+
+/// Flags and `-t` target at the start of `set-option` and `show-options` arguments, and the rest.
+fn option_flags<'a>(args: &'a [&'a str]) -> (String, Option<&'a str>, &'a [&'a str]) {
+    let (mut flags, mut target, mut i) = (String::new(), None, 0);
+    while let Some(a) = args.get(i).filter(|a| a.len() > 1 && a.starts_with('-')) {
+        if *a == "-t" { target = args.get(i + 1).copied(); i += 2; continue; }
+        flags.push_str(&a[1..]);
+        i += 1;
+    }
+    (flags, target, &args[i.min(args.len())..])
+}
+
+/// Scope an option is set or shown at: its own, or for user options the one the flags ask for.
+fn option_scope(name: Option<&str>, flags: &str, window_cmd: bool) -> Scope {
+    match name.and_then(options::find) {
+        // a pane option set for a window is inherited by its panes
+        Some(d) if d.scope == Scope::Pane && (window_cmd || flags.contains('w')) => Scope::Window,
+        Some(d) => d.scope,
+        None if flags.contains('s') => Scope::Server,
+        None if flags.contains('p') => Scope::Pane,
+        None if window_cmd || flags.contains('w') => Scope::Window,
+        None => Scope::Session,
+    }
+}
+
+/// The option level `set-option` and `show-options` work on.
+fn option_layer(app: &mut AppState, scope: Scope, global: bool, target: (usize, Option<Vec<usize>>)) -> Result<&mut options::Options, String> {
+    Ok(match scope {
+        Scope::Server => &mut app.server_options,
+        Scope::Session if global => &mut app.global_options,
+        Scope::Session => &mut app.session_options,
+        Scope::Window | Scope::Pane if global => &mut app.global_window_options,
+        Scope::Window => &mut app.windows.get_mut(target.0).ok_or("no current window")?.options,
+        Scope::Pane => {
+            let win = app.windows.get_mut(target.0).ok_or("no current window")?;
+            &mut active_pane_mut(&mut win.root, &target.1.unwrap_or_default()).ok_or("no current pane")?.options
+        }
+    })
+}
+
+/// `set-option [-agpqsuw] [-t target] option [value]`; `setw` is `set-option -w`.
+fn set_option(app: &mut AppState, args: &[&str], window_cmd: bool) -> Result<(), String> {
+    let (flags, target, rest) = option_flags(args);
+    let Some((&name, value)) = rest.split_first() else { return Err("usage: set-option [-agpqsuw] [-t target] option [value]".to_string()) };
+    let value = value.join(" ");
+    let def = options::find(name);
+    if def.is_none() && !name.starts_with('@') {
+        return if flags.contains('q') { Ok(()) } else { Err(format!("invalid option: {}", name)) };
+    }
+    let target = resolve_target(app, target)?;
+    let old_prefix = matches!(name, "prefix" | "prefix2").then(|| prefix_key(app, name));
+    let current = {
+        let pane = app.windows.get(target.0).zip(target.1.as_ref()).and_then(|(w, path)| active_pane_ref(&w.root, path));
+        option_value(app, target.0, pane, name)
+    };
+    let new = if flags.contains('u') {
+        None
+    } else {
+        let value = if flags.contains('a') { format!("{}{}", current, value) } else { value };
+        Some(match def { Some(d) => options::parse(d, &value, &current)?, None => value })
+    };
+    let layer = option_layer(app, option_scope(Some(name), &flags, window_cmd), flags.contains('g'), target)?;
+    match new {
+        Some(v) => layer.set(name, v),
+        None => layer.unset(name),
+    }
+    if let Some(old) = old_prefix { rebind_send_prefix(app, name, old); }
+    // the status lines may have moved or changed in number
+    update_window_area(app);
+    Ok(())
+}
+
+/// Move the `send-prefix` binding of option `name`, `prefix` or `prefix2`,
+/// from its `old` key to the one it is set to now.
+fn rebind_send_prefix(app: &mut AppState, name: &str, old: Option<(KeyCode, KeyModifiers)>) {
+    let new = prefix_key(app, name);
+    if new == old { return; }
+    let command: Vec<String> = if name == "prefix2" { vec!["send-prefix".to_string(), "-2".to_string()] } else { vec!["send-prefix".to_string()] };
+    // a key the user has bound to something else keeps that binding
+    if let Some(old) = old.filter(|k| app.key_tables.get("prefix", *k).is_some_and(|b| b.commands == [command.clone()])) {
+        app.key_tables.unbind("prefix", Some(old));
     }
+    if let Some(key) = new { app.key_tables.bind("prefix", bindings::Binding { key, repeat: false, commands: vec![command] }); }
 }
 
-fn parse_status(fmt: &str, app: &AppState, time_str: &str) -> Vec<Span<'static>> {
+/// `list-keys [-T table]`: the bindings as `bind-key` commands, one per line.
+fn list_keys(app: &AppState, args: &[&str]) -> Result<String, String> {
+    let table = match args {
+        [] => None,
+        ["-T", table] => Some(*table),
+        _ => return Err("usage: list-keys [-T table]".to_string()),
+    };
+    if let Some(t) = table.filter(|t| !app.key_tables.has_table(t)) { return Err(format!("table {} doesn't exist", t)); }
+    Ok(app.key_tables.list(table).into_iter().map(|l| l + "\n").collect())
+}
+
+/// `show-options [-gpqsvw] [-t target] [option]`: the values set at one
+/// level, one `name value` line each. Global levels list every option of
+/// their scope, defaults included.
+fn show_options(app: &mut AppState, args: &[&str], window_cmd: bool) -> Result<String, String> {
+    let (flags, target, rest) = option_flags(args);
+    let name = rest.first().copied();
+    if let Some(n) = name.filter(|n| options::find(n).is_none() && !n.starts_with('@')) {
+        return if flags.contains('q') { Ok(String::new()) } else { Err(format!("invalid option: {}", n)) };
+    }
+    let scope = option_scope(name, &flags, window_cmd);
+    let global = flags.contains('g') || scope == Scope::Server;
+    let target = resolve_target(app, target)?;
+    let layer = option_layer(app, scope, global, target)?;
+    let listed = |d: &options::Def| match name { Some(n) => options::find(n).is_some_and(|f| f.name == d.name), None => d.scope == scope };
+    let mut values: Vec<(String, String)> = Vec::new();
+    for d in options::TABLE.iter().filter(|d| listed(d)) {
+        if d.array {
+            values.extend(layer.iter().filter(|(k, _)| k.strip_prefix(d.name).is_some_and(|i| i.starts_with('['))).map(|(k, v)| (k.to_string(), v.to_string())));
+        } else if let Some(v) = layer.get(d.name) {
+            values.push((d.name.to_string(), v.to_string()));
+        } else if global {
+            values.push((d.name.to_string(), d.default.to_string()));
+        }
+    }
+    values.extend(layer.iter().filter(|(k, _)| k.starts_with('@')).map(|(k, v)| (k.to_string(), v.to_string())));
+    if let Some(n) = name { values.retain(|(k, _)| k == n); }
+    let mut out = String::new();
+    for (k, v) in values {
+        if flags.contains('v') { out.push_str(&v); } else { out.push_str(&format!("{} {}", k, config::quote(&v))); }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Split expanded status text into spans at its `#[...]` style markers,
+/// starting from `base`, which `#[default]` returns to.
+fn parse_status(fmt: &str, base: Style) -> Vec<Span<'static>> {
     let mut spans: Vec<Span<'static>> = Vec::new();
-    let mut cur_style = Style::default();
+    let mut cur_style = base;
     let mut i = 0;
     while i < fmt.len() {
         if fmt.as_bytes()[i] == b'#' && i + 1 < fmt.len() && fmt.as_bytes()[i+1] == b'[' {
             // parse style token #[...]
-            if let Some(end) = fmt[i+2..].find(']') { 
-                let token = &fmt[i+2..i+2+end];
-                for part in token.split(',') {
-                    let p = part.trim();
-                    if p.starts_with("fg=") { cur_style = cur_style.fg(map_color(&p[3..])); }
-                    else if p.starts_with("bg=") { cur_style = cur_style.bg(map_color(&p[3..])); }
-                    else if p == "bold" { cur_style = cur_style.add_modifier(Modifier::BOLD); }
-                    else if p == "italic" { cur_style = cur_style.add_modifier(Modifier::ITALIC); }
-                    else if p == "underline" { cur_style = cur_style.add_modifier(Modifier::UNDERLINED); }
-                    else if p == "default" { cur_style = Style::default(); }
-                }
-                i += 2 + end + 1; 
+            if let Some(end) = fmt[i+2..].find(']') {
+                cur_style = parse_style(&fmt[i+2..i+2+end], cur_style, base);
+                i += 2 + end + 1;
                 continue;
             }
         }
-        // regular text, expand placeholders
-        let mut j = i;
+        // regular text up to the next style marker
+        let mut j = i + 1;
         while j < fmt.len() && !(fmt.as_bytes()[j] == b'#' && j + 1 < fmt.len() && fmt.as_bytes()[j+1] == b'[') { j += 1; }
-        let chunk = &fmt[i..j];
-        let text = expand_status(chunk, app, time_str);
-        spans.push(Span::styled(text, cur_style));
+        spans.push(Span::styled(fmt[i..j].to_string(), cur_style));
         i = j;
     }
     spans
 }
 
+/// Apply a tmux style such as `fg=black,bg=green,bold` to `style`; `default` goes back to `base`.
+fn parse_style(token: &str, mut style: Style, base: Style) -> Style {
+    for part in token.split(',') {
+        let p = part.trim();
+        if let Some(c) = p.strip_prefix("fg=") { style = style.fg(map_color(c)); }
+        else if let Some(c) = p.strip_prefix("bg=") { style = style.bg(map_color(c)); }
+        else if p == "bold" { style = style.add_modifier(Modifier::BOLD); }
+        else if p == "italic" || p == "italics" { style = style.add_modifier(Modifier::ITALIC); }
+        else if p == "underline" || p == "underscore" { style = style.add_modifier(Modifier::UNDERLINED); }
+        else if p == "reverse" { style = style.add_modifier(Modifier::REVERSED); }
+        else if p == "default" || p == "none" { style = base; }
+    }
+    style
+}
+
+/// The status lines: the first has the left and right regions with the window
+/// list between them, any others come from `status-format[N]`.
+fn status_lines(app: &AppState) -> Vec<StatusLineJson> {
+    let style = styled_json(&Span::styled("", parse_style(&option(app, "status-style"), Style::default(), Style::default())));
+    let region = |fmt: &str, style: &str| -> Vec<StyledJson> {
+        let base = parse_style(style, Style::default(), Style::default());
+        parse_status(&expand_status(fmt, app), base).iter().map(styled_json).collect()
+    };
+    let mut windows: Vec<StyledJson> = Vec::new();
+    for (i, w) in app.windows.iter().enumerate() {
+        let pane = active_pane_ref(&w.root, &w.active_path);
+        let separator = option_value(app, i, pane, "window-status-separator");
+        if i > 0 && !separator.is_empty() { windows.push(styled_json(&Span::raw(separator))); }
+        let (fmt, style) = if i == app.active_idx { ("window-status-current-format", "window-status-current-style") } else { ("window-status-format", "window-status-style") };
+        let base = parse_style(&option_value(app, i, pane, style), Style::default(), Style::default());
+        let text = expand_format(app, i, pane, &format::strftime(&option_value(app, i, pane, fmt), &Local::now()));
+        windows.extend(parse_status(&text, base).iter().map(styled_json));
+    }
+    let mut lines = Vec::new();
+    for n in 0..status_line_count(app) {
+        let line = match n {
+            0 => StatusLineJson {
+                style: style.clone(),
+                left: region(&option(app, "status-left"), &option(app, "status-left-style")),
+                windows: std::mem::take(&mut windows),
+                right: region(&option(app, "status-right"), &option(app, "status-right-style")),
+                justify: option(app, "status-justify"),
+            },
+            n => StatusLineJson {
+                style: style.clone(),
+                left: region(&option(app, &format!("status-format[{}]", n)), ""),
+                windows: Vec::new(),
+                right: Vec::new(),
+                justify: "left".to_string(),
+            },
+        };
+        lines.push(line);
+    }
+    lines
+}
+
+fn styled_json(s: &Span) -> StyledJson {
+    StyledJson {
+        text: s.content.to_string(),
+        fg: s.style.fg.map(color_name),
+        bg: s.style.bg.map(color_name),
+        bold: s.style.add_modifier.contains(Modifier::BOLD),
+        italic: s.style.add_modifier.contains(Modifier::ITALIC),
+        underline: s.style.add_modifier.contains(Modifier::UNDERLINED),
+        reverse: s.style.add_modifier.contains(Modifier::REVERSED),
+    }
+}
+
+/// tmux colour names: the eight ANSI names and their `bright` forms, `colour0`
+/// to `colour255`, and `#rrggbb`; also the `idx:N` and `rgb:r,g,b` forms of CellJson.
 fn map_color(name: &str) -> Color {
-    match name.to_lowercase().as_str() {
-        "black" => Color::Black,
-        "red" => Color::Red,
-        "green" => Color::Green,
-        "yellow" => Color::Yellow,
-        "blue" => Color::Blue,
-        "magenta" => Color::Magenta,
-        "cyan" => Color::Cyan,
-        "white" => Color::White,
-        "default" => Color::Reset,
-        _ => Color::Reset,
+    let name = name.to_lowercase();
+    if let Some(hex) = name.strip_prefix('#').filter(|h| h.len() == 6) {
+        if let Ok(v) = u32::from_str_radix(hex, 16) { return Color::Rgb((v >> 16) as u8, (v >> 8) as u8, v as u8); }
+    }
+    if let Some(n) = name.strip_prefix("colour").or_else(|| name.strip_prefix("color")).or_else(|| name.strip_prefix("idx:")) {
+        if let Ok(i) = n.parse::<u8>() { return colors::indexed(i); }
+    }
+    // the `rgb:r,g,b` form used by CellJson
+    if let Some(rgb) = name.strip_prefix("rgb:") {
+        let v: Vec<u8> = rgb.split(',').filter_map(|x| x.parse().ok()).collect();
+        if let [r, g, b] = v[..] { return Color::Rgb(r, g, b); }
+    }
+    const NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+    let (base, bright) = match name.strip_prefix("bright") { Some(b) => (b, true), None => (name.as_str(), false) };
+    match NAMES.iter().position(|n| *n == base) {
+        Some(i) => colors::indexed(i as u8 + if bright { 8 } else { 0 }),
+        None => Color::Reset,
+    }
+}
+
+/// Inverse of `map_color` for colors produced by status formats.
+fn color_name(c: Color) -> String {
+    let named = match c {
+        Color::Black => Some(0), Color::Red => Some(1), Color::Green => Some(2), Color::Yellow => Some(3),
+        Color::Blue => Some(4), Color::Magenta => Some(5), Color::Cyan => Some(6), Color::Gray => Some(7),
+        Color::DarkGray => Some(8), Color::LightRed => Some(9), Color::LightGreen => Some(10), Color::LightYellow => Some(11),
+        Color::LightBlue => Some(12), Color::LightMagenta => Some(13), Color::LightCyan => Some(14), Color::White => Some(15),
+        Color::Indexed(i) => Some(i),
+        _ => None,
+    };
+    match (c, named) {
+        (Color::Rgb(r, g, b), _) => format!("#{:02x}{:02x}{:02x}", r, g, b),
+        (_, Some(i)) => format!("colour{}", i),
+        _ => "default".to_string(),
     }
 }
 
@@ -1646,7 +2327,7 @@ fn yank_selection(app: &mut AppState) -> io::Result<()> {
     let mut text = String::new();
     for r in r0..=r1 {
         for c in c0..=c1 {
-            if let Some(cell) = screen.cell(r, c) { text.push_str(&cell.contents().to_string()); } else { text.push(' '); }
+            if let Some(cell) = screen.cell(r, c) { text.push_str(cell.contents()); } else { text.push(' '); }
         }
         if r < r1 { text.push('\n'); }
     }
@@ -1655,14 +2336,38 @@ fn yank_selection(app: &mut AppState) -> io::Result<()> {
 }
 
 fn paste_latest(app: &mut AppState) -> io::Result<()> {
-    if let Some(buf) = app.paste_buffers.last() {
-        let win = &mut app.windows[app.active_idx];
-        if let Some(p) = active_pane_mut(&mut win.root, &win.active_path) { let _ = write!(p.master, "{}", buf); }
-    }
+    if let Some(buf) = app.paste_buffers.last().cloned() { paste_to_active(app, &buf); }
     Ok(())
 }
 
-fn path_exists(node: &Node, path: &Vec<usize>) -> bool {
+fn paste_to_active(app: &mut AppState, text: &str) {
+    let sync = synchronized(app, app.active_idx);
+    for p in input_panes(&mut app.windows[app.active_idx], sync) { write_paste(p, text); }
+}
+
+/// Write pasted text the way a terminal would, in chunks so a large paste
+/// neither overruns the PTY nor holds up a resize queued behind it.
+fn write_paste(pane: &mut Pane, text: &str) {
+    let bracketed = pane.term.lock().unwrap().screen().bracketed_paste();
+    for chunk in paste_chunks(text, bracketed) { pane.input.write(chunk); }
+}
+
+/// Largest piece of a paste written at once.
+const PASTE_CHUNK: usize = 1024;
+
+/// The bytes of a paste, newlines as CR and wrapped in bracketed-paste
+/// markers when the application asked for them, split into `PASTE_CHUNK`s.
+fn paste_chunks(text: &str, bracketed: bool) -> Vec<Vec<u8>> {
+    // an embedded end marker would let pasted text escape the bracket
+    let body = text.replace("\r\n", "\r").replace('\n', "\r").replace("\x1b[201~", "");
+    let mut data = Vec::with_capacity(body.len() + 12);
+    if bracketed { data.extend_from_slice(b"\x1b[200~"); }
+    data.extend_from_slice(body.as_bytes());
+    if bracketed { data.extend_from_slice(b"\x1b[201~"); }
+    data.chunks(PASTE_CHUNK).map(<[u8]>::to_vec).collect()
+}
+
+fn path_exists(node: &Node, path: &[usize]) -> bool {
     let mut cur = node;
     for &idx in path.iter() {
         match cur {
@@ -1698,7 +2403,7 @@ fn capture_active_pane(app: &mut AppState) -> io::Result<()> {
     let parser = match p.term.lock() { Ok(g) => g, Err(_) => return Ok(()) };
     let screen = parser.screen();
     let mut text = String::new();
-    for r in 0..p.last_rows { for c in 0..p.last_cols { if let Some(cell) = screen.cell(r, c) { text.push_str(&cell.contents().to_string()); } else { text.push(' '); } } text.push('\n'); }
+    for r in 0..p.last_rows { for c in 0..p.last_cols { if let Some(cell) = screen.cell(r, c) { text.push_str(cell.contents()); } else { text.push(' '); } } text.push('\n'); }
     app.paste_buffers.push(text);
     Ok(())
 }
@@ -1709,7 +2414,7 @@ fn capture_active_pane_text(app: &mut AppState) -> io::Result<Option<String>> {
     let parser = match p.term.lock() { Ok(g) => g, Err(_) => return Ok(None) };
     let screen = parser.screen();
     let mut text = String::new();
-    for r in 0..p.last_rows { for c in 0..p.last_cols { if let Some(cell) = screen.cell(r, c) { text.push_str(&cell.contents().to_string()); } else { text.push(' '); } } text.push('\n'); }
+    for r in 0..p.last_rows { for c in 0..p.last_cols { if let Some(cell) = screen.cell(r, c) { text.push_str(cell.contents()); } else { text.push(' '); } } text.push('\n'); }
     Ok(Some(text))
 }
 
@@ -1718,14 +2423,9 @@ fn save_latest_buffer(app: &mut AppState, file: &str) -> io::Result<()> {
     Ok(())
 }
 
-#[derive(Clone)]
-enum Action { DisplayPanes, MoveFocus(FocusDir) }
-
-#[derive(Clone)]
-struct Bind { key: (KeyCode, KeyModifiers), action: Action }
 enum CtrlReq {
-    NewWindow,
-    SplitWindow(LayoutKind),
+    NewWindow(Option<PathBuf>),
+    SplitWindow(LayoutKind, Option<PathBuf>),
     KillPane,
     CapturePane(mpsc::Sender<String>),
     FocusWindow(usize),
@@ -1735,8 +2435,15 @@ enum CtrlReq {
     ClientAttach,
     ClientDetach,
     DumpLayout(mpsc::Sender<String>),
+    DumpFrame(mpsc::Sender<String>),
     SendText(String),
+    Paste(String),
     SendKey(String),
+    Key(KeyEvent, mpsc::Sender<bool>),
+    Attach(usize, mpsc::Sender<ServerMsg>, mpsc::Receiver<()>),
+    ClientKey(usize, KeyEvent),
+    Detach(usize),
+    Mouse(crossterm::event::MouseEvent),
     ZoomPane,
     CopyEnter,
     CopyMove(i16, i16),
@@ -1745,17 +2452,17 @@ enum CtrlReq {
     ClientSize(u16, u16),
     FocusPaneCmd(usize),
     FocusWindowCmd(usize),
-    MouseDown(u16,u16),
-    MouseDrag(u16,u16),
-    MouseUp(u16,u16),
-    ScrollUp,
-    ScrollDown,
     NextWindow,
     PrevWindow,
     RenameWindow(String),
-    ListWindows(mpsc::Sender<String>),
+    ListWindows(Option<String>, mpsc::Sender<String>),
+    ListPanes(Option<String>, bool, mpsc::Sender<String>),
     ListTree(mpsc::Sender<String>),
+    DisplayMessage(Option<String>, Option<mpsc::Sender<String>>),
+    Command(Vec<String>, mpsc::Sender<String>),
     ToggleSync,
+    MarkPane,
+    ClearMarks,
     SetPaneTitle(String),
 }
 
@@ -1778,197 +2485,446 @@ fn focus_pane_by_id(app: &mut AppState, pid: usize) {
     rec(&win.root, &mut Vec::new(), &mut found, pid);
     if let Some(p) = found { win.active_path = p; }
 }
-fn run_server(session_name: String) -> io::Result<()> {
+
+/// The session server: owns every pane and serves attached clients and one-shot CLI commands.
+fn run_server(session_name: String, config_file: Option<PathBuf>) -> io::Result<()> {
+    // panes must not start nested sessions
+    env::set_var("PMUX_ACTIVE", "1");
     let pty_system = PtySystemSelection::default()
         .get()
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("pty system error: {e}")))?;
+        .map_err(|e| io::Error::other(format!("pty system error: {e}")))?;
 
-    let mut app = AppState {
-        windows: Vec::new(),
-        active_idx: 0,
-        mode: Mode::Passthrough,
-        escape_time_ms: 500,
-        prefix_key: (KeyCode::Char('b'), KeyModifiers::CONTROL),
-        drag: None,
-        last_window_area: Rect { x: 0, y: 0, width: 0, height: 0 },
-        mouse_enabled: true,
-        paste_buffers: Vec::new(),
-        status_left: "pmux:#I".to_string(),
-        status_right: "%H:%M".to_string(),
-        copy_anchor: None,
-        copy_pos: None,
-        display_map: Vec::new(),
-        binds: Vec::new(),
-        control_rx: None,
-        control_port: None,
-        session_name,
-        attached_clients: 0,
-        created_at: Local::now(),
-        next_win_id: 1,
-        next_pane_id: 1,
-        zoom_saved: None,
-        sync_input: false,
-    };
-    create_window(&*pty_system, &mut app)?;
+    let mut app = new_app_state(session_name);
+    load_config(&mut app, config_file.as_deref());
+    // the configuration may have opened windows of its own
+    if app.windows.is_empty() { create_window(&*pty_system, &mut app, None)?; }
     let (tx, rx) = mpsc::channel::<CtrlReq>();
     app.control_rx = Some(rx);
-    let listener = TcpListener::bind(("127.0.0.1", 0))?;
-    let port = listener.local_addr()?.port();
-    app.control_port = Some(port);
-    let home = env::var("USERPROFILE").or_else(|_| env::var("HOME")).unwrap_or_default();
-    let dir = format!("{}\\.pmux", home);
-    let _ = std::fs::create_dir_all(&dir);
-    let regpath = format!("{}\\{}.port", dir, app.session_name);
-    let _ = std::fs::write(&regpath, port.to_string());
-    thread::spawn(move || {
-        for conn in listener.incoming() {
-            if let Ok(mut stream) = conn {
-                let mut line = String::new();
-                let mut r = io::BufReader::new(stream.try_clone().unwrap());
-                let _ = r.read_line(&mut line);
-                let mut parts = line.split_whitespace();
-                let cmd = parts.next().unwrap_or("");
-                let mut args: Vec<&str> = parts.by_ref().collect();
-                let mut target_win: Option<usize> = None;
-                let mut target_pane: Option<usize> = None;
-                let mut i = 0;
-                while i < args.len() {
-                    if args[i] == "-t" {
-                        if let Some(v) = args.get(i+1) {
-                            if v.starts_with('%') { if let Ok(pid) = v[1..].parse::<usize>() { target_pane = Some(pid); } }
-                            else if v.starts_with('@') { if let Ok(wid) = v[1..].parse::<usize>() { target_win = Some(wid); } }
-                        }
-                        i += 2; continue;
-                    }
-                    i += 1;
+    let listener = transport::Listener::bind(&app.session_name)?;
+    app.control_port = listener.port();
+    let token = transport::create_token(&app.session_name)?;
+    spawn_control_listener(listener, token, app.session_name.clone(), tx);
+    loop {
+        let mut next = app.control_rx.as_ref().and_then(|rx| rx.recv_timeout(Duration::from_millis(20)).ok());
+        while let Some(req) = next {
+            let _ = handle_ctrl_req(&mut app, req, &*pty_system);
+            app.frame_dirty = true;
+            next = app.control_rx.as_ref().and_then(|rx| rx.try_recv().ok());
+        }
+        if let Mode::PaneChooser { opened_at } = &app.mode {
+            if opened_at.elapsed() > Duration::from_millis(1500) { app.mode = Mode::Passthrough; app.frame_dirty = true; }
+        }
+        // back to the root table once a repeatable key has not been pressed again in time
+        if app.key_table.as_ref().is_some_and(|t| t.repeat && t.since.elapsed() > Duration::from_millis(option_number(&app, "repeat-time"))) {
+            app.key_table = None;
+            app.frame_dirty = true;
+        }
+        if reap_children(&mut app)? { break; }
+        write_pane_replies(&mut app);
+        update_titles(&mut app);
+        automatic_rename(&mut app);
+        resize_panes(&mut app);
+        push_frames(&mut app);
+    }
+    transport::unregister(&app.session_name);
+    for c in app.clients.drain(..) {
+        let _ = c.tx.send(ServerMsg::Exited);
+        drop(c.tx);
+        let _ = c.done.recv_timeout(Duration::from_secs(1));
+    }
+    Ok(())
+}
+
+/// Send attached clients whatever changed since their last update: output in the
+/// visible panes as screen diffs, and the layout and status line when they differ.
+/// Nothing is done while the visible panes, the status and the rest of the
+/// state are as they were.
+fn push_frames(app: &mut AppState) {
+    if app.clients.is_empty() { return; }
+    let generations: Vec<(usize, u64)> = leaf_panes(&app.windows[app.active_idx].root).iter().map(|(_, p)| (p.id, p.generation.load(Ordering::Relaxed))).collect();
+    let status_changed = refresh_status(app, app.frame_dirty);
+    if !app.frame_dirty && !status_changed && generations == app.frame_generations { return; }
+    app.frame_dirty = false;
+    app.frame_generations = generations;
+    let Ok(frame) = frame_json(app, false) else { return };
+    let Ok(json) = serde_json::to_string(&frame) else { return };
+    fn collect<'a>(node: &'a Node, out: &mut Vec<&'a Pane>) {
+        match node {
+            Node::Leaf(p) => out.push(p),
+            Node::Split { children, .. } => { for c in children.iter() { collect(c, out); } }
+        }
+    }
+    let mut live: Vec<&Pane> = Vec::new();
+    for w in app.windows.iter() { collect(&w.root, &mut live); }
+    let live_ids: HashSet<usize> = live.iter().map(|p| p.id).collect();
+    let mut visible: Vec<&Pane> = Vec::new();
+    collect(&app.windows[app.active_idx].root, &mut visible);
+    for c in app.clients.iter_mut() {
+        let closed: Vec<usize> = c.panes.keys().filter(|id| !live_ids.contains(id)).copied().collect();
+        for id in closed {
+            c.panes.remove(&id);
+            let _ = c.tx.send(ServerMsg::PaneClosed { id });
+        }
+        for p in visible.iter() {
+            let parser = p.term.lock().unwrap();
+            let generation = p.generation.load(Ordering::Relaxed);
+            if c.panes.get(&p.id).is_some_and(|cp| cp.generation == generation) { continue; }
+            let screen = parser.screen();
+            let (rows, cols) = screen.size();
+            let (full, data) = match c.panes.get_mut(&p.id) {
+                Some(cp) if cp.shadow.screen().size() == (rows, cols) => {
+                    let data = screen.state_diff(cp.shadow.screen());
+                    cp.shadow.process(&data);
+                    cp.generation = generation;
+                    if data.is_empty() { continue; }
+                    (false, data)
                 }
-                if let Some(wid) = target_win { let _ = tx.send(CtrlReq::FocusWindow(wid)); }
-                if let Some(pid) = target_pane { let _ = tx.send(CtrlReq::FocusPane(pid)); }
-                match cmd {
-                    "new-window" => { let _ = tx.send(CtrlReq::NewWindow); }
-                    "split-window" => {
-                        let kind = if args.iter().any(|a| *a == "-h") { LayoutKind::Horizontal } else { LayoutKind::Vertical };
-                        let _ = tx.send(CtrlReq::SplitWindow(kind));
-                    }
-                    "kill-pane" => { let _ = tx.send(CtrlReq::KillPane); }
-                    "capture-pane" => {
-                        let (rtx, rrx) = mpsc::channel::<String>();
-                        let _ = tx.send(CtrlReq::CapturePane(rtx));
-                        if let Ok(text) = rrx.recv() { let _ = write!(stream, "{}", text); }
-                    }
-                    "dump-layout" => {
-                        let (rtx, rrx) = mpsc::channel::<String>();
-                        let _ = tx.send(CtrlReq::DumpLayout(rtx));
-                        if let Ok(text) = rrx.recv() { let _ = write!(stream, "{}", text); }
-                    }
-                    "send-text" => {
-                        if let Some(payload) = args.get(0) { let _ = tx.send(CtrlReq::SendText(payload.to_string())); }
-                    }
-                    "send-key" => {
-                        if let Some(payload) = args.get(0) { let _ = tx.send(CtrlReq::SendKey(payload.to_string())); }
-                    }
-                    "zoom-pane" => { let _ = tx.send(CtrlReq::ZoomPane); }
-                    "copy-enter" => { let _ = tx.send(CtrlReq::CopyEnter); }
-                    "copy-move" => {
-                        if args.len() >= 2 { if let (Ok(dx), Ok(dy)) = (args[0].parse::<i16>(), args[1].parse::<i16>()) { let _ = tx.send(CtrlReq::CopyMove(dx, dy)); } }
-                    }
-                    "copy-anchor" => { let _ = tx.send(CtrlReq::CopyAnchor); }
-                    "copy-yank" => { let _ = tx.send(CtrlReq::CopyYank); }
-                    "client-size" => {
-                        if args.len() >= 2 { if let (Ok(w), Ok(h)) = (args[0].parse::<u16>(), args[1].parse::<u16>()) { let _ = tx.send(CtrlReq::ClientSize(w, h)); } }
-                    }
-                    "focus-pane" => {
-                        if let Some(pid) = args.get(0).and_then(|s| s.parse::<usize>().ok()) { let _ = tx.send(CtrlReq::FocusPaneCmd(pid)); }
-                    }
-                    "focus-window" => {
-                        if let Some(wid) = args.get(0).and_then(|s| s.parse::<usize>().ok()) { let _ = tx.send(CtrlReq::FocusWindowCmd(wid)); }
-                    }
-                    "mouse-down" => {
-                        if args.len()>=2 { if let (Ok(x),Ok(y))=(args[0].parse::<u16>(),args[1].parse::<u16>()) { let _ = tx.send(CtrlReq::MouseDown(x,y)); } }
-                    }
-                    "mouse-drag" => {
-                        if args.len()>=2 { if let (Ok(x),Ok(y))=(args[0].parse::<u16>(),args[1].parse::<u16>()) { let _ = tx.send(CtrlReq::MouseDrag(x,y)); } }
-                    }
-                    "mouse-up" => {
-                        if args.len()>=2 { if let (Ok(x),Ok(y))=(args[0].parse::<u16>(),args[1].parse::<u16>()) { let _ = tx.send(CtrlReq::MouseUp(x,y)); } }
-                    }
-                    "scroll-up" => { let _ = tx.send(CtrlReq::ScrollUp); }
-                    "scroll-down" => { let _ = tx.send(CtrlReq::ScrollDown); }
-                    "next-window" => { let _ = tx.send(CtrlReq::NextWindow); }
-                    "previous-window" => { let _ = tx.send(CtrlReq::PrevWindow); }
-                    "rename-window" => { if let Some(name) = args.get(0) { let _ = tx.send(CtrlReq::RenameWindow((*name).to_string())); } }
-                    "list-windows" => { let (rtx, rrx) = mpsc::channel::<String>(); let _ = tx.send(CtrlReq::ListWindows(rtx)); if let Ok(text) = rrx.recv() { let _ = write!(stream, "{}", text); } }
-                    "list-tree" => { let (rtx, rrx) = mpsc::channel::<String>(); let _ = tx.send(CtrlReq::ListTree(rtx)); if let Ok(text) = rrx.recv() { let _ = write!(stream, "{}", text); } }
-                    "toggle-sync" => { let _ = tx.send(CtrlReq::ToggleSync); }
-                    "set-pane-title" => { let title = args.join(" "); let _ = tx.send(CtrlReq::SetPaneTitle(title)); }
-                    _ => {}
+                _ => {
+                    let data = screen.state_formatted();
+                    let mut shadow = vt100::Parser::new(rows, cols, 0);
+                    shadow.process(&data);
+                    c.panes.insert(p.id, ClientPane { generation, shadow });
+                    (true, data)
                 }
-            }
+            };
+            let data = String::from_utf8_lossy(&data).into_owned();
+            let _ = c.tx.send(ServerMsg::PaneUpdate { id: p.id, rows, cols, full, data });
+        }
+        if c.last_frame == json { continue; }
+        c.last_frame = json.clone();
+        let _ = c.tx.send(ServerMsg::Frame(Box::new(frame.clone())));
+    }
+}
+
+fn detach_client(app: &mut AppState, id: usize) {
+    if let Some(i) = app.clients.iter().position(|c| c.id == id) {
+        let c = app.clients.remove(i);
+        let _ = c.tx.send(ServerMsg::Detached);
+    }
+}
+
+fn spawn_control_listener(listener: transport::Listener, token: String, session_name: String, tx: mpsc::Sender<CtrlReq>) {
+    thread::spawn(move || {
+        let mut next_client_id = 1;
+        loop {
+            let Ok(stream) = listener.accept() else { continue };
+            let id = next_client_id;
+            next_client_id += 1;
+            let (token, session_name, tx) = (token.clone(), session_name.clone(), tx.clone());
+            // each connection authenticates on its own thread, so one that sends nothing stalls no one else
+            thread::spawn(move || serve_connection(id, stream, &token, &session_name, &tx));
         }
     });
-    loop {
-        while let Some(req) = app.control_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
-            match req {
-                CtrlReq::NewWindow => { let _ = create_window(&*pty_system, &mut app); }
-                CtrlReq::SplitWindow(k) => { let _ = split_active(&mut app, k); }
-                CtrlReq::KillPane => { let _ = kill_active_pane(&mut app); }
-                CtrlReq::CapturePane(resp) => {
-                    if let Some(text) = capture_active_pane_text(&mut app)? { let _ = resp.send(text); } else { let _ = resp.send(String::new()); }
-                }
-                CtrlReq::CapturePaneRange(resp, s, e) => {
-                    if let Some(text) = capture_active_pane_range(&mut app, s, e)? { let _ = resp.send(text); } else { let _ = resp.send(String::new()); }
-                }
-                CtrlReq::FocusWindow(wid) => { if let Some(idx) = find_window_index_by_id(&app, wid) { app.active_idx = idx; } }
-                CtrlReq::FocusPane(pid) => { focus_pane_by_id(&mut app, pid); }
-                CtrlReq::SessionInfo(resp) => {
-                    let attached = if app.attached_clients > 0 { "(attached)" } else { "(detached)" };
-                    let windows = app.windows.len();
-                    let (w,h) = {
-                        let win = &mut app.windows[app.active_idx];
-                        let mut size = (0,0);
-                        if let Some(p) = active_pane_mut(&mut win.root, &win.active_path) { size = (p.last_cols as i32, p.last_rows as i32); }
-                        size
-                    };
-                    let created = app.created_at.format("%a %b %e %H:%M:%S %Y");
-                    let line = format!("{}: {} windows (created {}) [{}x{}] {}\n", app.session_name, windows, created, w, h, attached);
-                    let _ = resp.send(line);
-                }
-                CtrlReq::ClientAttach => { app.attached_clients = app.attached_clients.saturating_add(1); }
-                CtrlReq::ClientDetach => { app.attached_clients = app.attached_clients.saturating_sub(1); }
-                CtrlReq::DumpLayout(resp) => {
-                    let json = dump_layout_json(&mut app)?;
-                    let _ = resp.send(json);
-                }
-                CtrlReq::SendText(s) => { send_text_to_active(&mut app, &s)?; }
-                CtrlReq::SendKey(k) => { send_key_to_active(&mut app, &k)?; }
-                CtrlReq::ZoomPane => { toggle_zoom(&mut app); }
-                CtrlReq::CopyEnter => { enter_copy_mode(&mut app); }
-                CtrlReq::CopyMove(dx, dy) => { move_copy_cursor(&mut app, dx, dy); }
-                CtrlReq::CopyAnchor => { if let Some((r,c)) = current_prompt_pos(&mut app) { app.copy_anchor = Some((r,c)); app.copy_pos = Some((r,c)); } }
-                CtrlReq::CopyYank => { let _ = yank_selection(&mut app); app.mode = Mode::Passthrough; }
-                CtrlReq::ClientSize(w, h) => { app.last_window_area = Rect { x: 0, y: 0, width: w, height: h }; }
-                CtrlReq::FocusPaneCmd(pid) => { focus_pane_by_id(&mut app, pid); }
-                CtrlReq::FocusWindowCmd(wid) => { if let Some(idx) = find_window_index_by_id(&app, wid) { app.active_idx = idx; } }
-                CtrlReq::MouseDown(x,y) => { remote_mouse_down(&mut app, x, y); }
-                CtrlReq::MouseDrag(x,y) => { remote_mouse_drag(&mut app, x, y); }
-                CtrlReq::MouseUp(_,_) => { app.drag = None; }
-                CtrlReq::ScrollUp => { remote_scroll_up(&mut app); }
-                CtrlReq::ScrollDown => { remote_scroll_down(&mut app); }
-                CtrlReq::NextWindow => { if !app.windows.is_empty() { app.active_idx = (app.active_idx + 1) % app.windows.len(); } }
-                CtrlReq::PrevWindow => { if !app.windows.is_empty() { app.active_idx = (app.active_idx + app.windows.len() - 1) % app.windows.len(); } }
-                CtrlReq::RenameWindow(name) => { let win = &mut app.windows[app.active_idx]; win.name = name; }
-                CtrlReq::ListWindows(resp) => { let json = list_windows_json(&app)?; let _ = resp.send(json); }
-                CtrlReq::ListTree(resp) => { let json = list_tree_json(&app)?; let _ = resp.send(json); }
-                CtrlReq::ToggleSync => { app.sync_input = !app.sync_input; }
-                CtrlReq::SetPaneTitle(title) => {
-                    let win = &mut app.windows[app.active_idx];
-                    if let Some(p) = active_pane_mut(&mut win.root, &win.active_path) { p.title = title; }
-                }
+}
+
+/// Check a new connection's token, then serve it as an attached client or run its one command.
+fn serve_connection(id: usize, mut stream: Stream, token: &str, session_name: &str, tx: &mpsc::Sender<CtrlReq>) {
+    let mut line = String::new();
+    let Ok(read_half) = stream.try_clone() else { return };
+    // a client that never finishes its first lines is dropped
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+    let mut r = io::BufReader::new(read_half);
+    if !transport::authenticate(&mut r, token) {
+        server_log(session_name, "rejected unauthenticated connection");
+        let _ = stream.shutdown();
+        return;
+    }
+    let _ = r.read_line(&mut line);
+    let _ = stream.set_read_timeout(None);
+    if line.trim() == protocol::FRAMED_HELLO {
+        serve_framed_client(id, r, stream, tx.clone());
+        return;
+    }
+    handle_control_line(&line, &mut stream, tx);
+}
+
+/// Append a timestamped line to the session's server log.
+fn server_log(session_name: &str, msg: &str) {
+    let Ok(path) = transport::log_path(session_name) else { return };
+    if let Ok(mut f) = std::fs::OpenOptions::new().create(true).append(true).open(path) {
+        let _ = writeln!(f, "{} {}", Local::now().format("%Y-%m-%d %H:%M:%S"), msg);
+    }
+}
+
+/// Serve one attached client: a reader thread turns its input into requests while
+/// this thread writes the frames and notifications the server loop queues for it.
+fn serve_framed_client(id: usize, mut reader: io::BufReader<Stream>, mut stream: Stream, tx: mpsc::Sender<CtrlReq>) {
+    let Ok(Some(ClientMsg::Hello { version, cols, rows })) = protocol::read_msg::<ClientMsg>(&mut reader) else { return };
+    if version != protocol::PROTOCOL_VERSION {
+        let text = format!("protocol version mismatch: server {}, client {}", protocol::PROTOCOL_VERSION, version);
+        let _ = protocol::write_msg(&mut stream, &ServerMsg::Message { text });
+        return;
+    }
+    if protocol::write_msg(&mut stream, &ServerMsg::Welcome { version: protocol::PROTOCOL_VERSION }).is_err() { return; }
+    let (stx, srx) = mpsc::channel::<ServerMsg>();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    let _ = tx.send(CtrlReq::ClientSize(cols, rows));
+    if tx.send(CtrlReq::Attach(id, stx, done_rx)).is_err() { return; }
+    let input_tx = tx.clone();
+    thread::spawn(move || {
+        while let Ok(Some(msg)) = protocol::read_msg::<ClientMsg>(&mut reader) {
+            let req = match msg {
+                ClientMsg::Key { mods, code } => key_from_wire(mods, &code).map(|k| CtrlReq::ClientKey(id, k)),
+                ClientMsg::Mouse { kind, mods, x, y } => mouse_from_wire(&kind, mods, x, y).map(CtrlReq::Mouse),
+                ClientMsg::Resize { cols, rows } => Some(CtrlReq::ClientSize(cols, rows)),
+                ClientMsg::Paste { text } => Some(CtrlReq::Paste(text)),
+                ClientMsg::Detach => break,
+                ClientMsg::Hello { .. } => None,
+            };
+            if let Some(req) = req { if input_tx.send(req).is_err() { return; } }
+        }
+        let _ = input_tx.send(CtrlReq::Detach(id));
+    });
+    for msg in srx.iter() {
+        let last = matches!(msg, ServerMsg::Detached | ServerMsg::Exited);
+        if protocol::write_msg(&mut stream, &msg).is_err() || last { break; }
+    }
+    let _ = stream.shutdown();
+    drop(done_tx);
+}
+
+/// Parse one control line, forward it to the server loop and write any reply to `stream`.
+fn handle_control_line(line: &str, stream: &mut Stream, tx: &mpsc::Sender<CtrlReq>) {
+    let mut parts = line.split_whitespace();
+    let cmd = parts.next().unwrap_or("");
+    // parse optional target specifier
+    let args: Vec<&str> = parts.by_ref().collect();
+    let mut target_win: Option<usize> = None;
+    let mut target_pane: Option<usize> = None;
+    let mut start_line: Option<u16> = None;
+    let mut end_line: Option<u16> = None;
+    let mut i = 0;
+    while i < args.len() {
+        if args[i] == "-t" {
+            if let Some(v) = args.get(i+1) {
+                if let Some(pid) = v.strip_prefix('%').and_then(|s| s.parse::<usize>().ok()) { target_pane = Some(pid); }
+                else if let Some(wid) = v.strip_prefix('@').and_then(|s| s.parse::<usize>().ok()) { target_win = Some(wid); }
             }
+            i += 2; continue;
+        } else if args[i] == "-S" {
+            if let Some(v) = args.get(i+1) { if let Ok(n) = v.parse::<u16>() { start_line = Some(n); } }
+            i += 2; continue;
+        } else if args[i] == "-E" {
+            if let Some(v) = args.get(i+1) { if let Ok(n) = v.parse::<u16>() { end_line = Some(n); } }
+            i += 2; continue;
+        }
+        i += 1;
+    }
+    if let Some(wid) = target_win { let _ = tx.send(CtrlReq::FocusWindow(wid)); }
+    if let Some(pid) = target_pane { let _ = tx.send(CtrlReq::FocusPane(pid)); }
+    let xy = || -> Option<(u16, u16)> { Some((args.first()?.parse().ok()?, args.get(1)?.parse().ok()?)) };
+    match cmd {
+        "new-window" | "split-window" => {
+            // the directory is quoted, as it may contain spaces
+            let words: Vec<String> = config::tokenize(line).ok().and_then(|mut c| c.pop()).unwrap_or_default();
+            let words: Vec<&str> = words.iter().map(String::as_str).collect();
+            let dir = start_dir_arg(&words);
+            if cmd == "new-window" { let _ = tx.send(CtrlReq::NewWindow(dir)); }
+            else {
+                let kind = if args.contains(&"-h") { LayoutKind::Horizontal } else { LayoutKind::Vertical };
+                let _ = tx.send(CtrlReq::SplitWindow(kind, dir));
+            }
+        }
+        "kill-pane" => { let _ = tx.send(CtrlReq::KillPane); }
+        "capture-pane" => {
+            let (rtx, rrx) = mpsc::channel::<String>();
+            if start_line.is_some() || end_line.is_some() { let _ = tx.send(CtrlReq::CapturePaneRange(rtx, start_line, end_line)); }
+            else { let _ = tx.send(CtrlReq::CapturePane(rtx)); }
+            if let Ok(text) = rrx.recv() { let _ = write!(stream, "{}", text); }
+        }
+        "client-attach" => { let _ = tx.send(CtrlReq::ClientAttach); let _ = writeln!(stream, "ok"); }
+        "client-detach" => { let _ = tx.send(CtrlReq::ClientDetach); let _ = writeln!(stream, "ok"); }
+        "session-info" => {
+            let (rtx, rrx) = mpsc::channel::<String>();
+            let _ = tx.send(CtrlReq::SessionInfo(rtx));
+            if let Ok(line) = rrx.recv() { let _ = write!(stream, "{}", line); let _ = stream.flush(); }
+        }
+        "dump-layout" => {
+            let (rtx, rrx) = mpsc::channel::<String>();
+            let _ = tx.send(CtrlReq::DumpLayout(rtx));
+            if let Ok(text) = rrx.recv() { let _ = write!(stream, "{}", text); }
+        }
+        "dump-frame" => {
+            let (rtx, rrx) = mpsc::channel::<String>();
+            let _ = tx.send(CtrlReq::DumpFrame(rtx));
+            if let Ok(text) = rrx.recv() { let _ = write!(stream, "{}", text); }
+        }
+        "send-text" => {
+            if let Some(payload) = args.first() { let _ = tx.send(CtrlReq::SendText(payload.to_string())); }
+        }
+        "send-key" => {
+            if let Some(payload) = args.first() { let _ = tx.send(CtrlReq::SendKey(payload.to_string())); }
+        }
+        "key" => {
+            let mods = args.first().and_then(|m| m.parse::<u8>().ok()).unwrap_or(0);
+            if let Some(key) = key_from_wire(mods, args.get(1).copied().unwrap_or("")) {
+                let (rtx, rrx) = mpsc::channel::<bool>();
+                let _ = tx.send(CtrlReq::Key(key, rtx));
+                if let Ok(detach) = rrx.recv() { let _ = writeln!(stream, "{}", if detach { "detach" } else { "ok" }); }
+            }
+        }
+        "zoom-pane" => { let _ = tx.send(CtrlReq::ZoomPane); }
+        "copy-enter" => { let _ = tx.send(CtrlReq::CopyEnter); }
+        "copy-move" if args.len() >= 2 => {
+            if let (Ok(dx), Ok(dy)) = (args[0].parse::<i16>(), args[1].parse::<i16>()) { let _ = tx.send(CtrlReq::CopyMove(dx, dy)); }
+        }
+        "copy-anchor" => { let _ = tx.send(CtrlReq::CopyAnchor); }
+        "copy-yank" => { let _ = tx.send(CtrlReq::CopyYank); }
+        "client-size" => {
+            if let Some((w, h)) = xy() { let _ = tx.send(CtrlReq::ClientSize(w, h)); }
+        }
+        "focus-pane" => {
+            if let Some(pid) = args.first().and_then(|s| s.parse::<usize>().ok()) { let _ = tx.send(CtrlReq::FocusPaneCmd(pid)); }
+        }
+        "focus-window" => {
+            if let Some(wid) = args.first().and_then(|s| s.parse::<usize>().ok()) { let _ = tx.send(CtrlReq::FocusWindowCmd(wid)); }
+        }
+        "mouse-down" | "mouse-drag" | "mouse-up" | "mouse-down-middle" | "mouse-drag-middle" | "mouse-up-middle"
+        | "mouse-down-right" | "mouse-drag-right" | "mouse-up-right" | "mouse-move"
+        | "scroll-up" | "scroll-down" | "scroll-left" | "scroll-right" => {
+            let (column, row) = xy().unwrap_or((0, 0));
+            let mods = args.get(2).and_then(|m| m.parse().ok()).unwrap_or(0);
+            if let Some(me) = mouse_from_wire(cmd, mods, column, row) { let _ = tx.send(CtrlReq::Mouse(me)); }
+        }
+        "next-window" => { let _ = tx.send(CtrlReq::NextWindow); }
+        "previous-window" => { let _ = tx.send(CtrlReq::PrevWindow); }
+        "rename-window" => { if let Some(name) = args.first() { let _ = tx.send(CtrlReq::RenameWindow((*name).to_string())); } }
+        "list-windows" => {
+            let (rtx, rrx) = mpsc::channel::<String>();
+            let _ = tx.send(CtrlReq::ListWindows(format_arg(line), rtx));
+            if let Ok(text) = rrx.recv() { let _ = write!(stream, "{}", text); }
+        }
+        "list-panes" => {
+            let (rtx, rrx) = mpsc::channel::<String>();
+            let _ = tx.send(CtrlReq::ListPanes(format_arg(line), args.contains(&"-s"), rtx));
+            if let Ok(text) = rrx.recv() { let _ = write!(stream, "{}", text); }
+        }
+        "display-message" | "display" => {
+            let text = line.trim().split_once(' ').map(|(_, rest)| rest.trim_start()).unwrap_or("");
+            let (print, text) = match text.strip_prefix("-p") { Some(rest) => (true, rest.trim_start()), None => (false, text) };
+            let fmt = (!text.is_empty()).then(|| text.to_string());
+            if print {
+                let (rtx, rrx) = mpsc::channel::<String>();
+                let _ = tx.send(CtrlReq::DisplayMessage(fmt, Some(rtx)));
+                if let Ok(text) = rrx.recv() { let _ = write!(stream, "{}", text); }
+            } else {
+                let _ = tx.send(CtrlReq::DisplayMessage(fmt, None));
+            }
+        }
+        "source-file" | "set-option" | "set-window-option" | "show-options" | "show-window-options"
+        | "bind-key" | "unbind-key" | "list-keys" => {
+            let Ok(mut cmds) = config::tokenize(line) else { return };
+            let (rtx, rrx) = mpsc::channel::<String>();
+            let _ = tx.send(CtrlReq::Command(cmds.pop().unwrap_or_default(), rtx));
+            if let Ok(text) = rrx.recv() { let _ = write!(stream, "{}", text); }
+        }
+        "list-tree" => { let (rtx, rrx) = mpsc::channel::<String>(); let _ = tx.send(CtrlReq::ListTree(rtx)); if let Ok(text) = rrx.recv() { let _ = write!(stream, "{}", text); } }
+        "toggle-sync" => { let _ = tx.send(CtrlReq::ToggleSync); }
+        "mark-pane" => { let _ = tx.send(CtrlReq::MarkPane); }
+        "clear-marks" => { let _ = tx.send(CtrlReq::ClearMarks); }
+        "set-pane-title" => { let title = args.join(" "); let _ = tx.send(CtrlReq::SetPaneTitle(title)); }
+        _ => {}
+    }
+}
+
+fn handle_ctrl_req(app: &mut AppState, req: CtrlReq, pty_system: &dyn portable_pty::PtySystem) -> io::Result<()> {
+    match req {
+        CtrlReq::NewWindow(dir) => { create_window(pty_system, app, dir)?; }
+        CtrlReq::SplitWindow(k, dir) => { split_active(app, k, dir)?; }
+        CtrlReq::KillPane => { kill_active_pane(app)?; }
+        CtrlReq::CapturePane(resp) => {
+            let _ = resp.send(capture_active_pane_text(app)?.unwrap_or_default());
+        }
+        CtrlReq::CapturePaneRange(resp, s, e) => {
+            let _ = resp.send(capture_active_pane_range(app, s, e)?.unwrap_or_default());
+        }
+        CtrlReq::FocusWindow(wid) | CtrlReq::FocusWindowCmd(wid) => { if let Some(idx) = find_window_index_by_id(app, wid) { app.active_idx = idx; } }
+        CtrlReq::FocusPane(pid) | CtrlReq::FocusPaneCmd(pid) => { focus_pane_by_id(app, pid); }
+        CtrlReq::SessionInfo(resp) => {
+            let attached = if app.attached_clients + app.clients.len() > 0 { "(attached)" } else { "(detached)" };
+            let windows = app.windows.len();
+            let (w,h) = {
+                let win = &mut app.windows[app.active_idx];
+                let mut size = (0,0);
+                if let Some(p) = active_pane_mut(&mut win.root, &win.active_path) { size = (p.last_cols as i32, p.last_rows as i32); }
+                size
+            };
+            let created = app.created_at.format("%a %b %e %H:%M:%S %Y");
+            let line = format!("{}: {} windows (created {}) [{}x{}] {}\n", app.session_name, windows, created, w, h, attached);
+            let _ = resp.send(line);
+        }
+        CtrlReq::ClientAttach => { app.attached_clients = app.attached_clients.saturating_add(1); }
+        CtrlReq::ClientDetach => { app.attached_clients = app.attached_clients.saturating_sub(1); }
+        CtrlReq::DumpLayout(resp) => {
+            let json = dump_layout_json(app)?;
+            let _ = resp.send(json);
+        }
+        CtrlReq::DumpFrame(resp) => {
+            let json = dump_frame_json(app)?;
+            let _ = resp.send(json);
+        }
+        CtrlReq::SendText(s) => { send_text_to_active(app, &s)?; }
+        CtrlReq::Paste(s) => { paste_to_active(app, &s); }
+        CtrlReq::SendKey(k) => { send_key_to_active(app, &k)?; }
+        CtrlReq::Key(key, resp) => {
+            let detach = handle_key(app, key).unwrap_or(false);
+            let _ = resp.send(detach);
+        }
+        CtrlReq::Attach(id, tx, done) => {
+            // the first client to attach sees what went wrong in the configuration
+            if !app.config_errors.is_empty() { let _ = tx.send(ServerMsg::Message { text: std::mem::take(&mut app.config_errors).join("; ") }); }
+            app.clients.push(AttachedClient { id, tx, last_frame: String::new(), panes: HashMap::new(), done });
+        }
+        CtrlReq::ClientKey(id, key) => { if handle_key(app, key).unwrap_or(false) { detach_client(app, id); } }
+        CtrlReq::Detach(id) => { detach_client(app, id); }
+        CtrlReq::Mouse(me) => { if option_flag(app, "mouse") { let area = app.last_window_area; handle_mouse(app, me, area)?; } }
+        CtrlReq::ZoomPane => { toggle_zoom(app); }
+        CtrlReq::CopyEnter => { enter_copy_mode(app); }
+        CtrlReq::CopyMove(dx, dy) => { move_copy_cursor(app, dx, dy); }
+        CtrlReq::CopyAnchor => { if let Some((r,c)) = current_prompt_pos(app) { app.copy_anchor = Some((r,c)); app.copy_pos = Some((r,c)); } }
+        CtrlReq::CopyYank => { let _ = yank_selection(app); exit_copy_mode(app); }
+        CtrlReq::ClientSize(w, h) => { app.client_size = (w, h); update_window_area(app); resize_panes(app); }
+        CtrlReq::NextWindow => { if !app.windows.is_empty() { app.active_idx = (app.active_idx + 1) % app.windows.len(); } }
+        CtrlReq::PrevWindow => { if !app.windows.is_empty() { app.active_idx = (app.active_idx + app.windows.len() - 1) % app.windows.len(); } }
+        CtrlReq::RenameWindow(name) => { rename_window(&mut app.windows[app.active_idx], name); }
+        CtrlReq::ListWindows(None, resp) => { let json = list_windows_json(app)?; let _ = resp.send(json); }
+        CtrlReq::ListWindows(Some(fmt), resp) => { let _ = resp.send(list_windows_formatted(app, &fmt)); }
+        CtrlReq::ListPanes(fmt, all, resp) => { let _ = resp.send(list_panes_formatted(app, fmt.as_deref(), all)); }
+        CtrlReq::DisplayMessage(fmt, resp) => {
+            let text = expand_status(fmt.as_deref().unwrap_or(DISPLAY_MESSAGE_FORMAT), app);
+            match resp {
+                Some(resp) => { let _ = resp.send(format!("{}\n", text)); }
+                None => display_message(app, text),
+            }
+        }
+        CtrlReq::ListTree(resp) => { let json = list_tree_json(app)?; let _ = resp.send(json); }
+        CtrlReq::Command(args, resp) => {
+            // errors from startup stay for the first client to attach
+            let before = app.config_errors.len();
+            let parts: Vec<&str> = args.iter().map(String::as_str).collect();
+            let result = match parts.first().copied().unwrap_or("") {
+                "show" | "show-options" => show_options(app, &parts[1..], false),
+                "showw" | "show-window-options" => show_options(app, &parts[1..], true),
+                "lsk" | "list-keys" => list_keys(app, &parts[1..]),
+                _ => run_command(app, &args).map(|_| String::new()),
+            };
+            let mut out = result.unwrap_or_else(|e| { app.config_errors.push(e); String::new() });
+            for e in app.config_errors.split_off(before) { out.push_str(&e); out.push('\n'); }
+            let _ = resp.send(out);
+            // with no key to say which, `detach-client` from the command line detaches every client
+            if std::mem::take(&mut app.detach) {
+                for id in app.clients.iter().map(|c| c.id).collect::<Vec<_>>() { detach_client(app, id); }
+            }
+        }
+        CtrlReq::ToggleSync => { toggle_sync(app); }
+        CtrlReq::MarkPane => { toggle_pane_mark(app); }
+        CtrlReq::ClearMarks => { app.windows[app.active_idx].sync_marked.clear(); }
+        CtrlReq::SetPaneTitle(title) => {
+            let win = &mut app.windows[app.active_idx];
+            if let Some(p) = active_pane_mut(&mut win.root, &win.active_path) { p.title = title; }
         }
-        let _ = reap_children(&mut app)?;
-        thread::sleep(Duration::from_millis(20));
     }
+    Ok(())
 }
 
 fn capture_active_pane_range(app: &mut AppState, s: Option<u16>, e: Option<u16>) -> io::Result<Option<String>> {
@@ -1979,37 +2935,96 @@ fn capture_active_pane_range(app: &mut AppState, s: Option<u16>, e: Option<u16>)
     let start = s.unwrap_or(0).min(p.last_rows.saturating_sub(1));
     let end = e.unwrap_or(p.last_rows.saturating_sub(1)).min(p.last_rows.saturating_sub(1));
     let mut text = String::new();
-    for r in start..=end { for c in 0..p.last_cols { if let Some(cell) = screen.cell(r, c) { text.push_str(&cell.contents().to_string()); } else { text.push(' '); } } text.push('\n'); }
+    for r in start..=end { for c in 0..p.last_cols { if let Some(cell) = screen.cell(r, c) { text.push_str(cell.contents()); } else { text.push(' '); } } text.push('\n'); }
     Ok(Some(text))
 }
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 struct CellJson { text: String, fg: String, bg: String, bold: bool, italic: bool, underline: bool, inverse: bool }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 enum LayoutJson {
     #[serde(rename = "split")]
     Split { kind: String, sizes: Vec<u16>, children: Vec<LayoutJson> },
     #[serde(rename = "leaf")]
-    Leaf { id: usize, rows: u16, cols: u16, cursor_row: u16, cursor_col: u16, content: Vec<Vec<CellJson>> },
-}
-
-fn dump_layout_json(app: &mut AppState) -> io::Result<String> {
-    fn build(node: &mut Node) -> LayoutJson {
+    Leaf {
+        id: usize, rows: u16, cols: u16, cursor_row: u16, cursor_col: u16,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        content: Vec<Vec<CellJson>>,
+        /// Receives synchronized input.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        synchronized: bool,
+    },
+}
+
+/// A run of status-line text; `None` colors inherit the status bar style.
+#[derive(Clone, Serialize, Deserialize)]
+struct StyledJson {
+    text: String, fg: Option<String>, bg: Option<String>, bold: bool, italic: bool, underline: bool,
+    #[serde(default)]
+    reverse: bool,
+}
+
+/// One status line: the left and right regions with the window list placed between them.
+#[derive(Clone, Serialize, Deserialize)]
+struct StatusLineJson {
+    /// The `status-style` the regions are drawn over; its text is empty.
+    style: StyledJson,
+    left: Vec<StyledJson>,
+    windows: Vec<StyledJson>,
+    right: Vec<StyledJson>,
+    justify: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum OverlayJson {
+    #[serde(rename = "prompt")]
+    Prompt { title: String, text: String },
+    #[serde(rename = "list")]
+    List { title: String, items: Vec<String>, selected: usize },
+    #[serde(rename = "pane-numbers")]
+    PaneNumbers,
+}
+
+/// Everything an attached client needs to draw one frame.
+#[derive(Clone, Serialize, Deserialize)]
+struct FrameJson {
+    layout: LayoutJson, active_pane: usize, overlay: Option<OverlayJson>, cursor_style: u8,
+    /// Status lines, none when `status` is off.
+    status: Vec<StatusLineJson>,
+    /// Status lines go above the panes rather than below.
+    #[serde(default)]
+    status_top: bool,
+    /// Scrollback offset and history length of the active pane while in copy mode.
+    #[serde(default)]
+    copy_position: Option<(usize, usize)>,
+    /// Title for the client terminal when `set-titles` is on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    /// The `terminal-overrides` option, applied by each client to its own terminal.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    terminal_overrides: String,
+    /// Capture the mouse in the client's terminal.
+    mouse: bool,
+}
+
+/// Layout of the active window; cell contents are only filled in when `with_content` is set.
+fn layout_json(app: &mut AppState, with_content: bool) -> LayoutJson {
+    fn build(node: &mut Node, with_content: bool, synced: &HashSet<usize>) -> LayoutJson {
         match node {
             Node::Split { kind, sizes, children } => {
                 let k = match *kind { LayoutKind::Horizontal => "Horizontal".to_string(), LayoutKind::Vertical => "Vertical".to_string() };
                 let mut ch: Vec<LayoutJson> = Vec::new();
-                for c in children.iter_mut() { ch.push(build(c)); }
+                for c in children.iter_mut() { ch.push(build(c, with_content, synced)); }
                 LayoutJson::Split { kind: k, sizes: sizes.clone(), children: ch }
             }
             Node::Leaf(p) => {
                 let parser = p.term.lock().unwrap();
                 let screen = parser.screen();
                 let (cr, cc) = screen.cursor_position();
-                if let Some(t) = infer_title_from_prompt(&screen, p.last_rows, p.last_cols) { p.title = t; }
                 let mut lines: Vec<Vec<CellJson>> = Vec::new();
-                for r in 0..p.last_rows {
+                for r in 0..if with_content { p.last_rows } else { 0 } {
                     let mut row: Vec<CellJson> = Vec::new();
                     for c in 0..p.last_cols {
                         if let Some(cell) = screen.cell(r, c) {
@@ -2023,76 +3038,102 @@ fn dump_layout_json(app: &mut AppState) -> io::Result<String> {
                     }
                     lines.push(row);
                 }
-                LayoutJson::Leaf { id: p.id, rows: p.last_rows, cols: p.last_cols, cursor_row: cr, cursor_col: cc, content: lines }
+                LayoutJson::Leaf { id: p.id, rows: p.last_rows, cols: p.last_cols, cursor_row: cr, cursor_col: cc, content: lines, synchronized: synced.contains(&p.id) }
             }
         }
     }
+    let sync = synchronized(app, app.active_idx);
     let win = &mut app.windows[app.active_idx];
-    let root = build(&mut win.root);
-    let s = serde_json::to_string(&root).map_err(|e| io::Error::new(io::ErrorKind::Other, format!("json error: {e}")))?;
+    let synced: HashSet<usize> = if sync { input_panes(win, true).into_iter().map(|p| p.id).collect() } else { HashSet::new() };
+    build(&mut win.root, with_content, &synced)
+}
+
+fn dump_layout_json(app: &mut AppState) -> io::Result<String> {
+    let root = layout_json(app, true);
+    let s = serde_json::to_string(&root).map_err(|e| io::Error::other(format!("json error: {e}")))?;
     Ok(s)
 }
 
-fn infer_title_from_prompt(screen: &vt100::Screen, rows: u16, cols: u16) -> Option<String> {
-    let mut last: Option<String> = None;
-    for r in (0..rows).rev() {
-        let mut s = String::new();
-        for c in 0..cols { if let Some(cell) = screen.cell(r, c) { s.push_str(&cell.contents().to_string()); } else { s.push(' '); } }
-        let t = s.trim_end().to_string();
-        if !t.trim().is_empty() { last = Some(t); break; }
-    }
-    let Some(line) = last else { return None };
-    let trimmed = line.trim().to_string();
-    if let Some(pos) = trimmed.rfind('>') {
-        let before = trimmed[..pos].trim().to_string();
-        if before.contains("\\") || before.contains("/") {
-            let parts: Vec<&str> = before.trim_matches(|ch: char| ch == '"').split(['\\','/']).collect();
-            if let Some(base) = parts.last() { return Some(base.to_string()); }
+fn dump_frame_json(app: &mut AppState) -> io::Result<String> {
+    refresh_status(app, true);
+    let frame = frame_json(app, true)?;
+    serde_json::to_string(&frame).map_err(|e| io::Error::other(format!("json error: {e}")))
+}
+
+/// Expand the status lines and title again when `changed`, once a `#()`
+/// command has finished, or every `status-interval` seconds. Returns whether
+/// they were.
+fn refresh_status(app: &mut AppState, changed: bool) -> bool {
+    let interval = Duration::from_secs(option_number(app, "status-interval"));
+    let due = app.status_cache.as_ref().is_none_or(|c| changed || (!interval.is_zero() && c.expanded.elapsed() >= interval));
+    if !due && !app.jobs.borrow_mut().poll() { return false; }
+    let lines = status_lines(app);
+    let title = option_flag(app, "set-titles").then(|| expand_status(&option(app, "set-titles-string"), app));
+    app.status_cache = Some(StatusCache { expanded: Instant::now(), lines, title });
+    true
+}
+
+/// The frame clients draw, with the status as `refresh_status` last expanded it.
+fn frame_json(app: &mut AppState, with_content: bool) -> io::Result<FrameJson> {
+    let layout = layout_json(app, with_content);
+    let active_pane = {
+        let win = &mut app.windows[app.active_idx];
+        active_pane_mut(&mut win.root, &win.active_path).map(|p| p.id).unwrap_or(0)
+    };
+    let (status, title) = app.status_cache.as_ref().map(|c| (c.lines.clone(), c.title.clone())).unwrap_or_default();
+    let overlay = match &app.mode {
+        Mode::CommandPrompt { input, prompt: None, .. } => Some(OverlayJson::Prompt { title: "command".to_string(), text: format!(":{}", input) }),
+        Mode::CommandPrompt { input, prompt: Some(prompt), .. } => Some(OverlayJson::Prompt { title: prompt.clone(), text: format!("{}: {}", prompt, input) }),
+        Mode::TreeChooser { selected } => {
+            let items = tree_entries(app).into_iter().map(|(_, _, _, label)| label).collect();
+            Some(OverlayJson::List { title: "choose-tree".to_string(), items, selected: *selected })
         }
-        return Some(before);
-    }
-    if let Some(pos) = trimmed.rfind('$') { return Some(trimmed[..pos].trim().to_string()); }
-    if let Some(pos) = trimmed.rfind('#') { return Some(trimmed[..pos].trim().to_string()); }
-    Some(trimmed)
+        Mode::PaneChooser { .. } => Some(OverlayJson::PaneNumbers),
+        Mode::Passthrough | Mode::Copy => None,
+    };
+    let copy_position = if matches!(app.mode, Mode::Copy) { history_position(app) } else { None };
+    // the shape the active pane's application asked for, else the configured one
+    let cursor_style = {
+        let win = &mut app.windows[app.active_idx];
+        active_pane_mut(&mut win.root, &win.active_path).and_then(|p| p.term.lock().unwrap().callbacks().cursor_shape())
+    }.unwrap_or_else(|| cursor_style_code(app));
+    let status_top = option(app, "status-position") == "top";
+    Ok(FrameJson { layout, active_pane, status, status_top, overlay, cursor_style, copy_position, title, terminal_overrides: option(app, "terminal-overrides"), mouse: option_flag(app, "mouse") })
+}
+
+fn styled_json_style(s: &StyledJson, depth: ColorDepth) -> Style {
+    let mut style = Style::default();
+    if let Some(fg) = &s.fg { style = style.fg(colors::downgrade(map_color(fg), depth)); }
+    if let Some(bg) = &s.bg { style = style.bg(colors::downgrade(map_color(bg), depth)); }
+    if s.bold { style = style.add_modifier(Modifier::BOLD); }
+    if s.italic { style = style.add_modifier(Modifier::ITALIC); }
+    if s.underline { style = style.add_modifier(Modifier::UNDERLINED); }
+    if s.reverse { style = style.add_modifier(Modifier::REVERSED); }
+    style
 }
 
 fn resolve_last_session_name() -> Option<String> {
-    let home = env::var("USERPROFILE").or_else(|_| env::var("HOME")).ok()?;
-    let dir = format!("{}\\.pmux", home);
-    let last = std::fs::read_to_string(format!("{}\\last_session", dir)).ok();
+    let last = transport::last_session_path().ok().and_then(|p| std::fs::read_to_string(p).ok());
     if let Some(name) = last {
         let name = name.trim().to_string();
-        let p = format!("{}\\{}.port", dir, name);
-        if std::path::Path::new(&p).exists() { return Some(name); }
-    }
-    let mut picks: Vec<(String, std::time::SystemTime)> = Vec::new();
-    if let Ok(rd) = std::fs::read_dir(&dir) {
-        for e in rd.flatten() {
-            if let Some(fname) = e.file_name().to_str() {
-                if let Some((base, ext)) = fname.rsplit_once('.') {
-                    if ext == "port" { if let Ok(md) = e.metadata() { picks.push((base.to_string(), md.modified().unwrap_or(std::time::SystemTime::UNIX_EPOCH))); } }
-                }
-            }
-        }
+        if transport::session_registered(&name) { return Some(name); }
     }
+    let mut picks = transport::list_sessions();
     picks.sort_by_key(|(_, t)| *t);
     picks.last().map(|(n, _)| n.clone())
 }
 
 fn resolve_default_session_name() -> Option<String> {
     if let Ok(name) = env::var("PMUX_DEFAULT_SESSION") {
-        let home = env::var("USERPROFILE").or_else(|_| env::var("HOME")).ok()?;
-        let p = format!("{}\\.pmux\\{}.port", home, name);
-        if std::path::Path::new(&p).exists() { return Some(name); }
+        if transport::session_registered(&name) { return Some(name); }
     }
-    let home = env::var("USERPROFILE").or_else(|_| env::var("HOME")).ok()?;
-    let candidates = [format!("{}\\.pmuxrc", home), format!("{}\\.pmux\\pmuxrc", home)];
+    let home = std::path::PathBuf::from(env::var("USERPROFILE").or_else(|_| env::var("HOME")).ok()?);
+    let candidates = [home.join(".pmuxrc"), home.join(".pmux").join("pmuxrc")];
     for cfg in candidates.iter() {
         if let Ok(text) = std::fs::read_to_string(cfg) {
             let line = text.lines().find(|l| !l.trim().is_empty())?;
             let name = if let Some(rest) = line.strip_prefix("default-session ") { rest.trim().to_string() } else { line.trim().to_string() };
-            let p = format!("{}\\.pmux\\{}.port", home, name);
-            if std::path::Path::new(&p).exists() { return Some(name); }
+            if transport::session_registered(&name) { return Some(name); }
         }
     }
     None
@@ -2110,7 +3151,7 @@ struct WinTree { id: usize, name: String, active: bool, panes: Vec<PaneInfo> }
 fn list_windows_json(app: &AppState) -> io::Result<String> {
     let mut v: Vec<WinInfo> = Vec::new();
     for (i, w) in app.windows.iter().enumerate() { v.push(WinInfo { id: w.id, name: w.name.clone(), active: i == app.active_idx }); }
-    let s = serde_json::to_string(&v).map_err(|e| io::Error::new(io::ErrorKind::Other, format!("json error: {e}")))?;
+    let s = serde_json::to_string(&v).map_err(|e| io::Error::other(format!("json error: {e}")))?;
     Ok(s)
 }
 
@@ -2127,10 +3168,53 @@ fn list_tree_json(app: &AppState) -> io::Result<String> {
         collect_panes(&w.root, &mut panes);
         v.push(WinTree { id: w.id, name: w.name.clone(), active: i == app.active_idx, panes });
     }
-    let s = serde_json::to_string(&v).map_err(|e| io::Error::new(io::ErrorKind::Other, format!("json error: {e}")))?;
+    let s = serde_json::to_string(&v).map_err(|e| io::Error::other(format!("json error: {e}")))?;
     Ok(s)
 }
 
+const CHOOSE_TREE_WINDOW_FORMAT: &str = "#{window_index}: #{window_name}#{window_flags} (#{window_panes} panes)";
+const CHOOSE_TREE_PANE_FORMAT: &str = "  #{pane_index}: #{pane_title}#{?pane_active, (active),}";
+
+/// Flattened window/pane list for choose-tree: `(is_window, window id, pane id, label)`.
+fn tree_entries(app: &AppState) -> Vec<(bool, usize, usize, String)> {
+    let mut out = Vec::new();
+    for (i, w) in app.windows.iter().enumerate() {
+        let active = active_pane_ref(&w.root, &w.active_path);
+        out.push((true, w.id, 0, expand_format(app, i, active, CHOOSE_TREE_WINDOW_FORMAT)));
+        for (_, p) in leaf_panes(&w.root) { out.push((false, w.id, p.id, expand_format(app, i, Some(p), CHOOSE_TREE_PANE_FORMAT))); }
+    }
+    out
+}
+
+const LIST_PANES_FORMAT: &str = "#{pane_index}: [#{pane_width}x#{pane_height}] #{pane_id}#{?pane_active, (active),}";
+const LIST_PANES_SESSION_FORMAT: &str = "#{window_index}.#{pane_index}: [#{pane_width}x#{pane_height}] #{pane_id}#{?pane_active, (active),}";
+const DISPLAY_MESSAGE_FORMAT: &str = "[#S] #I:#P, current pane #P - (%H:%M %d-%b-%y)";
+
+/// One line per window, expanded from `fmt` with each window's active pane.
+fn list_windows_formatted(app: &AppState, fmt: &str) -> String {
+    app.windows.iter().enumerate().map(|(i, w)| {
+        format!("{}\n", expand_format(app, i, active_pane_ref(&w.root, &w.active_path), fmt))
+    }).collect()
+}
+
+/// One line per pane of the active window, or of every window with `all`.
+fn list_panes_formatted(app: &AppState, fmt: Option<&str>, all: bool) -> String {
+    let fmt = fmt.unwrap_or(if all { LIST_PANES_SESSION_FORMAT } else { LIST_PANES_FORMAT });
+    let mut out = String::new();
+    for (i, w) in app.windows.iter().enumerate().filter(|(i, _)| all || *i == app.active_idx) {
+        for (_, p) in leaf_panes(&w.root) { out.push_str(&expand_format(app, i, Some(p), fmt)); out.push('\n'); }
+    }
+    out
+}
+
+fn vt_to_color(c: vt100::Color) -> Color {
+    match c {
+        vt100::Color::Default => Color::Reset,
+        vt100::Color::Idx(i) => colors::indexed(i),
+        vt100::Color::Rgb(r, g, b) => Color::Rgb(r, g, b),
+    }
+}
+
 fn color_to_name(c: vt100::Color) -> String {
     match c {
         vt100::Color::Default => "default".to_string(),
@@ -2140,26 +3224,15 @@ fn color_to_name(c: vt100::Color) -> String {
 }
 
 fn send_text_to_active(app: &mut AppState, text: &str) -> io::Result<()> {
-    let win = &mut app.windows[app.active_idx];
-    if let Some(p) = active_pane_mut(&mut win.root, &win.active_path) { let _ = write!(p.master, "{}", text); }
+    let sync = synchronized(app, app.active_idx);
+    for p in input_panes(&mut app.windows[app.active_idx], sync) { p.input.write(text.as_bytes().to_vec()); }
     Ok(())
 }
 
 fn send_key_to_active(app: &mut AppState, k: &str) -> io::Result<()> {
-    let win = &mut app.windows[app.active_idx];
-    if let Some(p) = active_pane_mut(&mut win.root, &win.active_path) {
-        match k {
-            "enter" => { let _ = write!(p.master, "\r"); }
-            "tab" => { let _ = write!(p.master, "\t"); }
-            "backspace" => { let _ = write!(p.master, "\x08"); }
-            "esc" => { let _ = write!(p.master, "\x1b"); }
-            "left" => { let _ = write!(p.master, "\x1b[D"); }
-            "right" => { let _ = write!(p.master, "\x1b[C"); }
-            "up" => { let _ = write!(p.master, "\x1b[A"); }
-            "down" => { let _ = write!(p.master, "\x1b[B"); }
-            _ => {}
-        }
-    }
+    let Some(key) = keys::parse_key_name(k) else { return Ok(()) };
+    let sync = synchronized(app, app.active_idx);
+    for p in input_panes(&mut app.windows[app.active_idx], sync) { write_key(p, &key); }
     Ok(())
 }
 
@@ -2172,40 +3245,145 @@ fn toggle_zoom(app: &mut AppState) {
             if let Some(Node::Split { sizes, .. }) = get_split_mut(&mut win.root, &p) {
                 let idx = win.active_path.get(depth).copied().unwrap_or(0);
                 saved.push((p.clone(), sizes.clone()));
-                for i in 0..sizes.len() { sizes[i] = if i == idx { 100 } else { 0 }; }
+                for (i, s) in sizes.iter_mut().enumerate() { *s = if i == idx { 100 } else { 0 }; }
             }
         }
         app.zoom_saved = Some(saved);
-    } else {
-        if let Some(saved) = app.zoom_saved.take() {
-            for (p, sz) in saved.into_iter() {
-                if let Some(Node::Split { sizes, .. }) = get_split_mut(&mut win.root, &p) { *sizes = sz; }
-            }
+    } else if let Some(saved) = app.zoom_saved.take() {
+        for (p, sz) in saved.into_iter() {
+            if let Some(Node::Split { sizes, .. }) = get_split_mut(&mut win.root, &p) { *sizes = sz; }
         }
     }
 }
 
-fn remote_mouse_down(app: &mut AppState, x: u16, y: u16) {
-    let win = &mut app.windows[app.active_idx];
-    let mut rects: Vec<(Vec<usize>, Rect)> = Vec::new();
-    compute_rects(&win.root, app.last_window_area, &mut rects);
-    for (path, area) in rects.iter() { if area.contains(ratatui::layout::Position { x, y }) { win.active_path = path.clone(); } }
-    let mut borders: Vec<(Vec<usize>, LayoutKind, usize, u16)> = Vec::new();
-    compute_split_borders(&win.root, app.last_window_area, &mut borders);
-    let tol = 1u16;
-    for (path, kind, idx, pos) in borders.iter() {
-        match kind {
-            LayoutKind::Horizontal => {
-                if x >= pos.saturating_sub(tol) && x <= pos + tol { if let Some((left,right)) = split_sizes_at(&win.root, path.clone(), *idx) { app.drag = Some(DragState { split_path: path.clone(), kind: *kind, index: *idx, start_x: x, start_y: y, left_initial: left, _right_initial: right }); } break; }
-            }
-            LayoutKind::Vertical => {
-                if y >= pos.saturating_sub(tol) && y <= pos + tol { if let Some((left,right)) = split_sizes_at(&win.root, path.clone(), *idx) { app.drag = Some(DragState { split_path: path.clone(), kind: *kind, index: *idx, start_x: x, start_y: y, left_initial: left, _right_initial: right }); } break; }
+fn mouse_to_wire(me: &crossterm::event::MouseEvent) -> Option<String> {
+    use crossterm::event::{MouseEventKind, MouseButton};
+    let (action, button) = match me.kind {
+        MouseEventKind::Down(b) => ("mouse-down", b),
+        MouseEventKind::Drag(b) => ("mouse-drag", b),
+        MouseEventKind::Up(b) => ("mouse-up", b),
+        MouseEventKind::Moved => return Some("mouse-move".to_string()),
+        MouseEventKind::ScrollUp => return Some("scroll-up".to_string()),
+        MouseEventKind::ScrollDown => return Some("scroll-down".to_string()),
+        MouseEventKind::ScrollLeft => return Some("scroll-left".to_string()),
+        MouseEventKind::ScrollRight => return Some("scroll-right".to_string()),
+    };
+    Some(match button {
+        MouseButton::Left => action.to_string(),
+        MouseButton::Middle => format!("{}-middle", action),
+        MouseButton::Right => format!("{}-right", action),
+    })
+}
+
+fn mouse_from_wire(kind: &str, mods: u8, column: u16, row: u16) -> Option<crossterm::event::MouseEvent> {
+    use crossterm::event::{MouseEvent, MouseEventKind, MouseButton};
+    let kind = match kind {
+        "mouse-move" => MouseEventKind::Moved,
+        "scroll-up" => MouseEventKind::ScrollUp,
+        "scroll-down" => MouseEventKind::ScrollDown,
+        "scroll-left" => MouseEventKind::ScrollLeft,
+        "scroll-right" => MouseEventKind::ScrollRight,
+        _ => {
+            let (action, button) = match kind.rsplit_once('-') {
+                Some((a, "middle")) => (a, MouseButton::Middle),
+                Some((a, "right")) => (a, MouseButton::Right),
+                _ => (kind, MouseButton::Left),
+            };
+            match action {
+                "mouse-down" => MouseEventKind::Down(button),
+                "mouse-drag" => MouseEventKind::Drag(button),
+                "mouse-up" => MouseEventKind::Up(button),
+                _ => return None,
             }
         }
-    }
+    };
+    Some(MouseEvent { kind, column, row, modifiers: KeyModifiers::from_bits_truncate(mods) })
 }
 
-fn remote_mouse_drag(app: &mut AppState, x: u16, y: u16) { let win = &mut app.windows[app.active_idx]; if let Some(d) = &app.drag { adjust_split_sizes(&mut win.root, d, x, y); } }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(app: &mut AppState, args: &str) -> Result<(), String> {
+        set_option(app, &args.split(' ').collect::<Vec<_>>(), false)
+    }
+
+    #[test]
+    fn set_option_levels_append_and_unset() {
+        let mut app = new_app_state("test".to_string());
+        set(&mut app, "-g status-left global").unwrap();
+        assert_eq!(option(&app, "status-left"), "global");
+        set(&mut app, "status-left session").unwrap();
+        assert_eq!(option(&app, "status-left"), "session");
+        assert_eq!(app.global_options.get("status-left"), Some("global"));
+        // -a appends to the value in effect
+        set(&mut app, "-a status-left +more").unwrap();
+        assert_eq!(option(&app, "status-left"), "session+more");
+        set(&mut app, "-ag status-left !").unwrap();
+        assert_eq!(app.global_options.get("status-left"), Some("session+more!"));
+        // -u falls back to the global value, then the default
+        set(&mut app, "-u status-left").unwrap();
+        assert_eq!(option(&app, "status-left"), "session+more!");
+        set(&mut app, "-gu status-left").unwrap();
+        assert_eq!(option(&app, "status-left"), "[#S] ");
+    }
+
+    #[test]
+    fn send_prefix_follows_the_prefix_options() {
+        let mut app = new_app_state("test".to_string());
+        let bound = |app: &AppState, key: &str| app.key_tables.list(Some("prefix")).into_iter().find(|l| l.starts_with(&format!("bind-key -T prefix {} ", key)));
+        assert_eq!(bound(&app, "C-b").as_deref(), Some("bind-key -T prefix C-b send-prefix"));
+        set(&mut app, "-g prefix C-a").unwrap();
+        assert_eq!(bound(&app, "C-b"), None);
+        assert_eq!(bound(&app, "C-a").as_deref(), Some("bind-key -T prefix C-a send-prefix"));
+        set(&mut app, "-g prefix2 F12").unwrap();
+        assert_eq!(bound(&app, "F12").as_deref(), Some("bind-key -T prefix F12 send-prefix -2"));
+        set(&mut app, "-g prefix2 None").unwrap();
+        assert_eq!(bound(&app, "F12"), None);
+        // a key bound to something else keeps its binding when the prefix moves off it
+        run_command(&mut app, &["bind-key".to_string(), "C-a".to_string(), "new-window".to_string()]).unwrap();
+        set(&mut app, "-g prefix C-x").unwrap();
+        assert_eq!(bound(&app, "C-a").as_deref(), Some("bind-key -T prefix C-a new-window"));
+        assert_eq!(bound(&app, "C-x").as_deref(), Some("bind-key -T prefix C-x send-prefix"));
+    }
+
+    #[test]
+    fn pastes_are_translated_and_chunked() {
+        assert_eq!(paste_chunks("a\nb\r\nc", false), [b"a\rb\rc".to_vec()]);
+        assert_eq!(paste_chunks("a\nb", true), [b"\x1b[200~a\rb\x1b[201~".to_vec()]);
+        assert_eq!(paste_chunks("x\x1b[201~rm -rf\n", true), [b"\x1b[200~xrm -rf\r\x1b[201~".to_vec()]);
+        assert!(paste_chunks("", false).is_empty());
+        let big = "y".repeat(2 * PASTE_CHUNK);
+        let chunks = paste_chunks(&big, true);
+        assert_eq!(chunks.iter().map(Vec::len).collect::<Vec<_>>(), [PASTE_CHUNK, PASTE_CHUNK, 12]);
+        assert!(chunks[0].starts_with(b"\x1b[200~y"));
+        assert_eq!(chunks[2], b"yyyyyy\x1b[201~");
+        assert_eq!(chunks.concat(), [b"\x1b[200~".as_slice(), big.as_bytes(), b"\x1b[201~"].concat());
+    }
 
-fn remote_scroll_up(app: &mut AppState) { let win = &mut app.windows[app.active_idx]; if let Some(p) = active_pane_mut(&mut win.root, &win.active_path) { let _ = write!(p.master, "\x1b[A"); } }
-fn remote_scroll_down(app: &mut AppState) { let win = &mut app.windows[app.active_idx]; if let Some(p) = active_pane_mut(&mut win.root, &win.active_path) { let _ = write!(p.master, "\x1b[B"); } }
+    #[test]
+    fn status_lines_are_clamped() {
+        let mut app = new_app_state("test".to_string());
+        for (value, lines) in [("off", 0), ("on", 1), ("3", 3), ("5", 5), ("9", 5), ("1000000", 5), ("junk", 1)] {
+            // stored directly, as nothing else lets a value outside the choices in
+            app.global_options.set("status", value.to_string());
+            assert_eq!(status_line_count(&app), lines, "{}", value);
+        }
+    }
+
+    #[test]
+    fn set_option_validates() {
+        let mut app = new_app_state("test".to_string());
+        assert!(set(&mut app, "history-limit lots").is_err());
+        assert!(set(&mut app, "no-such-option 1").is_err());
+        assert!(set(&mut app, "-q no-such-option 1").is_ok());
+        set(&mut app, "mouse").unwrap();
+        assert_eq!(option(&app, "mouse"), "off");
+        set(&mut app, "-g @mine one").unwrap();
+        set(&mut app, "-a @mine two").unwrap();
+        assert_eq!(option(&app, "@mine"), "onetwo");
+        set(&mut app, "-gw mode-keys emacs").unwrap();
+        assert_eq!(option(&app, "mode-keys"), "emacs");
+        assert_eq!(app.global_window_options.get("mode-keys"), Some("emacs"));
+    }
+}
@@ -0,0 +1,149 @@
+//! Mouse reporting: re-encoding mouse events for applications that asked for
+//! them with the xterm mouse modes (`?9`, `?1000`, `?1002`, `?1003`) in the
+//! encoding they selected (default, UTF-8 `?1005` or SGR `?1006`).
+
+use crossterm::event::{KeyModifiers, MouseButton, MouseEventKind};
+use vt100::{MouseProtocolEncoding, MouseProtocolMode};
+
+/// Bytes an xterm would send for a mouse event at the zero-based pane cell
+/// `(col, row)`, or `None` if the pane's mouse mode doesn't report it or the
+/// position can't be expressed in its encoding.
+pub fn encode_mouse(kind: MouseEventKind, mods: KeyModifiers, col: u16, row: u16, mode: MouseProtocolMode, encoding: MouseProtocolEncoding) -> Option<Vec<u8>> {
+    let wanted = match mode {
+        MouseProtocolMode::None => false,
+        MouseProtocolMode::Press => !matches!(kind, MouseEventKind::Up(_) | MouseEventKind::Drag(_) | MouseEventKind::Moved),
+        MouseProtocolMode::PressRelease => !matches!(kind, MouseEventKind::Drag(_) | MouseEventKind::Moved),
+        MouseProtocolMode::ButtonMotion => !matches!(kind, MouseEventKind::Moved),
+        MouseProtocolMode::AnyMotion => true,
+    };
+    if !wanted { return None; }
+
+    // button number, +32 for motion, +64 for the wheel
+    let mut cb: u32 = match kind {
+        MouseEventKind::Down(b) | MouseEventKind::Up(b) => button_code(b),
+        MouseEventKind::Drag(b) => button_code(b) + 32,
+        MouseEventKind::Moved => 3 + 32,
+        MouseEventKind::ScrollUp => 64,
+        MouseEventKind::ScrollDown => 65,
+        MouseEventKind::ScrollLeft => 66,
+        MouseEventKind::ScrollRight => 67,
+    };
+    // X10 mode reports no modifiers
+    if mode != MouseProtocolMode::Press {
+        if mods.contains(KeyModifiers::SHIFT) { cb |= 4; }
+        if mods.contains(KeyModifiers::ALT) { cb |= 8; }
+        if mods.contains(KeyModifiers::CONTROL) { cb |= 16; }
+    }
+    let release = matches!(kind, MouseEventKind::Up(_));
+
+    match encoding {
+        MouseProtocolEncoding::Sgr => {
+            Some(format!("\x1b[<{};{};{}{}", cb, col as u32 + 1, row as u32 + 1, if release { 'm' } else { 'M' }).into_bytes())
+        }
+        MouseProtocolEncoding::Default | MouseProtocolEncoding::Utf8 => {
+            // these encodings can't say which button was released
+            if release { cb = (cb & !3) | 3; }
+            let mut out = b"\x1b[M".to_vec();
+            out.push(32 + cb as u8);
+            for v in [col as u32 + 33, row as u32 + 33] {
+                if encoding == MouseProtocolEncoding::Utf8 {
+                    // two-byte UTF-8 sequences reach 2047
+                    let c = char::from_u32(v).filter(|_| v < 0x800)?;
+                    let mut buf = [0u8; 4];
+                    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                } else {
+                    out.push(u8::try_from(v).ok()?);
+                }
+            }
+            Some(out)
+        }
+    }
+}
+
+fn button_code(b: MouseButton) -> u32 {
+    match b {
+        MouseButton::Left => 0,
+        MouseButton::Middle => 1,
+        MouseButton::Right => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEFT: MouseButton = MouseButton::Left;
+
+    fn enc(kind: MouseEventKind, col: u16, row: u16, mode: MouseProtocolMode, encoding: MouseProtocolEncoding) -> Option<Vec<u8>> {
+        encode_mouse(kind, KeyModifiers::NONE, col, row, mode, encoding)
+    }
+
+    #[test]
+    fn modes_report_their_events() {
+        use MouseEventKind::*;
+        use MouseProtocolMode::*;
+        let events = [Down(LEFT), Up(LEFT), Drag(LEFT), Moved, ScrollUp];
+        let cases = [
+            (None, [false, false, false, false, false]),
+            (Press, [true, false, false, false, true]),
+            (PressRelease, [true, true, false, false, true]),
+            (ButtonMotion, [true, true, true, false, true]),
+            (AnyMotion, [true, true, true, true, true]),
+        ];
+        for (mode, wanted) in cases {
+            for (kind, want) in events.into_iter().zip(wanted) {
+                for encoding in [MouseProtocolEncoding::Default, MouseProtocolEncoding::Utf8, MouseProtocolEncoding::Sgr] {
+                    assert_eq!(enc(kind, 0, 0, mode, encoding).is_some(), want, "{:?} {:?} {:?}", mode, kind, encoding);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn default_encoding() {
+        let mode = MouseProtocolMode::AnyMotion;
+        let d = MouseProtocolEncoding::Default;
+        assert_eq!(enc(MouseEventKind::Down(LEFT), 0, 0, mode, d).unwrap(), b"\x1b[M !!");
+        assert_eq!(enc(MouseEventKind::Down(MouseButton::Right), 9, 4, mode, d).unwrap(), b"\x1b[M\"*%");
+        // a release doesn't say which button
+        assert_eq!(enc(MouseEventKind::Up(MouseButton::Right), 0, 0, mode, d).unwrap(), b"\x1b[M#!!");
+        assert_eq!(enc(MouseEventKind::Drag(LEFT), 1, 1, mode, d).unwrap(), b"\x1b[M@\"\"");
+        assert_eq!(enc(MouseEventKind::Moved, 1, 1, mode, d).unwrap(), b"\x1b[MC\"\"");
+        assert_eq!(enc(MouseEventKind::ScrollDown, 0, 0, mode, d).unwrap(), b"\x1b[Ma!!");
+        assert_eq!(enc(MouseEventKind::Down(LEFT), 222, 0, mode, d).unwrap(), b"\x1b[M \xff!");
+        assert_eq!(enc(MouseEventKind::Down(LEFT), 223, 0, mode, d), None);
+        assert_eq!(enc(MouseEventKind::Down(LEFT), 0, 300, mode, d), None);
+    }
+
+    #[test]
+    fn utf8_encoding() {
+        let mode = MouseProtocolMode::PressRelease;
+        let u = MouseProtocolEncoding::Utf8;
+        assert_eq!(enc(MouseEventKind::Down(LEFT), 0, 0, mode, u).unwrap(), b"\x1b[M !!");
+        assert_eq!(enc(MouseEventKind::Up(LEFT), 0, 0, mode, u).unwrap(), b"\x1b[M#!!");
+        // 223 + 33 = 256, sent as U+0100
+        assert_eq!(enc(MouseEventKind::Down(LEFT), 223, 300, mode, u).unwrap(), b"\x1b[M \xc4\x80\xc5\x8d");
+        assert_eq!(enc(MouseEventKind::Down(LEFT), 2014, 0, mode, u).unwrap(), b"\x1b[M \xdf\xbf!");
+        assert_eq!(enc(MouseEventKind::Down(LEFT), 2015, 0, mode, u), None);
+    }
+
+    #[test]
+    fn sgr_encoding() {
+        let mode = MouseProtocolMode::ButtonMotion;
+        let s = MouseProtocolEncoding::Sgr;
+        assert_eq!(enc(MouseEventKind::Down(MouseButton::Middle), 0, 0, mode, s).unwrap(), b"\x1b[<1;1;1M");
+        // SGR releases keep the button
+        assert_eq!(enc(MouseEventKind::Up(MouseButton::Middle), 0, 0, mode, s).unwrap(), b"\x1b[<1;1;1m");
+        assert_eq!(enc(MouseEventKind::Drag(LEFT), 499, 299, mode, s).unwrap(), b"\x1b[<32;500;300M");
+        assert_eq!(enc(MouseEventKind::ScrollUp, 5, 5, mode, s).unwrap(), b"\x1b[<64;6;6M");
+    }
+
+    #[test]
+    fn modifiers_are_reported_except_in_x10_mode() {
+        let mods = KeyModifiers::SHIFT | KeyModifiers::ALT | KeyModifiers::CONTROL;
+        let kind = MouseEventKind::Down(LEFT);
+        assert_eq!(encode_mouse(kind, mods, 0, 0, MouseProtocolMode::PressRelease, MouseProtocolEncoding::Sgr).unwrap(), b"\x1b[<28;1;1M");
+        assert_eq!(encode_mouse(kind, KeyModifiers::CONTROL, 0, 0, MouseProtocolMode::AnyMotion, MouseProtocolEncoding::Default).unwrap(), b"\x1b[M0!!");
+        assert_eq!(encode_mouse(kind, mods, 0, 0, MouseProtocolMode::Press, MouseProtocolEncoding::Default).unwrap(), b"\x1b[M !!");
+    }
+}
@@ -0,0 +1,304 @@
+//! Options: the typed settings changed with `set-option`.
+//!
+//! Every option has a scope and a type in `TABLE`. Server options have one
+//! value. Session and window options have a global value, set with `-g`, that
+//! the session and each window inherit unless given their own, and pane
+//! options inherit from their window. Names starting with `@` are user
+//! options, which hold any text at any scope. Values are kept as text, with
+//! flags stored as `on` or `off`.
+
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Scope { Server, Session, Window, Pane }
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Flag,
+    Number,
+    String,
+    /// A key such as `C-a`, or `None`.
+    Key,
+    Choice(&'static [&'static str]),
+}
+
+pub struct Def {
+    pub name: &'static str,
+    pub scope: Scope,
+    pub kind: Kind,
+    pub default: &'static str,
+    /// Set as `name[N]`, each index a separate value.
+    pub array: bool,
+}
+
+const fn def(name: &'static str, scope: Scope, kind: Kind, default: &'static str) -> Def {
+    Def { name, scope, kind, default, array: false }
+}
+
+pub const TABLE: &[Def] = &[
+    def("escape-time", Scope::Server, Kind::Number, "500"),
+    def("terminal-overrides", Scope::Server, Kind::String, ""),
+    def("cursor-style", Scope::Session, Kind::Choice(&["block", "underline", "bar", "beam"]), "bar"),
+    def("cursor-blink", Scope::Session, Kind::Flag, "on"),
+    def("history-limit", Scope::Session, Kind::Number, "3000"),
+    def("mouse", Scope::Session, Kind::Flag, "on"),
+    def("prefix", Scope::Session, Kind::Key, "C-b"),
+    def("prefix2", Scope::Session, Kind::Key, "None"),
+    def("repeat-time", Scope::Session, Kind::Number, "500"),
+    def("set-titles", Scope::Session, Kind::Flag, "off"),
+    def("set-titles-string", Scope::Session, Kind::String, "#S:#I:#W - \"#T\""),
+    def("status", Scope::Session, Kind::Choice(&["off", "on", "2", "3", "4", "5"]), "on"),
+    Def { name: "status-format", scope: Scope::Session, kind: Kind::String, default: "", array: true },
+    def("status-interval", Scope::Session, Kind::Number, "15"),
+    def("status-justify", Scope::Session, Kind::Choice(&["left", "centre", "center", "right", "absolute-centre"]), "left"),
+    def("status-left", Scope::Session, Kind::String, "[#S] "),
+    def("status-left-style", Scope::Session, Kind::String, ""),
+    def("status-position", Scope::Session, Kind::Choice(&["top", "bottom"]), "bottom"),
+    def("status-right", Scope::Session, Kind::String, "#{?#{!=:#{client_key_table},root},[#{client_key_table}] ,}%H:%M"),
+    def("status-right-style", Scope::Session, Kind::String, ""),
+    def("status-style", Scope::Session, Kind::String, "bg=green,fg=black"),
+    def("automatic-rename", Scope::Window, Kind::Flag, "on"),
+    def("mode-keys", Scope::Window, Kind::Choice(&["emacs", "vi"]), "vi"),
+    def("synchronize-panes", Scope::Window, Kind::Flag, "off"),
+    def("window-status-current-format", Scope::Window, Kind::String, "#I:#W#{?window_flags,#{window_flags}, }"),
+    def("window-status-current-style", Scope::Window, Kind::String, ""),
+    def("window-status-format", Scope::Window, Kind::String, "#I:#W#{?window_flags,#{window_flags}, }"),
+    def("window-status-separator", Scope::Window, Kind::String, " "),
+    def("window-status-style", Scope::Window, Kind::String, ""),
+    def("allow-rename", Scope::Pane, Kind::Flag, "off"),
+];
+
+/// The definition of option `name`; `name[N]` finds array options.
+pub fn find(name: &str) -> Option<&'static Def> {
+    let (base, indexed) = match name.split_once('[') {
+        Some((base, index)) => (base, index.strip_suffix(']').is_some_and(|i| i.parse::<usize>().is_ok())),
+        None => (name, false),
+    };
+    TABLE.iter().find(|d| d.name == base && d.array == indexed)
+}
+
+/// Option values set at one level: the server, a session, a window or a pane.
+#[derive(Default)]
+pub struct Options {
+    values: BTreeMap<String, String>,
+}
+
+impl Options {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    pub fn set(&mut self, name: &str, value: String) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.values.remove(name);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+/// The value of `name` in the first of `layers` that sets it, else its default.
+pub fn lookup(name: &str, layers: &[&Options]) -> String {
+    layers.iter().find_map(|o| o.get(name)).map(str::to_string)
+        .unwrap_or_else(|| find(name).map(|d| d.default.to_string()).unwrap_or_default())
+}
+
+/// The levels an option is looked up in for one pane or window.
+pub struct Levels<'a> {
+    pub pane: Option<&'a Options>,
+    pub window: Option<&'a Options>,
+    pub global_window: &'a Options,
+    pub session: &'a Options,
+    pub global: &'a Options,
+    pub server: &'a Options,
+}
+
+impl Levels<'_> {
+    /// Option `name`: the pane's and window's own values, then the session's,
+    /// then the global ones, then the default. Only the levels of the
+    /// option's scope are searched, user options searching them all.
+    pub fn value(&self, name: &str) -> String {
+        let scope = find(name).map(|d| d.scope);
+        let mut layers: Vec<&Options> = Vec::new();
+        if matches!(scope, Some(Scope::Pane) | None) { layers.extend(self.pane); }
+        if matches!(scope, Some(Scope::Window | Scope::Pane) | None) { layers.extend(self.window); layers.push(self.global_window); }
+        if matches!(scope, Some(Scope::Session) | None) { layers.extend([self.session, self.global]); }
+        if matches!(scope, Some(Scope::Server) | None) { layers.push(self.server); }
+        lookup(name, &layers)
+    }
+}
+
+/// Check `value` for option `def` and put it in the stored form. An empty
+/// value toggles a flag whose value is now `current`.
+pub fn parse(def: &Def, value: &str, current: &str) -> Result<String, String> {
+    match def.kind {
+        Kind::Flag => match value {
+            "" => Ok(if current == "on" { "off" } else { "on" }.to_string()),
+            "on" | "yes" | "true" | "1" => Ok("on".to_string()),
+            "off" | "no" | "false" | "0" => Ok("off".to_string()),
+            _ => Err(format!("{}: expected on or off: {}", def.name, value)),
+        },
+        Kind::Number => value.parse::<u64>().map(|n| n.to_string()).map_err(|_| format!("{}: expected a number: {}", def.name, value)),
+        Kind::String => Ok(value.to_string()),
+        Kind::Key => match crate::keys::parse_key_name(value) {
+            Some(_) => Ok(value.to_string()),
+            None if value.eq_ignore_ascii_case("none") => Ok("None".to_string()),
+            None => Err(format!("{}: unknown key: {}", def.name, value)),
+        },
+        Kind::Choice(choices) => {
+            // tmux spellings of the flags work for choices that are on and off
+            let value = match value { "yes" | "true" | "1" if choices.contains(&"on") => "on", "no" | "false" | "0" if choices.contains(&"off") => "off", v => v };
+            if choices.contains(&value) { Ok(value.to_string()) } else { Err(format!("{}: expected one of {}: {}", def.name, choices.join(", "), value)) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(name: &str, value: &str, current: &str) -> Result<String, String> {
+        parse(find(name).unwrap(), value, current)
+    }
+
+    #[test]
+    fn finds_options() {
+        assert!(find("status").is_some());
+        assert!(find("status-format[0]").is_some());
+        assert!(find("status-format").is_none());
+        assert!(find("status[0]").is_none());
+        assert!(find("status-format[x]").is_none());
+        assert!(find("nope").is_none());
+    }
+
+    #[test]
+    fn validates_values() {
+        let cases = [
+            ("mouse", "on", "off", Ok("on")),
+            ("mouse", "yes", "off", Ok("on")),
+            ("mouse", "0", "on", Ok("off")),
+            ("mouse", "", "on", Ok("off")),
+            ("mouse", "", "off", Ok("on")),
+            ("mouse", "maybe", "on", Err(())),
+            ("history-limit", "5000", "", Ok("5000")),
+            ("history-limit", "007", "", Ok("7")),
+            ("history-limit", "-1", "", Err(())),
+            ("history-limit", "lots", "", Err(())),
+            ("status-left", "any #{text}", "", Ok("any #{text}")),
+            ("prefix", "C-a", "", Ok("C-a")),
+            ("prefix2", "none", "", Ok("None")),
+            ("prefix", "C-Nope", "", Err(())),
+            ("status-position", "top", "", Ok("top")),
+            ("status-position", "middle", "", Err(())),
+            ("status-justify", "center", "", Ok("center")),
+            ("status-justify", "middle", "", Err(())),
+            ("status", "3", "", Ok("3")),
+            ("status", "yes", "", Ok("on")),
+            ("status", "6", "", Err(())),
+            ("mode-keys", "emacs", "", Ok("emacs")),
+            ("mode-keys", "1", "", Err(())),
+        ];
+        for (name, value, current, want) in cases {
+            let got = parsed(name, value, current);
+            match want {
+                Ok(v) => assert_eq!(got.as_deref(), Ok(v), "{} {:?}", name, value),
+                Err(()) => assert!(got.is_err(), "{} {:?} gave {:?}", name, value, got),
+            }
+        }
+    }
+
+    #[test]
+    fn lookup_takes_the_first_level_set() {
+        let (mut a, mut b) = (Options::default(), Options::default());
+        assert_eq!(lookup("status-left", &[&a, &b]), "[#S] ");
+        b.set("status-left", "b".to_string());
+        assert_eq!(lookup("status-left", &[&a, &b]), "b");
+        a.set("status-left", "a".to_string());
+        assert_eq!(lookup("status-left", &[&a, &b]), "a");
+        a.unset("status-left");
+        assert_eq!(lookup("status-left", &[&a, &b]), "b");
+        assert_eq!(lookup("@unset", &[&a, &b]), "");
+    }
+
+    #[derive(Default)]
+    struct All {
+        pane: Options,
+        window: Options,
+        global_window: Options,
+        session: Options,
+        global: Options,
+        server: Options,
+    }
+
+    impl All {
+        fn levels(&self) -> Levels<'_> {
+            Levels {
+                pane: Some(&self.pane),
+                window: Some(&self.window),
+                global_window: &self.global_window,
+                session: &self.session,
+                global: &self.global,
+                server: &self.server,
+            }
+        }
+    }
+
+    #[test]
+    fn session_options_fall_back_to_global() {
+        let mut all = All::default();
+        assert_eq!(all.levels().value("status-position"), "bottom");
+        all.global.set("status-position", "top".to_string());
+        assert_eq!(all.levels().value("status-position"), "top");
+        all.session.set("status-position", "bottom".to_string());
+        assert_eq!(all.levels().value("status-position"), "bottom");
+        // levels of other scopes aren't searched
+        all.session.unset("status-position");
+        all.window.set("status-position", "bottom".to_string());
+        all.server.set("status-position", "bottom".to_string());
+        assert_eq!(all.levels().value("status-position"), "top");
+    }
+
+    #[test]
+    fn window_options_fall_back_to_global_window() {
+        let mut all = All::default();
+        assert_eq!(all.levels().value("mode-keys"), "vi");
+        all.global_window.set("mode-keys", "emacs".to_string());
+        assert_eq!(all.levels().value("mode-keys"), "emacs");
+        all.window.set("mode-keys", "vi".to_string());
+        assert_eq!(all.levels().value("mode-keys"), "vi");
+        all.window.unset("mode-keys");
+        all.global.set("mode-keys", "vi".to_string());
+        all.pane.set("mode-keys", "vi".to_string());
+        assert_eq!(all.levels().value("mode-keys"), "emacs");
+        let no_window = Levels { window: None, ..all.levels() };
+        assert_eq!(no_window.value("mode-keys"), "emacs");
+    }
+
+    #[test]
+    fn pane_and_user_options_search_every_level() {
+        let mut all = All::default();
+        all.global_window.set("allow-rename", "on".to_string());
+        assert_eq!(all.levels().value("allow-rename"), "on");
+        all.pane.set("allow-rename", "off".to_string());
+        assert_eq!(all.levels().value("allow-rename"), "off");
+        all.server.set("@user", "server".to_string());
+        assert_eq!(all.levels().value("@user"), "server");
+        all.session.set("@user", "session".to_string());
+        assert_eq!(all.levels().value("@user"), "session");
+        all.pane.set("@user", "pane".to_string());
+        assert_eq!(all.levels().value("@user"), "pane");
+    }
+
+    #[test]
+    fn server_options_ignore_other_levels() {
+        let mut all = All::default();
+        all.global.set("escape-time", "10".to_string());
+        assert_eq!(all.levels().value("escape-time"), "500");
+        all.server.set("escape-time", "0".to_string());
+        assert_eq!(all.levels().value("escape-time"), "0");
+    }
+}
@@ -0,0 +1,85 @@
+//! What the operating system can tell us about the processes in panes.
+//!
+//! portable-pty doesn't report the pids of the shells it starts, so on Linux
+//! they are found through `/proc` by an environment variable set for each.
+//! Elsewhere these functions know nothing.
+
+#[cfg(target_os = "linux")]
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Pids of this process's direct children.
+#[cfg(target_os = "linux")]
+fn child_pids() -> HashSet<u32> {
+    let me = std::process::id();
+    let mut out = HashSet::new();
+    let Ok(entries) = std::fs::read_dir("/proc") else { return out };
+    for e in entries.flatten() {
+        let Some(pid) = e.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) else { continue };
+        if stat_field(pid, STAT_PPID) == Some(me) { out.insert(pid); }
+    }
+    out
+}
+
+/// The child of this process started with environment variable `name` set to `value`.
+#[cfg(target_os = "linux")]
+pub fn child_with_env(name: &str, value: &str) -> Option<u32> {
+    let entry = format!("{}={}", name, value).into_bytes();
+    child_pids().into_iter().find(|pid| {
+        std::fs::read(format!("/proc/{}/environ", pid)).is_ok_and(|env| env.split(|b| *b == 0).any(|e| e == entry.as_slice()))
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn child_with_env(_name: &str, _value: &str) -> Option<u32> {
+    None
+}
+
+/// Working directory of process `pid`.
+#[cfg(target_os = "linux")]
+pub fn cwd(pid: u32) -> Option<PathBuf> {
+    std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn cwd(_pid: u32) -> Option<PathBuf> {
+    None
+}
+
+/// The process in the foreground of `pid`'s terminal: the leader of the
+/// terminal's foreground process group.
+#[cfg(target_os = "linux")]
+pub fn foreground_pid(pid: u32) -> Option<u32> {
+    stat_field(pid, STAT_TPGID).filter(|&pgid| pgid > 0)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn foreground_pid(_pid: u32) -> Option<u32> {
+    None
+}
+
+/// Command name of process `pid`, without its arguments.
+#[cfg(target_os = "linux")]
+pub fn command_name(pid: u32) -> Option<String> {
+    let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    Some(comm.trim_end().to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn command_name(_pid: u32) -> Option<String> {
+    None
+}
+
+/// Fields of `/proc/<pid>/stat`, counted from the state field that follows the command name.
+#[cfg(target_os = "linux")]
+const STAT_PPID: usize = 1;
+#[cfg(target_os = "linux")]
+const STAT_TPGID: usize = 5;
+
+#[cfg(target_os = "linux")]
+fn stat_field(pid: u32, field: usize) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // the command name is in parentheses and may itself contain spaces or ')'
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(field)?.parse().ok()
+}
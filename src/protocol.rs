@@ -0,0 +1,74 @@
+//! Framed client/server protocol used by attached clients.
+//!
+//! A client opens one connection, sends the line `framed`, and from then on both
+//! sides exchange length-prefixed JSON messages: a big-endian `u32` byte count
+//! followed by that many bytes of JSON. One-shot CLI commands keep using the
+//! plain one-line-per-connection form.
+
+use std::io::{self, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::FrameJson;
+
+/// Bumped whenever a message changes shape incompatibly.
+pub const PROTOCOL_VERSION: u32 = 5;
+
+/// First line a client sends to switch the connection to framed messages.
+pub const FRAMED_HELLO: &str = "framed";
+
+const MAX_FRAME_LEN: u32 = 64 * 1024 * 1024;
+
+/// Input sent from an attached client to the server.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ClientMsg {
+    /// `cols` and `rows` are the whole terminal; the server fits the status lines in.
+    Hello { version: u32, cols: u16, rows: u16 },
+    Key { mods: u8, code: String },
+    Mouse { kind: String, #[serde(default)] mods: u8, x: u16, y: u16 },
+    Resize { cols: u16, rows: u16 },
+    Paste { text: String },
+    Detach,
+}
+
+/// Screen updates and notifications sent from the server to an attached client.
+///
+/// `Frame` carries the layout and status line without cell contents; pane contents
+/// arrive separately as `PaneUpdate`s holding terminal output that the client feeds
+/// into its own copy of the pane's screen. A `full` update replaces that copy, the
+/// others are diffs against what the client was last sent.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ServerMsg {
+    Welcome { version: u32 },
+    Frame(Box<FrameJson>),
+    PaneUpdate { id: usize, rows: u16, cols: u16, full: bool, data: String },
+    PaneClosed { id: usize },
+    Message { text: String },
+    Detached,
+    Exited,
+}
+
+pub fn write_msg<T: Serialize>(w: &mut impl Write, msg: &T) -> io::Result<()> {
+    let body = serde_json::to_vec(msg).map_err(|e| io::Error::other(format!("json error: {e}")))?;
+    let len = u32::try_from(body.len()).map_err(|_| io::Error::other("frame too large"))?;
+    w.write_all(&len.to_be_bytes())?;
+    w.write_all(&body)?;
+    w.flush()
+}
+
+/// Read one message; `Ok(None)` means the peer closed the connection cleanly.
+pub fn read_msg<T: for<'de> Deserialize<'de>>(r: &mut impl Read) -> io::Result<Option<T>> {
+    let mut len = [0u8; 4];
+    match r.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME_LEN { return Err(io::Error::other(format!("frame of {len} bytes exceeds limit"))); }
+    let mut body = vec![0u8; len as usize];
+    r.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| io::Error::other(format!("json error: {e}")))
+}
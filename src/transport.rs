@@ -0,0 +1,304 @@
+//! Where sessions live and how clients reach them.
+//!
+//! On Unix every session is a socket in a private per-user directory,
+//! `$TMPDIR/pmux-<uid>/<name>.sock` (mode 0700, like tmux's `/tmp/tmux-<uid>/`).
+//! On Windows the server listens on a loopback TCP port that it records in
+//! `%USERPROFILE%\.pmux\<name>.port`.
+//!
+//! Either way the server also writes a random token to `<name>.key`, readable
+//! only by its owner, and every connection must open with `auth <token>`.
+
+use std::io::{self, BufRead, Read, Write};
+use std::net::Shutdown;
+#[cfg(not(unix))]
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+#[cfg(unix)]
+const SESSION_EXT: &str = "sock";
+#[cfg(not(unix))]
+const SESSION_EXT: &str = "port";
+
+/// Per-user directory holding session endpoints and the `last_session` marker.
+#[cfg(unix)]
+pub fn session_dir() -> io::Result<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+    let base = std::env::var_os("TMPDIR").filter(|v| !v.is_empty()).map(PathBuf::from).unwrap_or_else(|| PathBuf::from("/tmp"));
+    // SAFETY: getuid has no preconditions and cannot fail.
+    let uid = unsafe { libc::getuid() };
+    let dir = base.join(format!("pmux-{}", uid));
+    // created private, so there is no moment when others can reach it;
+    // one that already exists must pass the checks below
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    let md = std::fs::symlink_metadata(&dir)?;
+    if !md.is_dir() || md.uid() != uid || md.mode() & 0o077 != 0 {
+        return Err(io::Error::other(format!("unsafe permissions on {}", dir.display())));
+    }
+    Ok(dir)
+}
+
+#[cfg(not(unix))]
+pub fn session_dir() -> io::Result<PathBuf> {
+    let home = std::env::var("USERPROFILE").or_else(|_| std::env::var("HOME")).unwrap_or_default();
+    let dir = PathBuf::from(home).join(".pmux");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn session_path(name: &str) -> io::Result<PathBuf> {
+    Ok(session_dir()?.join(format!("{}.{}", name, SESSION_EXT)))
+}
+
+fn key_path(name: &str) -> io::Result<PathBuf> {
+    Ok(session_dir()?.join(format!("{}.key", name)))
+}
+
+pub fn log_path(name: &str) -> io::Result<PathBuf> {
+    Ok(session_dir()?.join(format!("{}.log", name)))
+}
+
+pub fn last_session_path() -> io::Result<PathBuf> {
+    Ok(session_dir()?.join("last_session"))
+}
+
+/// Whether `name` has a registered endpoint, live or stale.
+pub fn session_registered(name: &str) -> bool {
+    session_path(name).is_ok_and(|p| p.exists())
+}
+
+pub fn session_alive(name: &str) -> bool {
+    connect(name).is_ok()
+}
+
+/// Names of every registered session with the modification time of its endpoint.
+pub fn list_sessions() -> Vec<(String, std::time::SystemTime)> {
+    let mut out = Vec::new();
+    let Ok(dir) = session_dir() else { return out };
+    let Ok(entries) = std::fs::read_dir(&dir) else { return out };
+    for e in entries.flatten() {
+        let path = e.path();
+        if path.extension().and_then(|x| x.to_str()) != Some(SESSION_EXT) { continue; }
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+        let modified = e.metadata().and_then(|m| m.modified()).unwrap_or(std::time::SystemTime::UNIX_EPOCH);
+        out.push((name.to_string(), modified));
+    }
+    out.sort();
+    out
+}
+
+pub enum Stream {
+    #[cfg(not(unix))]
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            #[cfg(not(unix))]
+            Stream::Tcp(s) => s.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.try_clone().map(Stream::Unix),
+        }
+    }
+
+    pub fn set_read_timeout(&self, dur: Option<std::time::Duration>) -> io::Result<()> {
+        match self {
+            #[cfg(not(unix))]
+            Stream::Tcp(s) => s.set_read_timeout(dur),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.set_read_timeout(dur),
+        }
+    }
+
+    pub fn shutdown(&self) -> io::Result<()> {
+        match self {
+            #[cfg(not(unix))]
+            Stream::Tcp(s) => s.shutdown(Shutdown::Both),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.shutdown(Shutdown::Both),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            #[cfg(not(unix))]
+            Stream::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            #[cfg(not(unix))]
+            Stream::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            #[cfg(not(unix))]
+            Stream::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.flush(),
+        }
+    }
+}
+
+fn open(name: &str) -> io::Result<Stream> {
+    let path = session_path(name)?;
+    #[cfg(unix)]
+    {
+        UnixStream::connect(&path).map(Stream::Unix)
+    }
+    #[cfg(not(unix))]
+    {
+        let port = std::fs::read_to_string(&path)?.trim().parse::<u16>().map_err(|_| io::Error::other(format!("bad port file {}", path.display())))?;
+        TcpStream::connect(("127.0.0.1", port)).map(Stream::Tcp)
+    }
+}
+
+/// Connect to the server of session `name` and present its token.
+pub fn connect(name: &str) -> io::Result<Stream> {
+    let token = std::fs::read_to_string(key_path(name)?)?;
+    let mut stream = open(name)?;
+    stream.write_all(format!("auth {}\n", token.trim()).as_bytes())?;
+    Ok(stream)
+}
+
+/// Generate a fresh token for session `name` and store it where only the owner can read it.
+pub fn create_token(name: &str) -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    fill_random(&mut bytes)?;
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    let path = key_path(name)?;
+    let _ = std::fs::remove_file(&path);
+    let mut opts = std::fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(0o600);
+    }
+    // on Windows the file inherits the owner-only ACL of the user's profile directory
+    opts.open(&path)?.write_all(token.as_bytes())?;
+    Ok(token)
+}
+
+#[cfg(target_os = "linux")]
+fn fill_random(buf: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let rest = &mut buf[filled..];
+        // SAFETY: the pointer and length describe `rest`, which is valid for writes.
+        let n = unsafe { libc::getrandom(rest.as_mut_ptr().cast(), rest.len(), 0) };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted { continue; }
+            return Err(e);
+        }
+        filled += n as usize;
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn fill_random(buf: &mut [u8]) -> io::Result<()> {
+    std::fs::File::open("/dev/urandom")?.read_exact(buf)
+}
+
+#[cfg(windows)]
+fn fill_random(buf: &mut [u8]) -> io::Result<()> {
+    #[link(name = "bcrypt")]
+    extern "system" {
+        fn BCryptGenRandom(algorithm: *mut std::ffi::c_void, buffer: *mut u8, len: u32, flags: u32) -> i32;
+    }
+    const BCRYPT_USE_SYSTEM_PREFERRED_RNG: u32 = 0x2;
+    for chunk in buf.chunks_mut(u32::MAX as usize) {
+        // SAFETY: a null algorithm handle is allowed with
+        // BCRYPT_USE_SYSTEM_PREFERRED_RNG, and the pointer and length describe
+        // `chunk`, which fits in a u32 and is valid for writes.
+        let status = unsafe { BCryptGenRandom(std::ptr::null_mut(), chunk.as_mut_ptr(), chunk.len() as u32, BCRYPT_USE_SYSTEM_PREFERRED_RNG) };
+        if status < 0 { return Err(io::Error::other(format!("BCryptGenRandom failed: {:#x}", status))); }
+    }
+    Ok(())
+}
+
+/// Read the `auth <token>` line a connection must start with and check it against `token`.
+pub fn authenticate(r: &mut impl BufRead, token: &str) -> bool {
+    let mut line = String::new();
+    if r.read_line(&mut line).is_err() { return false; }
+    let Some(given) = line.trim_end().strip_prefix("auth ") else { return false };
+    // compare in constant time so the token cannot be guessed byte by byte
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+pub enum Listener {
+    #[cfg(not(unix))]
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Bind the endpoint for session `name`, replacing a stale one left by a dead server.
+    pub fn bind(name: &str) -> io::Result<Listener> {
+        let path = session_path(name)?;
+        if open(name).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("session {} already exists", name)));
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = std::fs::remove_file(&path);
+            let listener = UnixListener::bind(&path)?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+            Ok(Listener::Unix(listener))
+        }
+        #[cfg(not(unix))]
+        {
+            let listener = TcpListener::bind(("127.0.0.1", 0))?;
+            std::fs::write(&path, listener.local_addr()?.port().to_string())?;
+            Ok(Listener::Tcp(listener))
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            #[cfg(not(unix))]
+            Listener::Tcp(l) => l.accept().map(|(s, _)| Stream::Tcp(s)),
+            #[cfg(unix)]
+            Listener::Unix(l) => l.accept().map(|(s, _)| Stream::Unix(s)),
+        }
+    }
+
+    /// The loopback port for TCP listeners.
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            #[cfg(not(unix))]
+            Listener::Tcp(l) => l.local_addr().ok().map(|a| a.port()),
+            #[cfg(unix)]
+            Listener::Unix(_) => None,
+        }
+    }
+}
+
+/// Remove the endpoint and token of session `name` when its server shuts down.
+pub fn unregister(name: &str) {
+    if let Ok(path) = session_path(name) { let _ = std::fs::remove_file(path); }
+    if let Ok(path) = key_path(name) { let _ = std::fs::remove_file(path); }
+}